@@ -0,0 +1,113 @@
+use std::{fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// File doesn't start with a known magic.
+    BadMagic,
+    /// Data ends before the structure being parsed.
+    Truncated,
+    /// Offset or size points outside of the data.
+    OutOfBounds,
+    /// Name isn't a valid string.
+    InvalidName,
+    /// Data has a valid size, but its content makes no sense.
+    Malformed,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::BadMagic => "bad magic",
+            Self::Truncated => "truncated data",
+            Self::OutOfBounds => "offset out of bounds",
+            Self::InvalidName => "invalid name",
+            Self::Malformed => "malformed data",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Parse {
+        /// Name of the lump being parsed, if known.
+        lump: Option<String>,
+        /// Offset from the start of the parsed lump (or file) where the error occurred.
+        offset: usize,
+        kind: ErrorKind,
+    },
+    Io(io::Error),
+}
+
+impl Error {
+    pub const fn new(kind: ErrorKind, offset: usize) -> Self {
+        Self::Parse {
+            lump: None,
+            offset,
+            kind,
+        }
+    }
+
+    /// Attaches lump name to the parse error unless it already has one.
+    pub fn with_lump<S: Into<String>>(mut self, name: S) -> Self {
+        if let Self::Parse {
+            lump: lump @ None, ..
+        } = &mut self
+        {
+            *lump = Some(name.into());
+        }
+        self
+    }
+
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Parse { kind, .. } => Some(*kind),
+            Self::Io(_) => None,
+        }
+    }
+
+    pub fn lump(&self) -> Option<&str> {
+        match self {
+            Self::Parse { lump, .. } => lump.as_deref(),
+            Self::Io(_) => None,
+        }
+    }
+
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Self::Parse { offset, .. } => Some(*offset),
+            Self::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { lump, offset, kind } => {
+                write!(f, "{} at offset {}", kind, offset)?;
+                if let Some(lump) = lump {
+                    write!(f, " of lump {}", lump)?;
+                }
+                Ok(())
+            }
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Parse { .. } => None,
+            Self::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
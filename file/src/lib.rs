@@ -1 +1,4 @@
+pub mod error;
 pub mod wad;
+
+pub use error::{Error, ErrorKind, Result};
@@ -0,0 +1,603 @@
+use std::{collections::HashMap, convert::TryFrom, f64::consts::PI};
+
+use crate::{
+    error::{Error, ErrorKind, Result},
+    wad::parser::level::{
+        BoundingBox, Level, LevelFormat, Node, NodeChild, Segment, SubSector, Vertex,
+    },
+};
+
+/// Number of subsectors or nodes vanilla 16-bit children can refer to.
+const VANILLA_CHILDREN: usize = 0x8000;
+/// Distance from a line below which a point counts as lying on it.
+const ON_LINE: f64 = 0.5;
+/// Distance a point of a convex subsector may lie behind its segs due to rounded split vertices.
+const CONVEX_TOLERANCE: f64 = 2.0;
+/// Distance by which each seg may differ from its part of linedef due to rounded split vertices.
+const COVERAGE_TOLERANCE: f64 = 1.0;
+
+type Point = (f64, f64);
+
+/// Piece of a linedef side being sorted into subsectors.
+#[derive(Clone, Copy)]
+struct Seg {
+    start: usize,
+    end: usize,
+    linedef: usize,
+    side: u8,
+    /// Distance from the start of the linedef side to the start of the seg
+    offset: f64,
+}
+
+/// Partition line with its start and direction.
+#[derive(Clone, Copy)]
+struct Line {
+    origin: Point,
+    delta: Point,
+}
+
+impl Line {
+    /// Signed distance of the point, positive on the right (front) side.
+    fn distance(&self, (x, y): Point) -> f64 {
+        let (dx, dy) = self.delta;
+        (dy * (x - self.origin.0) - dx * (y - self.origin.1)) / dx.hypot(dy)
+    }
+}
+
+enum Side {
+    Front,
+    Back,
+    /// Split at the point
+    Split(Point),
+}
+
+/// Builder of Doom nodes (`SEGS`, `SSECTORS` and `NODES`) choosing partition lines among segs.
+/// Every candidate is scored by the number of segs it splits, each costing `split_cost`,
+/// plus the difference in seg counts of both sides, and the cheapest one is taken.
+pub struct NodeBuilder {
+    pub split_cost: usize,
+    /// Number of segs tried as partitions at most, spread evenly among all of them
+    pub max_candidates: usize,
+}
+
+impl Default for NodeBuilder {
+    fn default() -> Self {
+        Self {
+            split_cost: 8,
+            max_candidates: 64,
+        }
+    }
+}
+
+struct State<'l> {
+    builder: &'l NodeBuilder,
+    points: Vec<Point>,
+    /// Vertices made by splits, so both sides of a line share them
+    split_points: HashMap<(i16, i16), usize>,
+    segments: Vec<Segment>,
+    subsectors: Vec<SubSector>,
+    nodes: Vec<Node>,
+    angles: Vec<f64>,
+}
+
+fn too_large(lump: &str, count: usize) -> Error {
+    Error::new(ErrorKind::OutOfBounds, count).with_lump(lump)
+}
+
+impl NodeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces nodes of the level with the new ones, vertices made by splitting segs
+    /// are appended to the level's ones. Fails with [`ErrorKind::OutOfBounds`] when
+    /// the map doesn't fit into Doom nodes format.
+    pub fn build(&self, level: &mut Level<'_>) -> Result<()> {
+        let mut state = State {
+            builder: self,
+            points: level
+                .vertices
+                .iter()
+                .map(|&(x, y)| (x.into(), y.into()))
+                .collect(),
+            split_points: HashMap::new(),
+            segments: Vec::new(),
+            subsectors: Vec::new(),
+            nodes: Vec::new(),
+            angles: Vec::new(),
+        };
+
+        let mut segs = Vec::new();
+        for (i, linedef) in level.linedefs.iter().enumerate() {
+            let (start, end) = (linedef.vertex_start as usize, linedef.vertex_end as usize);
+            match (state.points.get(start), state.points.get(end)) {
+                (Some(a), Some(b)) if a != b => {}
+                _ => continue,
+            }
+            let sides = [linedef.sidedef_right, linedef.sidedef_left];
+            for (side, &sidedef) in sides.iter().enumerate() {
+                if sidedef.is_some() {
+                    let (start, end) = if side == 0 {
+                        (start, end)
+                    } else {
+                        (end, start)
+                    };
+                    segs.push(Seg {
+                        start,
+                        end,
+                        linedef: i,
+                        side: side as u8,
+                        offset: 0.0,
+                    });
+                }
+            }
+        }
+        state.angles = level
+            .linedefs
+            .iter()
+            .map(|linedef| {
+                let a = state.points.get(linedef.vertex_start as usize);
+                let b = state.points.get(linedef.vertex_end as usize);
+                match (a, b) {
+                    (Some(&(ax, ay)), Some(&(bx, by))) => (by - ay).atan2(bx - ax),
+                    _ => 0.0,
+                }
+            })
+            .collect();
+
+        if !segs.is_empty() {
+            state.build(segs)?;
+        }
+        level.vertices.extend(
+            state.points[level.vertices.len()..]
+                .iter()
+                .map(|&(x, y)| (x as i16, y as i16)),
+        );
+        level.segments = state.segments;
+        level.subsectors = state.subsectors;
+        level.nodes = state.nodes;
+        Ok(())
+    }
+}
+
+impl State<'_> {
+    fn line(&self, seg: &Seg) -> Line {
+        let (a, b) = (self.points[seg.start], self.points[seg.end]);
+        Line {
+            origin: a,
+            delta: (b.0 - a.0, b.1 - a.1),
+        }
+    }
+
+    fn side(&self, partition: &Line, seg: &Seg) -> Side {
+        let (a, b) = (self.points[seg.start], self.points[seg.end]);
+        let (da, db) = (partition.distance(a), partition.distance(b));
+        let on_a = da.abs() < ON_LINE;
+        let on_b = db.abs() < ON_LINE;
+        if on_a && on_b {
+            let (dx, dy) = partition.delta;
+            if dx * (b.0 - a.0) + dy * (b.1 - a.1) > 0.0 {
+                Side::Front
+            } else {
+                Side::Back
+            }
+        } else if (on_a || da > 0.0) && (on_b || db > 0.0) {
+            Side::Front
+        } else if (on_a || da < 0.0) && (on_b || db < 0.0) {
+            Side::Back
+        } else {
+            let t = da / (da - db);
+            Side::Split((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t))
+        }
+    }
+
+    /// Checks whether no seg lies behind another one.
+    fn is_convex(&self, segs: &[Seg]) -> bool {
+        segs.iter().all(|seg| {
+            let line = self.line(seg);
+            segs.iter()
+                .all(|other| matches!(self.side(&line, other), Side::Front))
+        })
+    }
+
+    /// Scores the partition, `None` if it leaves all segs on one side.
+    fn cost(&self, partition: &Line, segs: &[Seg]) -> Option<usize> {
+        let (mut front, mut back, mut splits) = (0, 0, 0);
+        for seg in segs {
+            match self.side(partition, seg) {
+                Side::Front => front += 1,
+                Side::Back => back += 1,
+                Side::Split(_) => splits += 1,
+            }
+        }
+        if back + splits == 0 || front + splits == 0 {
+            None
+        } else {
+            Some(splits * self.builder.split_cost + (front as isize - back as isize).unsigned_abs())
+        }
+    }
+
+    /// Picks the cheapest partition which has segs on both sides, trying every seg
+    /// if none of the sampled ones fits.
+    fn choose_partition(&self, segs: &[Seg]) -> Option<Line> {
+        let step = (segs.len() / self.builder.max_candidates.max(1)).max(1);
+        let choose = |step| {
+            segs.iter()
+                .step_by(step)
+                .map(|candidate| self.line(candidate))
+                .filter_map(|line| Some((self.cost(&line, segs)?, line)))
+                .min_by_key(|&(cost, _)| cost)
+                .map(|(_, line)| line)
+        };
+        choose(step).or_else(|| if step > 1 { choose(1) } else { None })
+    }
+
+    fn split_point(&mut self, (x, y): Point) -> usize {
+        let key = (x.round() as i16, y.round() as i16);
+        let points = &mut self.points;
+        *self.split_points.entry(key).or_insert_with(|| {
+            points.push((key.0.into(), key.1.into()));
+            points.len() - 1
+        })
+    }
+
+    fn divide(&mut self, partition: &Line, segs: Vec<Seg>) -> (Vec<Seg>, Vec<Seg>) {
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        for seg in segs {
+            match self.side(partition, &seg) {
+                Side::Front => front.push(seg),
+                Side::Back => back.push(seg),
+                Side::Split(at) => {
+                    let middle = self.split_point(at);
+                    let start = self.points[seg.start];
+                    let middle_point = self.points[middle];
+                    let second = Seg {
+                        start: middle,
+                        offset: seg.offset
+                            + (middle_point.0 - start.0).hypot(middle_point.1 - start.1),
+                        ..seg
+                    };
+                    let first = Seg { end: middle, ..seg };
+                    // Rounded split point may be one of the ends, leaving nothing to split
+                    if middle == seg.start || middle == seg.end {
+                        let far_end = if middle == seg.start {
+                            seg.end
+                        } else {
+                            seg.start
+                        };
+                        if partition.distance(self.points[far_end]) > 0.0 {
+                            front.push(seg);
+                        } else {
+                            back.push(seg);
+                        }
+                    } else if partition.distance(start) > 0.0 {
+                        front.push(first);
+                        back.push(second);
+                    } else {
+                        back.push(first);
+                        front.push(second);
+                    }
+                }
+            }
+        }
+        (front, back)
+    }
+
+    fn bounds(&self, segs: &[Seg]) -> BoundingBox {
+        let (mut left, mut bottom, mut right, mut top) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+        for seg in segs {
+            for &(x, y) in &[self.points[seg.start], self.points[seg.end]] {
+                left = left.min(x);
+                right = right.max(x);
+                bottom = bottom.min(y);
+                top = top.max(y);
+            }
+        }
+        BoundingBox {
+            top: top as i16,
+            bottom: bottom as i16,
+            left: left as i16,
+            right: right as i16,
+        }
+    }
+
+    fn subsector(&mut self, segs: &[Seg]) -> Result<NodeChild> {
+        let start_seg = i16::try_from(self.segments.len())
+            .map_err(|_| too_large("SEGS", self.segments.len()))? as u32;
+        for seg in segs {
+            let mut angle = self.angles[seg.linedef];
+            if seg.side == 1 {
+                angle += PI;
+            }
+            let bams = (angle.rem_euclid(2.0 * PI) / (2.0 * PI) * 65536.0) as u32 as u16;
+            self.segments.push(Segment {
+                vertex_start: seg.start as u32,
+                vertex_end: seg.end as u32,
+                bams: bams as i16,
+                line_num: seg.linedef as u32,
+                segside: seg.side.into(),
+                segoffset: seg.offset.round() as i16,
+            });
+        }
+        let index = self.subsectors.len();
+        self.subsectors.push(SubSector {
+            numsegs: segs.len() as u32,
+            start_seg,
+        });
+        if index < VANILLA_CHILDREN {
+            Ok(NodeChild::Subsector(index as u32))
+        } else {
+            Err(too_large("SSECTORS", index))
+        }
+    }
+
+    /// Builds subtree of the segs returning reference to its root.
+    fn build(&mut self, segs: Vec<Seg>) -> Result<NodeChild> {
+        if self.is_convex(&segs) {
+            return self.subsector(&segs);
+        }
+        let partition = match self.choose_partition(&segs) {
+            Some(partition) => partition,
+            None => return self.subsector(&segs),
+        };
+        let (front, back) = self.divide(&partition, segs);
+        // Rounding may leave one side empty, which would never end
+        if front.is_empty() || back.is_empty() {
+            let segs: Vec<_> = front.into_iter().chain(back).collect();
+            return self.subsector(&segs);
+        }
+        let bbox = [self.bounds(&front), self.bounds(&back)];
+        let children = [self.build(front)?.into(), self.build(back)?.into()];
+
+        let index = self.nodes.len();
+        // Saturated direction would split the map other way than the builder did
+        let delta = |d: f64| i16::try_from(d as i64).map_err(|_| too_large("NODES", index));
+        self.nodes.push(Node {
+            x: partition.origin.0 as i16,
+            y: partition.origin.1 as i16,
+            dx: delta(partition.delta.0)?,
+            dy: delta(partition.delta.1)?,
+            bbox,
+            children,
+        });
+        if index < VANILLA_CHILDREN {
+            Ok(NodeChild::Node(index as u32))
+        } else {
+            Err(too_large("NODES", index))
+        }
+    }
+}
+
+fn vertex(vertices: &[Vertex], index: u32) -> Option<Point> {
+    vertices
+        .get(index as usize)
+        .map(|&(x, y)| (x.into(), y.into()))
+}
+
+/// Checks nodes of the level: every subsector is convex, reached from the root
+/// exactly once and linedef sides are covered by segs completely.
+/// Problems are reported as [`ErrorKind::Malformed`] error at the offset of the entry
+/// in its lump (`SSECTORS`, `NODES` or `LINEDEFS`).
+pub fn validate(level: &Level<'_>) -> Result<()> {
+    let malformed =
+        |lump: &str, offset: usize| Err(Error::new(ErrorKind::Malformed, offset).with_lump(lump));
+    let segs_of = |subsector: &SubSector| {
+        let start = subsector.start_seg as usize;
+        level
+            .segments
+            .get(start..start.checked_add(subsector.numsegs as usize)?)
+    };
+
+    for (i, subsector) in level.subsectors.iter().enumerate() {
+        let segs = match segs_of(subsector) {
+            Some(segs) => segs,
+            None => return malformed("SSECTORS", i * 4),
+        };
+        let mut ends = Vec::new();
+        for seg in segs {
+            match (
+                vertex(&level.vertices, seg.vertex_start),
+                vertex(&level.vertices, seg.vertex_end),
+            ) {
+                (Some(a), Some(b)) => ends.push((a, b)),
+                _ => return malformed("SSECTORS", i * 4),
+            }
+        }
+        let convex = ends.iter().all(|&(a, b)| {
+            let line = Line {
+                origin: a,
+                delta: (b.0 - a.0, b.1 - a.1),
+            };
+            (a != b)
+                && ends.iter().all(|&(c, d)| {
+                    line.distance(c) > -CONVEX_TOLERANCE && line.distance(d) > -CONVEX_TOLERANCE
+                })
+        });
+        if !convex {
+            return malformed("SSECTORS", i * 4);
+        }
+    }
+
+    let mut reached = vec![0; level.subsectors.len()];
+    if level.nodes.is_empty() {
+        reached.iter_mut().for_each(|count| *count += 1);
+    } else {
+        let mut stack = vec![NodeChild::Node(level.nodes.len() as u32 - 1)];
+        let mut visited = vec![false; level.nodes.len()];
+        while let Some(child) = stack.pop() {
+            match child {
+                NodeChild::Subsector(index) => match reached.get_mut(index as usize) {
+                    Some(count) => *count += 1,
+                    None => return malformed("NODES", 0),
+                },
+                NodeChild::Node(index) => {
+                    let index = index as usize;
+                    match visited.get_mut(index) {
+                        Some(seen @ false) => *seen = true,
+                        _ => return malformed("NODES", index.min(level.nodes.len()) * 28),
+                    }
+                    let node = &level.nodes[index];
+                    stack.extend(&[node.front(), node.back()]);
+                }
+            }
+        }
+    }
+    if let Some(i) = reached.iter().position(|&count| count != 1) {
+        return malformed("SSECTORS", i * 4);
+    }
+
+    let mut covered = vec![[(0.0, 0); 2]; level.linedefs.len()];
+    for seg in &level.segments {
+        let (a, b) = match (
+            vertex(&level.vertices, seg.vertex_start),
+            vertex(&level.vertices, seg.vertex_end),
+        ) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue,
+        };
+        if let Some(sides) = covered.get_mut(seg.line_num as usize) {
+            let (length, count) = &mut sides[usize::from(seg.segside != 0)];
+            *length += (b.0 - a.0).hypot(b.1 - a.1);
+            *count += 1;
+        }
+    }
+    let linedef_size = if level.format == LevelFormat::Hexen {
+        16
+    } else {
+        14
+    };
+    for (i, (linedef, covered)) in level.linedefs.iter().zip(&covered).enumerate() {
+        let length = match linedef.ends(&level.vertices) {
+            Some(((ax, ay), (bx, by))) => {
+                (f64::from(bx) - f64::from(ax)).hypot(f64::from(by) - f64::from(ay))
+            }
+            None => continue,
+        };
+        let sides = [linedef.sidedef_right, linedef.sidedef_left];
+        for (side, &sidedef) in sides.iter().enumerate() {
+            let (seg_length, count) = covered[side];
+            if sidedef.is_some()
+                && (seg_length - length).abs() > COVERAGE_TOLERANCE * count.max(1) as f64
+            {
+                return malformed("LINEDEFS", i * linedef_size);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, NodeBuilder};
+    use crate::{
+        error::ErrorKind,
+        wad::parser::level::{Level, LevelFormat, Linedef, Sector, Sidedef},
+    };
+
+    fn room_with_pillar() -> Level<'static> {
+        let vertices = vec![
+            (0, 0),
+            (256, 0),
+            (256, 256),
+            (0, 256),
+            (96, 96),
+            (96, 160),
+            (160, 160),
+            (160, 96),
+        ];
+        let lines = [
+            (0, 3),
+            (3, 2),
+            (2, 1),
+            (1, 0),
+            (4, 7),
+            (7, 6),
+            (6, 5),
+            (5, 4),
+        ];
+        let linedefs = lines
+            .iter()
+            .map(|&(vertex_start, vertex_end)| Linedef {
+                vertex_start,
+                vertex_end,
+                flags: 1,
+                function: 0,
+                tag: 0,
+                args: [0; 5],
+                sidedef_right: Some(0),
+                sidedef_left: None,
+            })
+            .collect();
+        Level {
+            name: "MAP01",
+            format: LevelFormat::Doom,
+            behavior: None,
+            things: Vec::new(),
+            linedefs,
+            sidedefs: vec![Sidedef {
+                x_offset: 0,
+                y_offset: 0,
+                upper_texture: "-",
+                lower_texture: "-",
+                mid_texture: "STARTAN3",
+                sector_ref: 0,
+            }],
+            vertices,
+            segments: Vec::new(),
+            subsectors: Vec::new(),
+            nodes: Vec::new(),
+            sectors: vec![Sector {
+                floor_height: 0,
+                ceiling_height: 128,
+                floor_pic: "FLOOR0_1",
+                ceiling_pic: "CEIL1_1",
+                light_level: 160,
+                special_sector: 0,
+                tag: 0,
+            }],
+            reject: None,
+            blockmap: None,
+            gl_nodes: None,
+            udmf: None,
+        }
+    }
+
+    #[test]
+    fn build_convex_subsectors() {
+        let mut level = room_with_pillar();
+        NodeBuilder::new()
+            .build(&mut level)
+            .expect("Error building nodes");
+        assert!(level.subsectors.len() >= 4);
+        assert_eq!(level.nodes.len(), level.subsectors.len() - 1);
+        validate(&level).expect("Invalid nodes");
+
+        let seg = &mut level.segments[0];
+        std::mem::swap(&mut seg.vertex_start, &mut seg.vertex_end);
+        let error = validate(&level).err().and_then(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::Malformed));
+    }
+
+    #[test]
+    fn refuse_long_partitions() {
+        let mut level = room_with_pillar();
+        let corners = [
+            (-32000, -32000),
+            (32000, -32000),
+            (32000, 32000),
+            (-32000, 32000),
+        ];
+        let pillar = [
+            (-20000, -20000),
+            (-20000, 20000),
+            (20000, 20000),
+            (20000, -20000),
+        ];
+        level.vertices = corners.iter().chain(&pillar).copied().collect();
+        let error = NodeBuilder::new()
+            .build(&mut level)
+            .expect_err("Partition longer than 32767 units stored");
+        assert_eq!(error.kind(), Some(ErrorKind::OutOfBounds));
+        assert_eq!(error.lump(), Some("NODES"));
+    }
+}
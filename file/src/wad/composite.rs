@@ -0,0 +1,400 @@
+use crate::{
+    error::{Error, ErrorKind, Result},
+    wad::{
+        namespace::Namespace,
+        parser::{
+            picture::Picture,
+            pnames::parse_pnames,
+            texture::Texture,
+            ztextures::{TextureDefinition, TexturePatch},
+        },
+        resource::ResourceSet,
+    },
+};
+
+/// Wall texture drawn from its patches.
+pub struct Composite {
+    pub width: usize,
+    pub height: usize,
+    /// Palette indices column by column, `height` of them per column
+    pub pixels: Vec<u8>,
+    /// Whether each pixel of `pixels` is covered by a patch
+    pub mask: Vec<bool>,
+}
+
+impl Composite {
+    pub fn column(&self, x: usize) -> Option<&[u8]> {
+        self.pixels.get(x * self.height..(x + 1) * self.height)
+    }
+
+    /// Palette index of the pixel, `None` if it's transparent.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let i = x * self.height + y;
+        Some(self.pixels[i]).filter(|_| self.mask[i])
+    }
+}
+
+/// Draws `TEXTURE1`/`TEXTURE2` entries from patches listed in `PNAMES`,
+/// and `TEXTURES` definitions naming the patches.
+pub struct TextureCompositor {
+    /// Patches in `PNAMES` order, `None` for the ones missing
+    patches: Vec<Option<Picture>>,
+    /// Uppercase `PNAMES`
+    names: Vec<String>,
+    /// Reproduce vanilla renderer bugs of columns made of several patches: posts above the top
+    /// of the texture are shifted down rather than clipped, and the columns are opaque
+    /// as a whole, showing the "Medusa" effect and tutti-frutti in their gaps.
+    /// Columns of a single patch are drawn from it as is, its vertical offset ignored
+    pub vanilla_quirks: bool,
+}
+
+impl TextureCompositor {
+    /// Resolves patch names through `lookup`, missing patches are skipped while drawing
+    /// like ZDoom does.
+    pub fn new<'a, F>(pnames: &[&str], mut lookup: F) -> Result<Self>
+    where
+        F: FnMut(&str) -> Option<&'a [u8]>,
+    {
+        let patches = pnames
+            .iter()
+            .map(|&name| {
+                lookup(name)
+                    .map(|data| Picture::parse(data).map_err(|e| e.with_lump(name)))
+                    .transpose()
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            patches,
+            names: pnames
+                .iter()
+                .map(|name| name.to_ascii_uppercase())
+                .collect(),
+            vanilla_quirks: false,
+        })
+    }
+
+    /// Reads `PNAMES` of the resources and finds patches among them,
+    /// the ones of `P_START`...`P_END` taking precedence over global lumps.
+    pub fn from_resources(resources: &ResourceSet<'_>) -> Result<Self> {
+        let pnames = resources
+            .get_by_name("PNAMES")
+            .ok_or_else(|| Error::new(ErrorKind::Malformed, 0).with_lump("PNAMES"))?;
+        let names = parse_pnames(pnames.lump.data).map_err(|e| e.with_lump("PNAMES"))?;
+        Self::new(&names, |name| {
+            resources
+                .get_in_namespace(Namespace::Patches, name.to_ascii_uppercase())
+                .or_else(|| resources.get_by_name(name.to_ascii_uppercase()))
+                .map(|resource| resource.lump.data)
+        })
+    }
+
+    /// Draws the texture, patch index past `PNAMES` is reported as [`ErrorKind::OutOfBounds`]
+    /// of the texture.
+    pub fn compose(&self, texture: &Texture<'_>) -> Result<Composite> {
+        let width = texture.width.max(0) as usize;
+        let height = texture.height.max(0) as usize;
+        let mut pixels = vec![0; width * height];
+        let mut mask = vec![false; width * height];
+        let mut patches = Vec::with_capacity(texture.patch_descriptors.len());
+        for (i, descriptor) in texture.patch_descriptors.iter().enumerate() {
+            match self.patches.get(descriptor.id as u16 as usize) {
+                Some(Some(patch)) => patches.push((descriptor, patch)),
+                Some(None) => {}
+                None => return Err(Error::new(ErrorKind::OutOfBounds, i).with_lump(texture.name)),
+            }
+        }
+        // Patch columns along with the texture columns they cover
+        let columns = |x_offset: i16, patch_width: i16| {
+            (0..patch_width.max(0) as usize).filter_map(move |column| {
+                let x = i32::from(x_offset) + column as i32;
+                Some((column, x as usize)).filter(|_| x >= 0 && (x as usize) < width)
+            })
+        };
+        let mut patch_count = vec![0; width];
+        for &(descriptor, patch) in &patches {
+            columns(descriptor.x_offset, patch.width).for_each(|(_, x)| patch_count[x] += 1);
+        }
+
+        for &(descriptor, patch) in &patches {
+            for (column, x) in columns(descriptor.x_offset, patch.width) {
+                // Vanilla draws columns of a single patch straight from it, ignoring the offset
+                let y_offset = if self.vanilla_quirks && patch_count[x] == 1 {
+                    0
+                } else {
+                    i32::from(descriptor.y_offset)
+                };
+                for (rowstart, post) in patch.posts(column) {
+                    let mut position = y_offset + rowstart as i32;
+                    let mut source = post;
+                    if position < 0 {
+                        let clipped = (-position as usize).min(post.len());
+                        source = if self.vanilla_quirks {
+                            // Vanilla shortens the post but keeps drawing from its start
+                            &post[..post.len() - clipped]
+                        } else {
+                            &post[clipped..]
+                        };
+                        position = 0;
+                    }
+                    let position = position as usize;
+                    if position >= height {
+                        continue;
+                    }
+                    let count = source.len().min(height - position);
+                    let start = x * height + position;
+                    pixels[start..start + count].copy_from_slice(&source[..count]);
+                    mask[start..start + count]
+                        .iter_mut()
+                        .for_each(|m| *m = true);
+                }
+            }
+        }
+
+        if self.vanilla_quirks {
+            for (x, _) in patch_count.iter().enumerate().filter(|(_, &n)| n > 1) {
+                mask[x * height..(x + 1) * height]
+                    .iter_mut()
+                    .for_each(|m| *m = true);
+            }
+        }
+        Ok(Composite {
+            width,
+            height,
+            pixels,
+            mask,
+        })
+    }
+
+    /// Draws the definition, patches not in `PNAMES` are resolved through `lookup` and skipped
+    /// when missing. Blend, alpha and style need palette colors and are left to the caller.
+    pub fn compose_definition<'a, F>(
+        &self,
+        definition: &TextureDefinition<'_>,
+        mut lookup: F,
+    ) -> Result<Composite>
+    where
+        F: FnMut(&str) -> Option<&'a [u8]>,
+    {
+        let width = definition.width.max(0) as usize;
+        let height = definition.height.max(0) as usize;
+        let mut composite = Composite {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            mask: vec![false; width * height],
+        };
+        for part in &definition.patches {
+            let known = self
+                .names
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(part.name))
+                .and_then(|i| self.patches[i].as_ref());
+            let parsed;
+            let patch = match known {
+                Some(patch) => patch,
+                None => match lookup(part.name) {
+                    Some(data) => {
+                        parsed = Picture::parse(data).map_err(|e| e.with_lump(part.name))?;
+                        &parsed
+                    }
+                    None => continue,
+                },
+            };
+            draw_part(&mut composite, patch, part);
+        }
+        Ok(composite)
+    }
+}
+
+/// Draws the patch flipped, rotated and translated as the part tells.
+fn draw_part(composite: &mut Composite, patch: &Picture, part: &TexturePatch<'_>) {
+    let (width, height) = (patch.width.max(0) as usize, patch.height.max(0) as usize);
+    let (mut x0, mut y0) = (part.x_offset, part.y_offset);
+    if part.use_offsets {
+        x0 -= i32::from(patch.left_offset);
+        y0 -= i32::from(patch.top_offset);
+    }
+    let map = part.translation.as_ref().and_then(|t| t.index_map());
+    for column in 0..width {
+        for (rowstart, post) in patch.posts(column) {
+            for (row, &value) in (rowstart..height).zip(post) {
+                let sx = if part.flip_x {
+                    width - 1 - column
+                } else {
+                    column
+                };
+                let sy = if part.flip_y { height - 1 - row } else { row };
+                let (dx, dy) = match part.rotate {
+                    90 => (height - 1 - sy, sx),
+                    180 => (width - 1 - sx, height - 1 - sy),
+                    270 => (sy, width - 1 - sx),
+                    _ => (sx, sy),
+                };
+                let (x, y) = (x0 + dx as i32, y0 + dy as i32);
+                if x < 0 || y < 0 || x as usize >= composite.width || y as usize >= composite.height
+                {
+                    continue;
+                }
+                let i = x as usize * composite.height + y as usize;
+                composite.pixels[i] = map.map_or(value, |map| map[usize::from(value)]);
+                composite.mask[i] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TextureCompositor;
+    use crate::wad::parser::{
+        texture::{PatchDescriptor, Texture},
+        ztextures::{TextureDefinition, TextureDefinitions},
+    };
+
+    /// Patch of 1-pixel wide columns, each one a single post.
+    fn patch(posts: &[(u8, &[u8])]) -> Vec<u8> {
+        let header_size = 8 + 4 * posts.len();
+        let mut data = Vec::new();
+        for &value in &[posts.len() as i16, 4, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut columns = Vec::new();
+        for &(rowstart, pixels) in posts {
+            data.extend_from_slice(&((header_size + columns.len()) as i32).to_le_bytes());
+            columns.extend_from_slice(&[rowstart, pixels.len() as u8, 0]);
+            columns.extend_from_slice(pixels);
+            columns.extend_from_slice(&[0, 0xFF]);
+        }
+        data.extend(columns);
+        data
+    }
+
+    #[test]
+    fn compose_with_clipping_and_quirks() {
+        let wide = patch(&[(0, &[1, 2, 3]), (2, &[4])]);
+        let narrow = patch(&[(0, &[6, 7])]);
+        let mut compositor =
+            TextureCompositor::new(&["WIDE", "NARROW", "MISSING"], |name| match name {
+                "WIDE" => Some(&wide[..]),
+                "NARROW" => Some(&narrow[..]),
+                _ => None,
+            })
+            .expect("Error reading patches");
+        let descriptor = |x_offset, y_offset, id| PatchDescriptor {
+            x_offset,
+            y_offset,
+            id,
+            stepdir: 0,
+            colormap: 0,
+        };
+        let texture = Texture {
+            name: "TEST",
+            flags: 0,
+            scale_x: 0,
+            scale_y: 0,
+            width: 3,
+            height: 3,
+            column_directory: 0,
+            patch_descriptors: vec![
+                descriptor(0, -1, 0),
+                descriptor(1, 2, 1),
+                descriptor(2, 0, 2),
+            ],
+        };
+
+        let composite = compositor
+            .compose(&texture)
+            .expect("Error composing texture");
+        assert_eq!(composite.column(0), Some(&[2, 3, 0][..]));
+        assert_eq!(composite.column(1), Some(&[0, 4, 6][..]));
+        assert_eq!(composite.pixel(1, 0), None);
+        assert_eq!(composite.pixel(0, 2), None);
+        assert_eq!(composite.pixel(2, 0), None);
+
+        compositor.vanilla_quirks = true;
+        let composite = compositor
+            .compose(&texture)
+            .expect("Error composing texture");
+        // Single patch column is drawn unshifted
+        assert_eq!(composite.column(0), Some(&[1, 2, 3][..]));
+        // Two patches make the column opaque, gaps included
+        assert_eq!(composite.pixel(1, 0), Some(0));
+
+        // Post of a multi-patch column above the top is shortened but read from the start
+        let stacked = Texture {
+            width: 1,
+            patch_descriptors: vec![descriptor(0, -1, 0), descriptor(0, 2, 1)],
+            ..texture
+        };
+        let composite = compositor
+            .compose(&stacked)
+            .expect("Error composing texture");
+        assert_eq!(composite.column(0), Some(&[1, 2, 6][..]));
+        compositor.vanilla_quirks = false;
+        let composite = compositor
+            .compose(&stacked)
+            .expect("Error composing texture");
+        assert_eq!(composite.column(0), Some(&[2, 3, 6][..]));
+
+        let broken = Texture {
+            patch_descriptors: vec![descriptor(0, 0, 3)],
+            ..stacked
+        };
+        assert!(compositor.compose(&broken).is_err());
+    }
+
+    #[test]
+    fn compose_text_definitions() {
+        let wide = patch(&[(0, &[1, 2, 3]), (2, &[4])]);
+        let extra = patch(&[(0, &[6, 7])]);
+        let compositor =
+            TextureCompositor::new(&["WIDE"], |_| Some(&wide[..])).expect("Error reading patches");
+        let lump = br#"Texture "T", 4, 4 {
+            Patch "wide", 0, 0 { FlipX }
+            Patch "EXTRA", -2, 3 { Rotate 90 Translation "6:7=8:9" }
+            Patch "MISSING", 0, 0
+        }"#;
+        let definitions = TextureDefinitions::parse(lump).expect("Error parsing TEXTURES");
+
+        let composite = compositor
+            .compose_definition(&definitions.definitions[0], |name| match name {
+                "EXTRA" => Some(&extra[..]),
+                _ => None,
+            })
+            .expect("Error composing texture");
+        assert_eq!(composite.column(1), Some(&[1, 2, 3, 8][..]));
+        assert_eq!(composite.pixel(0, 0), None);
+        assert_eq!(composite.pixel(0, 2), Some(4));
+        assert_eq!(composite.pixel(0, 3), Some(9));
+
+        // Binary textures resolve through the same names
+        let texture = Texture {
+            name: "BIN",
+            flags: 0,
+            scale_x: 0,
+            scale_y: 0,
+            width: 2,
+            height: 4,
+            column_directory: 0,
+            patch_descriptors: vec![PatchDescriptor {
+                x_offset: 0,
+                y_offset: -1,
+                id: 0,
+                stepdir: 1,
+                colormap: 0,
+            }],
+        };
+        let definition =
+            TextureDefinition::from_texture(&texture, &["WIDE"]).expect("Patch not in PNAMES");
+        let binary = compositor
+            .compose(&texture)
+            .expect("Error composing texture");
+        let text = compositor
+            .compose_definition(&definition, |_| None)
+            .expect("Error composing texture");
+        assert_eq!((binary.pixels, binary.mask), (text.pixels, text.mask));
+    }
+}
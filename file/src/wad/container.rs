@@ -0,0 +1,112 @@
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+
+use crate::wad::{
+    namespace::{Namespace, NamespaceLumps},
+    parser::file::Lump,
+};
+
+/// Read access to an ordered set of named lumps, shared by every archive backend.
+pub trait Container {
+    fn len(&self) -> usize;
+
+    fn get_by_index(&self, i: usize) -> Option<Lump<'_>>;
+
+    /// Gets the last lump with such name.
+    fn get_by_name(&self, name: &str) -> Option<Lump<'_>>;
+
+    /// Gets all lumps with such name in directory order.
+    fn get_all_by_name(&self, name: &str) -> Vec<Lump<'_>> {
+        (0..self.len())
+            .filter_map(|i| self.get_by_index(i))
+            .filter(|lump| lump.name == name)
+            .collect()
+    }
+
+    /// Gets lumps placed between markers of the namespace.
+    fn namespace(&self, namespace: Namespace) -> NamespaceLumps<'_> {
+        namespace.collect((0..self.len()).filter_map(|i| self.get_by_index(i)))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn iter(&self) -> Lumps<'_, Self>
+    where
+        Self: Sized,
+    {
+        Lumps {
+            container: self,
+            range: 0..self.len(),
+        }
+    }
+}
+
+/// Iterator over lumps of a [`Container`] in directory order.
+pub struct Lumps<'a, C> {
+    container: &'a C,
+    range: std::ops::Range<usize>,
+}
+
+impl<'a, C: Container> Iterator for Lumps<'a, C> {
+    type Item = Lump<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range
+            .next()
+            .and_then(|i| self.container.get_by_index(i))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'a, C: Container> DoubleEndedIterator for Lumps<'a, C> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range
+            .next_back()
+            .and_then(|i| self.container.get_by_index(i))
+    }
+}
+
+impl<'a, C: Container> ExactSizeIterator for Lumps<'a, C> {}
+
+/// Indices of lumps sharing the same name, kept in directory order.
+/// Lookup by name gives the last one like the vanilla engine does.
+#[derive(Clone)]
+pub(crate) struct NameIndex<K>(HashMap<K, Vec<usize>>);
+
+impl<K: Borrow<str> + Hash + Eq> NameIndex<K> {
+    pub(crate) fn build<I: IntoIterator<Item = K>>(names: I) -> Self {
+        let mut index = Self(HashMap::new());
+        names
+            .into_iter()
+            .enumerate()
+            .for_each(|(i, name)| index.insert(name, i));
+        index
+    }
+
+    pub(crate) fn insert(&mut self, name: K, i: usize) {
+        let indices = self.0.entry(name).or_default();
+        let pos = indices.partition_point(|&x| x < i);
+        indices.insert(pos, i);
+    }
+
+    pub(crate) fn remove(&mut self, name: &str, i: usize) {
+        if let Some(indices) = self.0.get_mut(name) {
+            indices.retain(|&x| x != i);
+            if indices.is_empty() {
+                self.0.remove(name);
+            }
+        }
+    }
+
+    pub(crate) fn all(&self, name: &str) -> &[usize] {
+        self.0.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub(crate) fn last(&self, name: &str) -> Option<usize> {
+        self.all(name).last().copied()
+    }
+}
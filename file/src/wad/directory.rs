@@ -0,0 +1,562 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::Result,
+    wad::{
+        container::{Container, NameIndex},
+        namespace::Namespace,
+        parser::{
+            file::{write_wad, Archive, ArchiveBuilder, Lump, Type},
+            level::LevelLumps,
+        },
+    },
+};
+use nom::Offset;
+
+const NAME_LEN: usize = 8;
+const LUMP_EXTENSION: &str = "lmp";
+const MAPS_DIR: &str = "maps";
+/// Lists lumps in directory order, one per line: path of the file (always with extension)
+/// or name of a marker. Files missing in it go after the listed ones.
+const MANIFEST: &str = "lumps.txt";
+
+const fn namespace_dir(namespace: Namespace) -> &'static str {
+    match namespace {
+        Namespace::Sprites => "sprites",
+        Namespace::Flats => "flats",
+        Namespace::Patches => "patches",
+        Namespace::Colormaps => "colormaps",
+    }
+}
+
+/// Where a file of the tree goes, judging by its top directory.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Global,
+    Namespace(Namespace),
+    Map,
+}
+
+impl Location {
+    fn of(path: &str) -> Option<Self> {
+        let dir = match path.find('/') {
+            Some(pos) => path[..pos].to_ascii_lowercase(),
+            None => return Some(Self::Global),
+        };
+        if let Some(&namespace) = Namespace::ALL
+            .iter()
+            .find(|&&namespace| namespace_dir(namespace) == dir)
+        {
+            return Some(Self::Namespace(namespace));
+        }
+        match dir.as_str() {
+            MAPS_DIR if path.to_ascii_lowercase().ends_with(".wad") => Some(Self::Map),
+            "graphics" | "sounds" | "music" => Some(Self::Global),
+            _ => None,
+        }
+    }
+}
+
+/// Lump name made of the file name without extension the way ZDoom does,
+/// `^` standing for `\` that can't be used in file names (e.g. `VILE^1` sprite frame).
+fn lump_name(path: &str) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let stem = match file_name.find('.') {
+        Some(pos) if pos > 0 => &file_name[..pos],
+        _ => file_name,
+    };
+    stem.chars()
+        .take(NAME_LEN)
+        .map(|c| {
+            if c == '^' {
+                '\\'
+            } else {
+                c.to_ascii_uppercase()
+            }
+        })
+        .collect()
+}
+
+/// Reverse of [`lump_name`]. Names that can't stand for a file of the directory,
+/// e.g. `../x` of a crafted WAD, are refused rather than written outside of it.
+fn file_name(name: &str, extension: &str) -> io::Result<String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| {
+            !c.is_control() && !matches!(c, '/' | '^' | '<' | '>' | ':' | '"' | '|' | '?' | '*')
+        });
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Lump name {:?} isn't a valid file name", name),
+        ));
+    }
+    Ok(format!("{}.{}", name.replace('\\', "^"), extension))
+}
+
+struct Entry {
+    name: String,
+    path: Option<String>,
+    range: Range<usize>,
+}
+
+impl Entry {
+    const fn marker(name: String) -> Self {
+        Self {
+            name,
+            path: None,
+            range: 0..0,
+        }
+    }
+}
+
+/// File of the tree with its lumps, a map giving every lump of its WAD.
+struct Unit {
+    location: Location,
+    path: String,
+    entries: Vec<Entry>,
+}
+
+/// Takes lumps of the units left at the location, in order of their paths.
+fn take_entries(units: &mut [Option<Unit>], location: Location) -> Vec<Entry> {
+    units
+        .iter_mut()
+        .filter(|unit| {
+            unit.as_ref()
+                .map_or(false, |unit| unit.location == location)
+        })
+        .filter_map(Option::take)
+        .flat_map(|unit| unit.entries)
+        .collect()
+}
+
+/// Lumps of a file tree laid out as a WAD would have them: root files, `graphics/`,
+/// `sounds/` and `music/` as global lumps, `sprites/`, `flats/`, `patches/` and
+/// `colormaps/` wrapped with namespace markers and every `maps/*.wad` expanded
+/// in place with its header named after the file. Other directories aren't lumps.
+/// If the tree has `lumps.txt` written by [`unpack`], lumps and markers follow its order.
+pub(crate) struct Tree {
+    data: Vec<u8>,
+    entries: Vec<Entry>,
+    named_entries: NameIndex<String>,
+}
+
+impl Tree {
+    /// Checks whether file with such `/`-separated path is a lump or the manifest of their order,
+    /// so the rest needn't be read.
+    pub(crate) fn is_needed(path: &str) -> bool {
+        path == MANIFEST || Location::of(path).is_some()
+    }
+
+    /// Builds lumps of the files given as path and content, in any order.
+    pub(crate) fn build<I>(files: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (String, Vec<u8>)>,
+    {
+        let mut files: Vec<_> = files.into_iter().collect();
+        let manifest = files
+            .iter()
+            .position(|(path, _)| path == MANIFEST)
+            .map(|i| files.swap_remove(i).1);
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut data = Vec::new();
+        let mut units = Vec::new();
+        for (path, content) in files {
+            let location = match Location::of(&path) {
+                Some(location) => location,
+                None => continue,
+            };
+            let start = data.len();
+            data.extend(content);
+            let name = lump_name(&path);
+            let entries = match location {
+                Location::Map => {
+                    let wad = &data[start..];
+                    let archive = Archive::parse(wad).map_err(|e| e.with_lump(name.clone()))?;
+                    archive
+                        .iter()
+                        .enumerate()
+                        .map(|(i, lump)| {
+                            let start = start + wad.offset(lump.data);
+                            Entry {
+                                name: if i == 0 {
+                                    name.clone()
+                                } else {
+                                    lump.name.to_owned()
+                                },
+                                path: Some(path.clone()),
+                                range: start..start + lump.data.len(),
+                            }
+                        })
+                        .collect()
+                }
+                _ => vec![Entry {
+                    name,
+                    path: Some(path.clone()),
+                    range: start..data.len(),
+                }],
+            };
+            units.push(Some(Unit {
+                location,
+                path,
+                entries,
+            }));
+        }
+
+        let mut entries = Vec::new();
+        if let Some(manifest) = manifest {
+            let by_path: HashMap<_, _> = units
+                .iter()
+                .enumerate()
+                .filter_map(|(i, unit)| Some((unit.as_ref()?.path.clone(), i)))
+                .collect();
+            let manifest = String::from_utf8_lossy(&manifest);
+            for line in manifest
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+            {
+                if line.contains('.') {
+                    // Files removed since unpacking are skipped
+                    if let Some(unit) = by_path.get(line).and_then(|&i| units[i].take()) {
+                        entries.extend(unit.entries);
+                    }
+                    continue;
+                }
+                // New files of the namespace go before its end
+                if let Some(&namespace) = Namespace::ALL.iter().find(|ns| ns.is_end(line)) {
+                    entries.extend(take_entries(&mut units, Location::Namespace(namespace)));
+                }
+                entries.push(Entry::marker(line.to_owned()));
+            }
+        }
+        entries.extend(take_entries(&mut units, Location::Global));
+        for &namespace in &Namespace::ALL {
+            let lumps = take_entries(&mut units, Location::Namespace(namespace));
+            if !lumps.is_empty() {
+                entries.push(Entry::marker(namespace.start_marker().to_owned()));
+                entries.extend(lumps);
+                entries.push(Entry::marker(namespace.end_marker().to_owned()));
+            }
+        }
+        entries.extend(take_entries(&mut units, Location::Map));
+
+        let named_entries = NameIndex::build(entries.iter().map(|entry| entry.name.clone()));
+        Ok(Self {
+            data,
+            entries,
+            named_entries,
+        })
+    }
+
+    pub(crate) fn path(&self, i: usize) -> Option<&str> {
+        self.entries.get(i).and_then(|entry| entry.path.as_deref())
+    }
+
+    pub(crate) fn index_of(&self, name: &str) -> Option<usize> {
+        self.named_entries.last(name)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn get_by_index(&self, i: usize) -> Option<Lump<'_>> {
+        self.entries.get(i).map(|entry| Lump {
+            name: &entry.name,
+            data: &self.data[entry.range.clone()],
+        })
+    }
+
+    pub(crate) fn get_all_by_name(&self, name: &str) -> Vec<Lump<'_>> {
+        self.named_entries
+            .all(name)
+            .iter()
+            .filter_map(|&i| self.get_by_index(i))
+            .collect()
+    }
+}
+
+/// Unpacked lumps kept in a directory, laid out the same way as in a PK3
+/// (see [`unpack`] for the layout). Files are read at once, so edits made later
+/// need the directory to be opened again.
+pub struct Directory {
+    root: PathBuf,
+    tree: Tree,
+}
+
+impl Directory {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let root = path.as_ref().to_path_buf();
+        let mut files = Vec::new();
+        read_files(&root, "", &mut files)?;
+        Ok(Self {
+            root,
+            tree: Tree::build(files)?,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Path of the file relative to the root the lump comes from, `None` for generated markers.
+    pub fn path(&self, i: usize) -> Option<&str> {
+        self.tree.path(i)
+    }
+
+    pub fn index_of<S: AsRef<str>>(&self, s: S) -> Option<usize> {
+        self.tree.index_of(s.as_ref())
+    }
+
+    /// Packs every lump into a WAD of the given type.
+    pub fn write_to<W: Write>(&self, w: W, wtype: Type) -> io::Result<()> {
+        write_wad(
+            w,
+            wtype,
+            self.iter().map(|lump| (lump.name, lump.data, None)),
+        )
+    }
+}
+
+/// Recursively collects lump files of the directory with `/`-separated paths relative to root.
+fn read_files(dir: &Path, prefix: &str, files: &mut Vec<(String, Vec<u8>)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let path = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            read_files(&entry.path(), &format!("{}/", path), files)?;
+        } else if Tree::is_needed(&path) {
+            files.push((path, fs::read(entry.path())?));
+        }
+    }
+    Ok(())
+}
+
+impl Container for Directory {
+    fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn get_by_index(&self, i: usize) -> Option<Lump<'_>> {
+        self.tree.get_by_index(i)
+    }
+
+    fn get_by_name(&self, name: &str) -> Option<Lump<'_>> {
+        self.tree
+            .index_of(name)
+            .and_then(|i| self.tree.get_by_index(i))
+    }
+
+    fn get_all_by_name(&self, name: &str) -> Vec<Lump<'_>> {
+        self.tree.get_all_by_name(name)
+    }
+}
+
+/// Unpacks lumps of the container into a directory readable by [`Directory`]:
+/// namespace lumps go to `sprites/`, `flats/`, `patches/` and `colormaps/`,
+/// levels to `maps/<marker>.wad` and the rest to the root as `<name>.lmp`.
+/// Directory order along with namespace markers as they were named (e.g. `FF_START`)
+/// is kept in `lumps.txt`, so packing gives the same directory back.
+/// A duplicate lump overwrites the previous one and takes its place in the order,
+/// so the effective one stays.
+pub fn unpack<C, P>(container: &C, path: P) -> io::Result<()>
+where
+    C: Container + ?Sized,
+    P: AsRef<Path>,
+{
+    let root = path.as_ref();
+    let lumps: Vec<_> = (0..container.len())
+        .filter_map(|i| container.get_by_index(i))
+        .collect();
+    let namespaces = Namespace::classify(lumps.iter().copied());
+
+    let write = |dir: &str, file: String, data: &[u8]| {
+        let dir = root.join(dir);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(file), data)
+    };
+    let mut order = Vec::new();
+    let mut i = 0;
+    while i < lumps.len() {
+        let lump = lumps[i];
+        match namespaces[i] {
+            Some(namespace) => {
+                if namespace.is_start(lump.name)
+                    || namespace.is_end(lump.name)
+                    || namespace.is_submarker(lump.name)
+                {
+                    order.push(lump.name.to_owned());
+                } else {
+                    // Readers decide which lumps make resources, every one is kept for packing
+                    let dir = namespace_dir(namespace);
+                    let file = file_name(lump.name, LUMP_EXTENSION)?;
+                    write(dir, file.clone(), lump.data)?;
+                    order.push(format!("{}/{}", dir, file));
+                }
+                i += 1;
+            }
+            None => match LevelLumps::at(&lumps[i..]) {
+                Some(level) => {
+                    let level = level.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    let len = level.lump_count();
+                    let mut wad = ArchiveBuilder::new(Type::PWAD);
+                    lumps[i..i + len]
+                        .iter()
+                        .for_each(|lump| wad.add_lump(lump.name, lump.data));
+                    let file = file_name(lump.name, "wad")?;
+                    write(MAPS_DIR, file.clone(), &wad.to_bytes()?)?;
+                    order.push(format!("{}/{}", MAPS_DIR, file));
+                    i += len;
+                }
+                None => {
+                    let file = file_name(lump.name, LUMP_EXTENSION)?;
+                    write("", file.clone(), lump.data)?;
+                    order.push(file);
+                    i += 1;
+                }
+            },
+        }
+    }
+
+    // Only the last of duplicate files is listed, the one that was written
+    let mut seen = HashSet::new();
+    let mut manifest: Vec<_> = order
+        .iter()
+        .rev()
+        .filter(|line| !line.contains('.') || seen.insert(line.as_str()))
+        .map(String::as_str)
+        .collect();
+    manifest.reverse();
+    let mut manifest = manifest.join("\n");
+    manifest.push('\n');
+    write("", MANIFEST.to_owned(), manifest.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unpack, Directory};
+    use crate::wad::{
+        container::Container,
+        namespace::Namespace,
+        parser::file::{Archive, ArchiveBuilder, Type},
+    };
+
+    #[test]
+    fn refuse_hostile_names() {
+        let dir = std::env::temp_dir().join(format!("room-hostile-{}", std::process::id()));
+        for &name in &["../../x", "/tmp/x", ".x", "A\tB", "C:X"] {
+            let wad = ArchiveBuilder::new(Type::PWAD)
+                .lump(name, vec![1; 4])
+                .to_bytes()
+                .expect("Error writing wad");
+            let archive = Archive::parse(&wad).expect("Wad file parser error");
+            let error = unpack(&archive, &dir).expect_err("Hostile name unpacked");
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+        // Level marker names the map file
+        let wad = ArchiveBuilder::new(Type::PWAD)
+            .marker("../MAP01")
+            .lump("THINGS", vec![2; 10])
+            .lump("LINEDEFS", vec![3; 14])
+            .to_bytes()
+            .expect("Error writing wad");
+        let archive = Archive::parse(&wad).expect("Wad file parser error");
+        assert!(unpack(&archive, &dir).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(!std::env::temp_dir().join("x.lmp").exists());
+    }
+
+    #[test]
+    fn unpack_and_pack_back() {
+        let wad = ArchiveBuilder::new(Type::PWAD)
+            .lump("PLAYPAL", vec![1; 768])
+            .marker("E1M1")
+            .lump("THINGS", vec![2; 10])
+            .lump("LINEDEFS", vec![3; 14])
+            .lump("DEMO1", vec![4; 4])
+            .marker("S_START")
+            .lump("VILE\\1", vec![5; 16])
+            .lump("TNT1A0", Vec::new())
+            .marker("S_END")
+            .marker("FF_START")
+            .lump("NUKAGE1", vec![6; 4096])
+            .marker("FF_END")
+            .to_bytes()
+            .expect("Error writing wad");
+        let archive = Archive::parse(&wad).expect("Wad file parser error");
+
+        let dir = std::env::temp_dir().join(format!("room-unpack-{}", std::process::id()));
+        unpack(&archive, &dir).expect("Error unpacking wad");
+        assert!(dir.join("sprites/VILE^1.lmp").is_file());
+        assert!(dir.join("maps/E1M1.wad").is_file());
+
+        let directory = Directory::open(&dir).expect("Error reading directory");
+        let names = |directory: &Directory| {
+            directory
+                .iter()
+                .map(|lump| lump.name.to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&directory),
+            archive
+                .iter()
+                .map(|lump| lump.name.to_owned())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(directory.path(0), Some("PLAYPAL.lmp"));
+        assert_eq!(directory.path(1), Some("maps/E1M1.wad"));
+        assert_eq!(directory.path(5), None);
+
+        // Files added later go to the end of their namespace, removed ones are left out
+        std::fs::write(dir.join("flats/SLIME01.lmp"), vec![7; 4096]).expect("Error writing file");
+        std::fs::remove_file(dir.join("DEMO1.lmp")).expect("Error removing file");
+        let edited = Directory::open(&dir).expect("Error reading directory");
+        assert_eq!(
+            names(&edited),
+            [
+                "PLAYPAL", "E1M1", "THINGS", "LINEDEFS", "S_START", "VILE\\1", "TNT1A0", "S_END",
+                "FF_START", "NUKAGE1", "SLIME01", "FF_END"
+            ]
+        );
+
+        // Without the manifest lumps are laid out by directories
+        std::fs::remove_file(dir.join("lumps.txt")).expect("Error removing file");
+        let unordered = Directory::open(&dir).expect("Error reading directory");
+        std::fs::remove_dir_all(&dir).expect("Error removing directory");
+        assert_eq!(
+            names(&unordered),
+            [
+                "PLAYPAL", "S_START", "TNT1A0", "VILE\\1", "S_END", "F_START", "NUKAGE1",
+                "SLIME01", "F_END", "E1M1", "THINGS", "LINEDEFS"
+            ]
+        );
+
+        let mut packed = Vec::new();
+        directory
+            .write_to(&mut packed, Type::PWAD)
+            .expect("Error writing wad");
+        assert_eq!(packed, wad);
+        let packed = Archive::parse(&packed).expect("Wad file parser error");
+        assert_eq!(
+            packed.get_by_name("LINEDEFS"),
+            archive.get_by_name("LINEDEFS")
+        );
+        assert_eq!(
+            packed.namespace(Namespace::Flats).get_by_name("NUKAGE1"),
+            archive.namespace(Namespace::Flats).get_by_name("NUKAGE1")
+        );
+    }
+}
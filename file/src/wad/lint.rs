@@ -0,0 +1,538 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    error::Result,
+    wad::{
+        namespace::Namespace,
+        parser::{
+            level::{Level, NodeChild},
+            texture::Textures,
+        },
+        resource::ResourceSet,
+    },
+};
+
+/// Editor numbers of Doom and Doom II things, player starts included.
+pub const DOOM_THING_TYPES: [i16; 123] = [
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+    27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50,
+    51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74,
+    75, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 88, 89, 2001, 2002, 2003, 2004, 2005, 2006,
+    2007, 2008, 2010, 2011, 2012, 2013, 2014, 2015, 2018, 2019, 2022, 2023, 2024, 2025, 2026, 2028,
+    2035, 2045, 2046, 2047, 2048, 2049, 3001, 3002, 3003, 3004, 3005, 3006,
+];
+
+/// Texture name meaning no texture.
+const NO_TEXTURE: &str = "-";
+/// Player starts, the first one needed for single player and the rest for co-op.
+const PLAYER_STARTS: [i16; 4] = [1, 2, 3, 4];
+
+/// Names and types a level may refer to, each check is skipped while its set is `None`.
+/// Names are kept uppercase.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceContext {
+    pub textures: Option<HashSet<String>>,
+    pub flats: Option<HashSet<String>>,
+    /// Editor numbers of things, e.g. [`DOOM_THING_TYPES`]
+    pub thing_types: Option<HashSet<i16>>,
+    /// Expects starts of every co-op player, even in levels without any of them
+    pub coop: bool,
+}
+
+impl ResourceContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collects textures of `TEXTURE1` and `TEXTURE2` along with flats of the resources,
+    /// thing types are left unknown.
+    pub fn from_resources(resources: &ResourceSet<'_>) -> Result<Self> {
+        let mut textures = None;
+        for name in &["TEXTURE1", "TEXTURE2"] {
+            if let Some(resource) = resources.get_by_name(name) {
+                let parsed = Textures::parse(resource.lump.data).map_err(|e| e.with_lump(*name))?;
+                textures.get_or_insert_with(HashSet::new).extend(
+                    parsed
+                        .iter()
+                        .map(|texture| texture.name.to_ascii_uppercase()),
+                );
+            }
+        }
+        let flats: HashSet<_> = resources
+            .namespace(Namespace::Flats)
+            .iter()
+            .map(|flat| flat.lump.name.to_ascii_uppercase())
+            .collect();
+        Ok(Self {
+            textures,
+            flats: Some(flats).filter(|flats| !flats.is_empty()),
+            thing_types: None,
+            coop: false,
+        })
+    }
+
+    pub fn has_texture(&self, name: &str) -> bool {
+        has_name(&self.textures, name)
+    }
+
+    pub fn has_flat(&self, name: &str) -> bool {
+        has_name(&self.flats, name)
+    }
+
+    pub fn has_thing_type(&self, ttype: i16) -> bool {
+        self.thing_types
+            .as_ref()
+            .map_or(true, |types| types.contains(&ttype))
+    }
+}
+
+fn has_name(names: &Option<HashSet<String>>, name: &str) -> bool {
+    names
+        .as_ref()
+        .map_or(true, |names| names.contains(&name.to_ascii_uppercase()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Harmless leftover, e.g. unused vertex
+    Info,
+    /// Works, but likely not as intended or not in every port
+    Warning,
+    /// Breaks the level in vanilla and most ports
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// Index past the end of the lump it refers to
+    DanglingReference {
+        target: &'static str,
+        index: u32,
+    },
+    MissingFrontSide,
+    ZeroLengthLine,
+    /// Sector outline doesn't form closed loops at the vertex
+    UnclosedSector {
+        vertex: u32,
+    },
+    UnusedVertex,
+    /// Vertex at the same place as the earlier one
+    DuplicateVertex {
+        of: usize,
+    },
+    MissingPlayerStart {
+        player: u8,
+    },
+    UnknownThingType(i16),
+    MissingTexture(String),
+    MissingFlat(String),
+}
+
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::UnusedVertex | Self::DuplicateVertex { .. } => Severity::Info,
+            Self::ZeroLengthLine
+            | Self::UnclosedSector { .. }
+            | Self::UnknownThingType(_)
+            | Self::MissingPlayerStart { player: 2..=4 } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DanglingReference { target, index } => {
+                write!(f, "refers to missing {} entry {}", target, index)
+            }
+            Self::MissingFrontSide => f.write_str("has no front sidedef"),
+            Self::ZeroLengthLine => f.write_str("has zero length"),
+            Self::UnclosedSector { vertex } => write!(f, "isn't closed at vertex {}", vertex),
+            Self::UnusedVertex => f.write_str("isn't used by any line"),
+            Self::DuplicateVertex { of } => write!(f, "duplicates vertex {}", of),
+            Self::MissingPlayerStart { player } => write!(f, "has no player {} start", player),
+            Self::UnknownThingType(ttype) => write!(f, "has unknown type {}", ttype),
+            Self::MissingTexture(name) => write!(f, "uses missing texture {}", name),
+            Self::MissingFlat(name) => write!(f, "uses missing flat {}", name),
+        }
+    }
+}
+
+/// Problem found in a level along with the entry it's about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Lump of the entry, binary name is used for UDMF levels as well
+    pub lump: &'static str,
+    /// Index of the entry in its lump, `None` when it's about the whole level
+    pub index: Option<usize>,
+    pub problem: Problem,
+}
+
+impl Diagnostic {
+    fn new(lump: &'static str, index: Option<usize>, problem: Problem) -> Self {
+        Self {
+            severity: problem.severity(),
+            lump,
+            index,
+            problem,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(
+                f,
+                "{:?}: {} {} {}",
+                self.severity, self.lump, index, self.problem
+            ),
+            None => write!(f, "{:?}: {} {}", self.severity, self.lump, self.problem),
+        }
+    }
+}
+
+/// Collects diagnostics of a level.
+struct Lint<'l, 'a> {
+    level: &'l Level<'a>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Lint<'_, '_> {
+    fn report(&mut self, lump: &'static str, index: usize, problem: Problem) {
+        self.diagnostics
+            .push(Diagnostic::new(lump, Some(index), problem));
+    }
+
+    /// Reports `index` if it's past `len` entries of `target`, returns whether it's valid.
+    fn check_ref(
+        &mut self,
+        lump: &'static str,
+        i: usize,
+        target: &'static str,
+        len: usize,
+        index: u32,
+    ) -> bool {
+        let valid = (index as usize) < len;
+        if !valid {
+            self.report(lump, i, Problem::DanglingReference { target, index });
+        }
+        valid
+    }
+
+    fn things(&mut self, context: &ResourceContext) {
+        let level = self.level;
+        for (i, thing) in level.things.iter().enumerate() {
+            if !context.has_thing_type(thing.ttype) {
+                self.report("THINGS", i, Problem::UnknownThingType(thing.ttype));
+            }
+        }
+        // Single player levels needn't have co-op starts, but co-op ones need all of them
+        let coop = context.coop
+            || level
+                .things
+                .iter()
+                .any(|thing| PLAYER_STARTS[1..].contains(&thing.ttype));
+        let starts = if coop {
+            &PLAYER_STARTS[..]
+        } else {
+            &PLAYER_STARTS[..1]
+        };
+        for (player, &ttype) in (1..).zip(starts) {
+            if level.things.iter().all(|thing| thing.ttype != ttype) {
+                self.diagnostics.push(Diagnostic::new(
+                    "THINGS",
+                    None,
+                    Problem::MissingPlayerStart { player },
+                ));
+            }
+        }
+    }
+
+    fn linedefs(&mut self) {
+        let level = self.level;
+        let (vertices, sidedefs) = (level.vertices.len(), level.sidedefs.len());
+        let mut used = vec![false; vertices];
+        for (i, linedef) in level.linedefs.iter().enumerate() {
+            let start = self.check_ref("LINEDEFS", i, "VERTEXES", vertices, linedef.vertex_start);
+            let end = self.check_ref("LINEDEFS", i, "VERTEXES", vertices, linedef.vertex_end);
+            for (&valid, &index) in [start, end]
+                .iter()
+                .zip(&[linedef.vertex_start, linedef.vertex_end])
+            {
+                if valid {
+                    used[index as usize] = true;
+                }
+            }
+            if start && end && linedef.ends(&level.vertices).map_or(false, |(a, b)| a == b) {
+                self.report("LINEDEFS", i, Problem::ZeroLengthLine);
+            }
+            match linedef.sidedef_right {
+                Some(index) => {
+                    self.check_ref("LINEDEFS", i, "SIDEDEFS", sidedefs, index);
+                }
+                None => self.report("LINEDEFS", i, Problem::MissingFrontSide),
+            }
+            if let Some(index) = linedef.sidedef_left {
+                self.check_ref("LINEDEFS", i, "SIDEDEFS", sidedefs, index);
+            }
+        }
+
+        // Split vertices of nodes are used by segs only
+        for seg in &level.segments {
+            for &index in &[seg.vertex_start, seg.vertex_end] {
+                if let Some(used) = used.get_mut(index as usize) {
+                    *used = true;
+                }
+            }
+        }
+        let mut first_at = HashMap::new();
+        for (i, &vertex) in level.vertices.iter().enumerate() {
+            if !used[i] {
+                self.report("VERTEXES", i, Problem::UnusedVertex);
+            }
+            let &mut first = first_at.entry(vertex).or_insert(i);
+            if first != i {
+                self.report("VERTEXES", i, Problem::DuplicateVertex { of: first });
+            }
+        }
+    }
+
+    fn sidedefs(&mut self, context: &ResourceContext) {
+        let level = self.level;
+        for (i, sidedef) in level.sidedefs.iter().enumerate() {
+            let sector = u32::from(sidedef.sector_ref as u16);
+            self.check_ref("SIDEDEFS", i, "SECTORS", level.sectors.len(), sector);
+            let textures = [
+                sidedef.upper_texture,
+                sidedef.lower_texture,
+                sidedef.mid_texture,
+            ];
+            for &name in &textures {
+                if !name.is_empty() && name != NO_TEXTURE && !context.has_texture(name) {
+                    self.report("SIDEDEFS", i, Problem::MissingTexture(name.to_owned()));
+                }
+            }
+        }
+    }
+
+    /// Every vertex of a sector outline must be left as many times as it's entered,
+    /// following the lines with the sector on their right.
+    fn sectors(&mut self, context: &ResourceContext) {
+        let level = self.level;
+        let mut balance = vec![HashMap::new(); level.sectors.len()];
+        for linedef in &level.linedefs {
+            let sides = [
+                (
+                    linedef.sidedef_right,
+                    linedef.vertex_start,
+                    linedef.vertex_end,
+                ),
+                (
+                    linedef.sidedef_left,
+                    linedef.vertex_end,
+                    linedef.vertex_start,
+                ),
+            ];
+            for &(sidedef, from, to) in &sides {
+                let sector = sidedef
+                    .and_then(|index| level.sidedefs.get(index as usize))
+                    .and_then(|sidedef| balance.get_mut(usize::from(sidedef.sector_ref as u16)));
+                if let Some(sector) = sector {
+                    *sector.entry(from).or_insert(0) -= 1;
+                    *sector.entry(to).or_insert(0) += 1;
+                }
+            }
+        }
+        for (i, sector) in level.sectors.iter().enumerate() {
+            let open = balance[i]
+                .iter()
+                .filter(|&(_, &count)| count != 0)
+                .map(|(&vertex, _)| vertex)
+                .min();
+            if let Some(vertex) = open {
+                self.report("SECTORS", i, Problem::UnclosedSector { vertex });
+            }
+            for &name in &[sector.floor_pic, sector.ceiling_pic] {
+                if !name.is_empty() && name != NO_TEXTURE && !context.has_flat(name) {
+                    self.report("SECTORS", i, Problem::MissingFlat(name.to_owned()));
+                }
+            }
+        }
+    }
+
+    fn nodes(&mut self) {
+        let level = self.level;
+        let (vertices, linedefs) = (level.vertices.len(), level.linedefs.len());
+        for (i, seg) in level.segments.iter().enumerate() {
+            self.check_ref("SEGS", i, "VERTEXES", vertices, seg.vertex_start);
+            self.check_ref("SEGS", i, "VERTEXES", vertices, seg.vertex_end);
+            if seg.line_num != u32::MAX {
+                self.check_ref("SEGS", i, "LINEDEFS", linedefs, seg.line_num);
+            }
+        }
+        for (i, subsector) in level.subsectors.iter().enumerate() {
+            let end = subsector.start_seg.saturating_add(subsector.numsegs);
+            if subsector.numsegs > 0 {
+                self.check_ref("SSECTORS", i, "SEGS", level.segments.len(), end - 1);
+            }
+        }
+        for (i, node) in level.nodes.iter().enumerate() {
+            for child in &[node.front(), node.back()] {
+                match *child {
+                    NodeChild::Node(index) => {
+                        self.check_ref("NODES", i, "NODES", level.nodes.len(), index)
+                    }
+                    NodeChild::Subsector(index) => {
+                        self.check_ref("NODES", i, "SSECTORS", level.subsectors.len(), index)
+                    }
+                };
+            }
+        }
+    }
+}
+
+impl Level<'_> {
+    /// Checks references between entries of the level and to the resources it uses,
+    /// geometry of sectors and presence of player starts. Nodes are only checked for
+    /// dangling references, see [`bsp::validate`](crate::wad::bsp::validate) for the rest.
+    pub fn validate(&self, context: &ResourceContext) -> Vec<Diagnostic> {
+        let mut lint = Lint {
+            level: self,
+            diagnostics: Vec::new(),
+        };
+        lint.things(context);
+        lint.linedefs();
+        lint.sidedefs(context);
+        lint.sectors(context);
+        lint.nodes();
+        lint.diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Problem, ResourceContext, Severity, DOOM_THING_TYPES};
+    use crate::wad::parser::{level::Level, udmf::TextMap};
+
+    const TEXTMAP: &[u8] = br#"
+        namespace = "doom";
+        thing { x = 32; y = 32; type = 1; }
+        thing { x = 48; y = 32; type = 12345; }
+        vertex { x = 0; y = 0; }
+        vertex { x = 64; y = 0; }
+        vertex { x = 64; y = 64; }
+        vertex { x = 0; y = 64; }
+        vertex { x = 0; y = 64; }
+        linedef { v1 = 0; v2 = 1; sidefront = 0; }
+        linedef { v1 = 1; v2 = 2; sidefront = 1; }
+        linedef { v1 = 2; v2 = 3; sidefront = 0; }
+        linedef { v1 = 3; v2 = 3; sidefront = 5; }
+        sidedef { sector = 0; texturemiddle = "STARTAN3"; }
+        sidedef { sector = 0; texturemiddle = "MISSING"; }
+        sector { texturefloor = "FLOOR4_8"; textureceiling = "CEIL3_5"; }
+        sector { textureceiling = "FLOOR4_8"; }
+    "#;
+
+    #[test]
+    fn report_level_problems() {
+        let textmap = TextMap::parse(TEXTMAP).expect("Error parsing TEXTMAP");
+        let mut level = Level::from_textmap("MAP01", textmap);
+        let context = ResourceContext {
+            textures: Some(["STARTAN3"].iter().map(|&name| name.to_owned()).collect()),
+            flats: Some(["FLOOR4_8"].iter().map(|&name| name.to_owned()).collect()),
+            thing_types: Some(DOOM_THING_TYPES.iter().copied().collect()),
+            coop: false,
+        };
+        let diagnostics = level.validate(&context);
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.lump, d.index, d.problem.clone()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    Severity::Warning,
+                    "THINGS",
+                    Some(1),
+                    Problem::UnknownThingType(12345)
+                ),
+                (
+                    Severity::Warning,
+                    "LINEDEFS",
+                    Some(3),
+                    Problem::ZeroLengthLine
+                ),
+                (
+                    Severity::Error,
+                    "LINEDEFS",
+                    Some(3),
+                    Problem::DanglingReference {
+                        target: "SIDEDEFS",
+                        index: 5
+                    }
+                ),
+                (Severity::Info, "VERTEXES", Some(4), Problem::UnusedVertex),
+                (
+                    Severity::Info,
+                    "VERTEXES",
+                    Some(4),
+                    Problem::DuplicateVertex { of: 3 }
+                ),
+                (
+                    Severity::Error,
+                    "SIDEDEFS",
+                    Some(1),
+                    Problem::MissingTexture("MISSING".to_owned())
+                ),
+                (
+                    Severity::Warning,
+                    "SECTORS",
+                    Some(0),
+                    Problem::UnclosedSector { vertex: 0 }
+                ),
+                (
+                    Severity::Error,
+                    "SECTORS",
+                    Some(0),
+                    Problem::MissingFlat("CEIL3_5".to_owned())
+                ),
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "Warning: THINGS 1 has unknown type 12345"
+        );
+        // Nothing is known about resources of an empty context
+        let unchecked = level.validate(&ResourceContext::new());
+        assert!(unchecked.iter().all(|d| !matches!(
+            d.problem,
+            Problem::MissingTexture(_) | Problem::UnknownThingType(_)
+        )));
+
+        // Co-op starts are expected when asked for or when the level has any
+        let coop = ResourceContext {
+            coop: true,
+            ..ResourceContext::new()
+        };
+        let players = |diagnostics: Vec<Diagnostic>| {
+            diagnostics
+                .into_iter()
+                .filter_map(|d| match d.problem {
+                    Problem::MissingPlayerStart { player } => Some(player),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(players(level.validate(&coop)), [2, 3, 4]);
+        level.things[0].ttype = 3;
+        assert_eq!(players(level.validate(&context)), [1, 2, 4]);
+    }
+}
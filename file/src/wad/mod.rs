@@ -1,2 +1,11 @@
+pub mod bsp;
+pub mod composite;
+pub mod container;
+pub mod directory;
+pub mod lint;
+pub mod namespace;
 pub mod parser;
+#[cfg(feature = "pk3")]
+pub mod pk3;
+pub mod resource;
 pub mod utils;
@@ -0,0 +1,256 @@
+use crate::wad::parser::file::Lump;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    /// `S_START`/`S_END` or doubled `SS_START`/`SS_END`
+    Sprites,
+    /// `F_START`/`F_END` or doubled `FF_START`/`FF_END`, with `F1_`-`F3_` sub-markers inside
+    Flats,
+    /// `P_START`/`P_END` or doubled `PP_START`/`PP_END`, with `P1_`-`P3_` sub-markers inside
+    Patches,
+    /// Boom's `C_START`/`C_END`
+    Colormaps,
+}
+
+impl Namespace {
+    pub const ALL: [Self; 4] = [Self::Sprites, Self::Flats, Self::Patches, Self::Colormaps];
+
+    const fn prefix(self) -> u8 {
+        match self {
+            Self::Sprites => b'S',
+            Self::Flats => b'F',
+            Self::Patches => b'P',
+            Self::Colormaps => b'C',
+        }
+    }
+
+    pub const fn start_marker(self) -> &'static str {
+        match self {
+            Self::Sprites => "S_START",
+            Self::Flats => "F_START",
+            Self::Patches => "P_START",
+            Self::Colormaps => "C_START",
+        }
+    }
+
+    pub const fn end_marker(self) -> &'static str {
+        match self {
+            Self::Sprites => "S_END",
+            Self::Flats => "F_END",
+            Self::Patches => "P_END",
+            Self::Colormaps => "C_END",
+        }
+    }
+
+    /// Checks for `X_<suffix>` or its doubled `XX_<suffix>` form, ignoring case as Boom does.
+    fn is_marker(self, name: &str, suffix: &str) -> bool {
+        let prefix = self.prefix();
+        let name = match name.as_bytes() {
+            [a, b, rest @ ..] if a.eq_ignore_ascii_case(&prefix) && a.eq_ignore_ascii_case(b) => {
+                rest
+            }
+            [a, rest @ ..] if a.eq_ignore_ascii_case(&prefix) => rest,
+            _ => return false,
+        };
+        match name {
+            [b'_', rest @ ..] => rest.eq_ignore_ascii_case(suffix.as_bytes()),
+            _ => false,
+        }
+    }
+
+    pub fn is_start(self, name: &str) -> bool {
+        self.is_marker(name, "START")
+    }
+
+    pub fn is_end(self, name: &str) -> bool {
+        self.is_marker(name, "END")
+    }
+
+    /// Checks for numbered sub-markers like `F1_START` or `P3_END`.
+    pub fn is_submarker(self, name: &str) -> bool {
+        match self {
+            Self::Flats | Self::Patches => match name.as_bytes() {
+                [a, b'1'..=b'3', b'_', rest @ ..] => {
+                    a.eq_ignore_ascii_case(&self.prefix())
+                        && (rest.eq_ignore_ascii_case(b"START")
+                            || rest.eq_ignore_ascii_case(b"END"))
+                }
+                _ => false,
+            },
+            Self::Sprites | Self::Colormaps => false,
+        }
+    }
+
+    /// Checks whether lump inside the namespace is a resource rather than a sub-marker,
+    /// every other lump counts like in vanilla, empty ones included.
+    pub fn accepts(self, lump: &Lump<'_>) -> bool {
+        !self.is_submarker(lump.name)
+    }
+
+    /// Finds the namespace of every lump in directory order, `None` stands for the global one.
+    /// Works the way Boom coalesces namespaces: every start marker (single or doubled)
+    /// opens the namespace and the first end marker closes it, so both nested markers
+    /// and PWAD-style halves like `FF_START`...`F_END` work.
+    /// Namespace left open lasts until the end of directory.
+    pub fn classify<'a, I>(lumps: I) -> Vec<Option<Self>>
+    where
+        I: IntoIterator<Item = Lump<'a>>,
+    {
+        let mut current = None;
+        lumps
+            .into_iter()
+            .map(|lump| {
+                if let Some(&namespace) = Self::ALL.iter().find(|ns| ns.is_start(lump.name)) {
+                    current = Some(namespace);
+                    current
+                } else if let Some(&namespace) = Self::ALL.iter().find(|ns| ns.is_end(lump.name)) {
+                    if current == Some(namespace) {
+                        current = None;
+                    }
+                    Some(namespace)
+                } else {
+                    current
+                }
+            })
+            .collect()
+    }
+
+    /// Collects lumps of the namespace, skipping its markers.
+    pub fn collect<'a, I>(self, lumps: I) -> NamespaceLumps<'a>
+    where
+        I: IntoIterator<Item = Lump<'a>>,
+    {
+        let lumps: Vec<_> = lumps.into_iter().collect();
+        let lumps = Self::classify(lumps.iter().copied())
+            .into_iter()
+            .zip(lumps)
+            .filter(|&(namespace, lump)| {
+                namespace == Some(self)
+                    && !self.is_start(lump.name)
+                    && !self.is_end(lump.name)
+                    && self.accepts(&lump)
+            })
+            .map(|(_, lump)| lump)
+            .collect();
+        NamespaceLumps { lumps }
+    }
+}
+
+/// Lumps belonging to a namespace in directory order.
+pub struct NamespaceLumps<'a> {
+    lumps: Vec<Lump<'a>>,
+}
+
+impl<'a> NamespaceLumps<'a> {
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Lump<'a>> + '_ {
+        self.lumps.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lumps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lumps.is_empty()
+    }
+
+    /// Gets the last lump with such name, so PWAD resources override previous ones.
+    pub fn get_by_name<S: AsRef<str>>(&self, s: S) -> Option<Lump<'a>> {
+        let name = s.as_ref();
+        self.iter().rev().find(|lump| lump.name == name)
+    }
+}
+
+impl<'a> IntoIterator for NamespaceLumps<'a> {
+    type Item = Lump<'a>;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.lumps.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Namespace;
+    use crate::wad::parser::file::Lump;
+
+    fn lumps(names: &[&'static str]) -> Vec<Lump<'static>> {
+        names
+            .iter()
+            .map(|&name| Lump {
+                name,
+                data: if name.ends_with("_START") || name.ends_with("_END") {
+                    &[]
+                } else {
+                    &[0; 16]
+                },
+            })
+            .collect()
+    }
+
+    fn names(namespace: Namespace, all: &[&'static str]) -> Vec<&'static str> {
+        namespace
+            .collect(lumps(all))
+            .iter()
+            .map(|lump| lump.name)
+            .collect()
+    }
+
+    #[test]
+    fn match_markers() {
+        assert!(Namespace::Sprites.is_start("S_START"));
+        assert!(Namespace::Sprites.is_start("SS_START"));
+        assert!(Namespace::Sprites.is_start("s_start"));
+        assert!(!Namespace::Sprites.is_start("SSS_START"));
+        assert!(!Namespace::Sprites.is_start("F_START"));
+        assert!(Namespace::Flats.is_end("FF_END"));
+        assert!(Namespace::Flats.is_submarker("F2_START"));
+        assert!(!Namespace::Flats.is_submarker("F4_START"));
+        assert!(!Namespace::Flats.is_start("F1_START"));
+    }
+
+    #[test]
+    fn collect_nested_and_partial_namespaces() {
+        let directory = [
+            "PLAYPAL", "F_START", "F1_START", "FLOOR0_1", "F1_END", "FF_START", "NUKAGE1",
+            "FF_END", "F_END", "OUTSIDE1", "FF_START", "SLIME01", "F_END", "S_START", "TROOA1",
+            "S_END", "FF_START", "RROCK01",
+        ];
+        assert_eq!(
+            names(Namespace::Flats, &directory),
+            ["FLOOR0_1", "NUKAGE1", "SLIME01", "RROCK01"]
+        );
+        assert_eq!(names(Namespace::Sprites, &directory), ["TROOA1"]);
+        assert!(names(Namespace::Patches, &directory).is_empty());
+    }
+
+    #[test]
+    fn last_lump_wins() {
+        let mut directory = lumps(&["S_START", "TROOA1", "S_END", "SS_START"]);
+        directory.push(Lump {
+            name: "TROOA1",
+            data: b"OVERRIDDEN",
+        });
+        directory.extend(lumps(&["SS_END"]));
+
+        let sprites = Namespace::Sprites.collect(directory.clone());
+        assert_eq!(sprites.len(), 2);
+        assert_eq!(
+            sprites.get_by_name("TROOA1").map(|lump| lump.data),
+            Some(&b"OVERRIDDEN"[..])
+        );
+
+        // Tiny and empty sprites are resources too
+        directory.insert(
+            1,
+            Lump {
+                name: "TNT1A0",
+                data: &[],
+            },
+        );
+        let sprites = Namespace::Sprites.collect(directory);
+        assert_eq!(sprites.len(), 3);
+        assert!(sprites.get_by_name("TNT1A0").is_some());
+    }
+}
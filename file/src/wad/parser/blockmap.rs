@@ -0,0 +1,357 @@
+use super::{
+    level::{Linedef, Vertex},
+    types::{run, seek, Input, OnlyResult, ParseError, ParseResult},
+};
+use crate::error::ErrorKind;
+use nom::{
+    multi::count,
+    number::complete::{le_i16, le_u16},
+    sequence::tuple,
+};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+/// Size of a blockmap cell in map units.
+pub const BLOCK_SIZE: i32 = 128;
+const LIST_START: u16 = 0;
+const LIST_END: u16 = 0xFFFF;
+
+/// Grid of linedef lists used for collision detection, the cells going row by row
+/// from the bottom left corner at origin.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Blockmap {
+    pub x_origin: i16,
+    pub y_origin: i16,
+    pub columns: u16,
+    pub rows: u16,
+    /// Linedefs of every cell without the leading zero
+    pub cells: Vec<Vec<u16>>,
+    /// Whether every list starts with zero. Vanilla engine treats it as a linedef,
+    /// so linedef 0 is checked in each cell (which is the quirk some demos depend on).
+    pub leading_zero: bool,
+}
+
+type Point = (f64, f64);
+
+fn point((x, y): Vertex) -> Point {
+    (x.into(), y.into())
+}
+
+/// Grid of [`BLOCK_SIZE`] cells covering the points, with lists of segments crossing them.
+struct Grid {
+    x_origin: i32,
+    y_origin: i32,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    fn new<I: IntoIterator<Item = Vertex>>(points: I) -> Self {
+        let bounds = points.into_iter().fold(None, |bounds, (x, y)| {
+            let (x, y) = (i32::from(x), i32::from(y));
+            Some(match bounds {
+                None => (x, y, x, y),
+                Some((left, bottom, right, top)) => {
+                    (x.min(left), y.min(bottom), x.max(right), y.max(top))
+                }
+            })
+        });
+        let (x_origin, y_origin, columns, rows) = match bounds {
+            Some((left, bottom, right, top)) => (
+                left,
+                bottom,
+                ((right - left) / BLOCK_SIZE + 1) as usize,
+                ((top - bottom) / BLOCK_SIZE + 1) as usize,
+            ),
+            None => (0, 0, 0, 0),
+        };
+        Self {
+            x_origin,
+            y_origin,
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+        }
+    }
+
+    /// Finds cells the segment passes through, column by column.
+    fn cells_along(&self, (x1, y1): Point, (x2, y2): Point) -> Vec<usize> {
+        let ((x1, y1), (x2, y2)) = if x1 <= x2 {
+            ((x1, y1), (x2, y2))
+        } else {
+            ((x2, y2), (x1, y1))
+        };
+        let size = f64::from(BLOCK_SIZE);
+        let (ox, oy) = (f64::from(self.x_origin), f64::from(self.y_origin));
+        let clamp = |n: f64, max: usize| (n.max(0.0) as usize).min(max.saturating_sub(1));
+        let y_at = |x: f64| {
+            if x2 == x1 {
+                y1
+            } else {
+                y1 + (y2 - y1) * (x - x1) / (x2 - x1)
+            }
+        };
+
+        let mut cells = Vec::new();
+        if self.columns == 0 || self.rows == 0 {
+            return cells;
+        }
+        let first = clamp(((x1 - ox) / size).floor(), self.columns);
+        let last = clamp(((x2 - ox) / size).floor(), self.columns);
+        for column in first..=last {
+            let left = x1.max(ox + column as f64 * size);
+            let right = x2.min(ox + (column + 1) as f64 * size);
+            let (ya, yb) = if x2 == x1 {
+                (y1, y2)
+            } else {
+                (y_at(left), y_at(right))
+            };
+            let bottom = clamp(((ya.min(yb) - oy) / size).floor(), self.rows);
+            let top = clamp(((ya.max(yb) - oy) / size).floor(), self.rows);
+            cells.extend((bottom..=top).map(|row| row * self.columns + column));
+        }
+        cells
+    }
+
+    fn add(&mut self, index: usize, a: Point, b: Point) {
+        for cell in self.cells_along(a, b) {
+            self.cells[cell].push(index);
+        }
+    }
+}
+
+/// Parses list of linedefs ending with `0xFFFF`.
+fn parse_list(mut i: Input<'_>) -> ParseResult<'_, Vec<u16>> {
+    let mut lines = Vec::new();
+    loop {
+        let (rest, line) = le_u16(i)?;
+        i = rest;
+        if line == LIST_END {
+            return Ok((i, lines));
+        }
+        lines.push(line);
+    }
+}
+
+impl Blockmap {
+    fn parse_with_lines(base: Input<'_>, linedefs: usize) -> ParseResult<'_, Self> {
+        let (i, (x_origin, y_origin, columns, rows)) =
+            tuple((le_i16, le_i16, le_u16, le_u16))(base)?;
+        let cell_count = usize::from(columns) * usize::from(rows);
+        // Checked before counting, so a huge grid of a broken lump isn't preallocated
+        if cell_count * 2 > i.len() {
+            return ParseError::fail(i, ErrorKind::Truncated);
+        }
+        let (i, offsets) = count(le_u16, cell_count)(i)?;
+
+        let mut cells = Vec::with_capacity(cell_count);
+        let mut leading_zero = true;
+        for (n, &offset) in offsets.iter().enumerate() {
+            let at = &base[8 + n * 2..];
+            let (_, list) = seek(base, usize::from(offset) * 2, at)?;
+            let (_, lines) = parse_list(list)?;
+            if lines.iter().any(|&line| usize::from(line) >= linedefs) {
+                return ParseError::fail(list, ErrorKind::OutOfBounds);
+            }
+            leading_zero &= lines.first() == Some(&LIST_START);
+            cells.push(lines);
+        }
+        // Zero is a real linedef unless every list starts with it
+        if leading_zero {
+            cells.iter_mut().for_each(|lines| {
+                lines.remove(0);
+            });
+        }
+        Ok((
+            i,
+            Self {
+                x_origin,
+                y_origin,
+                columns,
+                rows,
+                cells,
+                leading_zero,
+            },
+        ))
+    }
+
+    /// Parses blockmap of a level with `linedefs` linedefs, lines out of them are
+    /// reported as [`ErrorKind::OutOfBounds`].
+    pub fn parse(i: &[u8], linedefs: usize) -> OnlyResult<Self> {
+        run(|i| Self::parse_with_lines(i, linedefs), i)
+    }
+
+    /// Builds blockmap with origin at the bottom left vertex, lines are listed in every cell
+    /// they pass through. Linedefs past 65535th can't be referenced by the format and are left out.
+    pub fn build(vertices: &[Vertex], linedefs: &[Linedef]) -> Self {
+        let lines: Vec<_> = linedefs
+            .iter()
+            .take(usize::from(LIST_END))
+            .map(|linedef| linedef.ends(vertices))
+            .collect();
+        let mut grid = Grid::new(lines.iter().flatten().flat_map(|&(a, b)| vec![a, b]));
+        for (i, &(a, b)) in lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, l)| Some((i, l.as_ref()?)))
+        {
+            grid.add(i, point(a), point(b));
+        }
+        let clamp = |n: i32| n.max(i16::MIN.into()).min(i16::MAX.into()) as i16;
+        Self {
+            x_origin: clamp(grid.x_origin),
+            y_origin: clamp(grid.y_origin),
+            columns: u16::try_from(grid.columns).unwrap_or(u16::MAX),
+            rows: u16::try_from(grid.rows).unwrap_or(u16::MAX),
+            cells: grid
+                .cells
+                .into_iter()
+                .map(|cell| cell.into_iter().map(|line| line as u16).collect())
+                .collect(),
+            leading_zero: true,
+        }
+    }
+
+    /// Writes blockmap lump, `compress` makes cells with the same lines share one list.
+    /// Fails when lists don't fit into 16-bit offsets.
+    pub fn write_to<W: Write>(&self, mut w: W, compress: bool) -> io::Result<()> {
+        let header_size = 4 + self.cells.len();
+        let mut offsets = Vec::with_capacity(self.cells.len());
+        let mut lists: Vec<u16> = Vec::new();
+        let mut shared: HashMap<&[u16], usize> = HashMap::new();
+        for cell in &self.cells {
+            let offset = match shared.get(cell.as_slice()) {
+                Some(&offset) => offset,
+                None => {
+                    let offset = header_size + lists.len();
+                    if self.leading_zero {
+                        lists.push(LIST_START);
+                    }
+                    lists.extend(cell);
+                    lists.push(LIST_END);
+                    if compress {
+                        shared.insert(cell, offset);
+                    }
+                    offset
+                }
+            };
+            offsets.push(u16::try_from(offset).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Blockmap is too large")
+            })?);
+        }
+
+        let header = [
+            self.x_origin as u16,
+            self.y_origin as u16,
+            self.columns,
+            self.rows,
+        ];
+        for word in header.iter().chain(&offsets).chain(&lists) {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        w.flush()
+    }
+
+    /// Gets linedefs of the cell, leading zero excluded.
+    pub fn cell(&self, column: usize, row: usize) -> Option<&[u16]> {
+        if column < usize::from(self.columns) && row < usize::from(self.rows) {
+            self.cells
+                .get(row * usize::from(self.columns) + column)
+                .map(Vec::as_slice)
+        } else {
+            None
+        }
+    }
+
+    /// Gets column and row of the cell containing map point.
+    pub fn cell_of(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let column = (x - i32::from(self.x_origin)).div_euclid(BLOCK_SIZE);
+        let row = (y - i32::from(self.y_origin)).div_euclid(BLOCK_SIZE);
+        let column = usize::try_from(column).ok()?;
+        let row = usize::try_from(row).ok()?;
+        if column < usize::from(self.columns) && row < usize::from(self.rows) {
+            Some((column, row))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Blockmap;
+    use crate::error::ErrorKind;
+
+    fn words(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parse_cells() {
+        // 2x1 grid at (-64, 0), both cells share the list of linedefs 0 and 1
+        let lump = words(&[0xFFC0, 0, 2, 1, 6, 6, 0, 0, 1, 0xFFFF]);
+        let blockmap = Blockmap::parse(&lump, 2).expect("Error parsing blockmap");
+        assert_eq!((blockmap.x_origin, blockmap.columns), (-64, 2));
+        assert!(blockmap.leading_zero);
+        assert_eq!(blockmap.cell(1, 0), Some(&[0, 1][..]));
+        assert_eq!(blockmap.cell(2, 0), None);
+        assert_eq!(blockmap.cell_of(70, 127), Some((1, 0)));
+        assert_eq!(blockmap.cell_of(-65, 0), None);
+
+        let error = Blockmap::parse(&lump, 1).err().and_then(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::OutOfBounds));
+        let error = Blockmap::parse(&lump[..18], 2).err().and_then(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::Truncated));
+
+        let mut compressed = Vec::new();
+        blockmap
+            .write_to(&mut compressed, true)
+            .expect("Error writing blockmap");
+        assert_eq!(compressed, words(&[0xFFC0, 0, 2, 1, 6, 6, 0, 0, 1, 0xFFFF]));
+
+        // Second list lacks the leading zero, so zero in the first one is a linedef
+        let lump = words(&[0, 0, 2, 1, 6, 9, 0, 1, 0xFFFF, 1, 0xFFFF]);
+        let blockmap = Blockmap::parse(&lump, 2).expect("Error parsing blockmap");
+        assert!(!blockmap.leading_zero);
+        assert_eq!(blockmap.cell(0, 0), Some(&[0, 1][..]));
+        assert_eq!(blockmap.cell(1, 0), Some(&[1][..]));
+    }
+
+    #[test]
+    fn build_from_lines() {
+        use crate::wad::parser::level::Linedef;
+
+        let line = |vertex_start, vertex_end| Linedef {
+            vertex_start,
+            vertex_end,
+            flags: 0,
+            function: 0,
+            tag: 0,
+            args: [0; 5],
+            sidedef_right: Some(0),
+            sidedef_left: None,
+        };
+        // Diagonal through 3x2 grid and a short line in the top right cell
+        let vertices = [(0, 0), (300, 200), (260, 150), (280, 150)];
+        let blockmap = Blockmap::build(&vertices, &[line(0, 1), line(2, 3)]);
+        assert_eq!((blockmap.columns, blockmap.rows), (3, 2));
+        let cells: Vec<_> = (0..2)
+            .flat_map(|row| (0..3).map(move |column| (column, row)))
+            .map(|(column, row)| blockmap.cell(column, row).map(<[u16]>::len))
+            .collect();
+        assert_eq!(
+            cells,
+            [Some(1), Some(1), Some(0), Some(0), Some(1), Some(2)]
+        );
+
+        let mut lump = Vec::new();
+        blockmap
+            .write_to(&mut lump, false)
+            .expect("Error writing blockmap");
+        assert_eq!(Blockmap::parse(&lump, 2).ok(), Some(blockmap));
+    }
+}
@@ -1,17 +1,16 @@
-use super::types::{OnlyResult, ParseResult};
+use super::types::{run, OnlyResult, ParseResult};
 use nom::{combinator::map_res, multi::count, number::complete::le_u8};
 use std::convert::TryInto;
 
 pub type Colormap = [u8; 256];
 pub type Colormaps = [Colormap; 34];
 
-fn parse_colormap(i: &[u8]) -> ParseResult<Colormap> {
+fn parse_colormap(i: &[u8]) -> ParseResult<'_, Colormap> {
     map_res(count(le_u8, 256), |res| res.try_into())(i)
 }
 
 pub fn parse_colormaps(i: &[u8]) -> OnlyResult<Colormaps> {
-    let (_, out) = map_res(count(parse_colormap, 34), |res| res.try_into())(i)?;
-    Ok(out)
+    run(map_res(count(parse_colormap, 34), |res| res.try_into()), i)
 }
 
 #[cfg(test)]
@@ -1,22 +1,41 @@
-use std::collections::HashMap;
+use std::{
+    convert::TryFrom,
+    fs,
+    io::{self, Write},
+    ops::{Deref, Range},
+    path::Path,
+    sync::Arc,
+};
 
 use super::{
-    name::parse_name,
-    types::{OnlyResult, ParseResult},
+    name::{parse_long_name, parse_name},
+    types::{run, seek, OnlyResult, ParseError, ParseResult},
+};
+use crate::{
+    error::{Error, ErrorKind},
+    wad::{
+        container::{Container, NameIndex},
+        namespace::{Namespace, NamespaceLumps},
+    },
 };
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take},
-    combinator::map,
+    bytes::complete::tag,
+    combinator::{map, map_res},
     multi::count,
-    number::complete::le_i32,
+    number::complete::{le_i32, le_u16, le_u8},
     sequence::tuple,
+    Offset,
 };
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Type {
     IWAD,
     PWAD,
+    /// Quake texture archive
+    WAD2,
+    /// Half-Life texture archive
+    WAD3,
 }
 
 impl From<&[u8]> for Type {
@@ -25,24 +44,168 @@ impl From<&[u8]> for Type {
         match i {
             b"PWAD" => Self::PWAD,
             b"IWAD" => Self::IWAD,
+            b"WAD2" => Self::WAD2,
+            b"WAD3" => Self::WAD3,
             _ => unreachable!(),
         }
     }
 }
 
+impl Type {
+    const fn magic(self) -> &'static [u8; 4] {
+        match self {
+            Self::IWAD => b"IWAD",
+            Self::PWAD => b"PWAD",
+            Self::WAD2 => b"WAD2",
+            Self::WAD3 => b"WAD3",
+        }
+    }
+
+    /// Whether directory has Quake-style entries with [`EntryInfo`].
+    pub const fn has_entry_info(self) -> bool {
+        matches!(self, Self::WAD2 | Self::WAD3)
+    }
+
+    const fn name_size(self) -> usize {
+        if self.has_entry_info() {
+            16
+        } else {
+            8
+        }
+    }
+
+    const fn dir_entry_size(self) -> usize {
+        if self.has_entry_info() {
+            32
+        } else {
+            16
+        }
+    }
+}
+
+/// Extra fields of WAD2/WAD3 directory entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryInfo {
+    pub ltype: u8,
+    /// Never used in practice, so compressed lumps are given as is
+    pub compression: u8,
+    /// Size of the uncompressed lump
+    pub size: usize,
+}
+
+impl EntryInfo {
+    pub const PALETTE: u8 = 0x40;
+    pub const QPIC: u8 = 0x42;
+    /// Half-Life miptex
+    pub const MIPTEX_HL: u8 = 0x43;
+    /// Quake miptex
+    pub const MIPTEX: u8 = 0x44;
+    pub const FONT: u8 = 0x46;
+
+    /// Uncompressed entry of the given type.
+    pub const fn new(ltype: u8, size: usize) -> Self {
+        Self {
+            ltype,
+            compression: 0,
+            size,
+        }
+    }
+}
+
+const HEADER_SIZE: usize = 12;
+
+/// Writes header, lump data and directory (in this order) of a WAD file.
+/// Empty lumps get the offset of the data position they're placed at.
+/// Entries of WAD2/WAD3 archives without info are written as untyped uncompressed ones.
+pub(crate) fn write_wad<'l, W, I>(mut w: W, wtype: Type, lumps: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (&'l str, &'l [u8], Option<EntryInfo>)>,
+{
+    let lumps: Vec<_> = lumps.into_iter().collect();
+    if let Some((name, _, _)) = lumps
+        .iter()
+        .find(|(name, _, _)| name.len() > wtype.name_size() || name.contains('\0'))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid lump name: {:?}", name),
+        ));
+    }
+    let dir_offset = lumps.iter().map(|(_, data, _)| data.len()).sum::<usize>() + HEADER_SIZE;
+    if lumps.len() > i32::MAX as usize || dir_offset > i32::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "WAD file is too large",
+        ));
+    }
+
+    w.write_all(wtype.magic())?;
+    w.write_all(&(lumps.len() as i32).to_le_bytes())?;
+    w.write_all(&(dir_offset as i32).to_le_bytes())?;
+    for (_, data, _) in &lumps {
+        w.write_all(data)?;
+    }
+
+    let mut offset = HEADER_SIZE;
+    for (name, data, info) in &lumps {
+        let mut raw_name = vec![0; wtype.name_size()];
+        raw_name[..name.len()].copy_from_slice(name.as_bytes());
+
+        w.write_all(&(offset as i32).to_le_bytes())?;
+        w.write_all(&(data.len() as i32).to_le_bytes())?;
+        if wtype.has_entry_info() {
+            let info = info.unwrap_or_else(|| EntryInfo::new(0, data.len()));
+            w.write_all(&(info.size as i32).to_le_bytes())?;
+            w.write_all(&[info.ltype, info.compression, 0, 0])?;
+        }
+        w.write_all(&raw_name)?;
+        offset += data.len();
+    }
+    w.flush()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Lump<'a> {
     pub name: &'a str,
     pub data: &'a [u8],
 }
 
 impl<'a> Lump<'a> {
-    fn parse(i: &'a [u8], file: &'a [u8]) -> ParseResult<'a, Self> {
-        let (i, (offset, disk_size, name)) = tuple((le_i32, le_i32, parse_name))(i)?;
-
-        let (data_i, _) = take(offset as usize)(file)?;
-        let (_, data) = take(disk_size as usize)(data_i)?;
+    /// Parses directory entry, validating its bounds against the whole `file`.
+    fn parse(
+        i: &'a [u8],
+        file: &'a [u8],
+        wtype: Type,
+    ) -> ParseResult<'a, OnlyResult<(Self, Option<EntryInfo>)>> {
+        let (next_i, (offset, disk_size)) = tuple((le_i32, le_i32))(i)?;
+        let (next_i, (name, info)) = if wtype.has_entry_info() {
+            let (next_i, (size, ltype, compression, _, name)) = tuple((
+                map_res(le_i32, usize::try_from),
+                le_u8,
+                le_u8,
+                le_u16,
+                parse_long_name,
+            ))(next_i)?;
+            let info = EntryInfo {
+                ltype,
+                compression,
+                size,
+            };
+            (next_i, (name, Some(info)))
+        } else {
+            let (next_i, name) = parse_name(next_i)?;
+            (next_i, (name, None))
+        };
 
-        Ok((i, Self { name, data }))
+        let data = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(disk_size).ok())
+            .and_then(|(offset, size)| file.get(offset..offset.checked_add(size)?));
+        let lump = data
+            .map(|data| (Self { name, data }, info))
+            .ok_or_else(|| Error::new(ErrorKind::OutOfBounds, file.offset(i)).with_lump(name));
+        Ok((next_i, lump))
     }
 
     pub const fn is_virtual(&self) -> bool {
@@ -53,60 +216,131 @@ impl<'a> Lump<'a> {
 pub struct Archive<'a> {
     pub wtype: Type,
     lumps: Vec<Lump<'a>>,
-    named_lumps: HashMap<&'a str, usize>,
+    info: Vec<Option<EntryInfo>>,
+    named_lumps: NameIndex<&'a str>,
 }
 
 impl<'a> Archive<'a> {
     fn build_from_lumps(wtype: Type, lumps: Vec<Lump<'a>>) -> Archive<'a> {
-        let named_lumps = lumps
-            .iter()
-            .map(|lump| lump.name)
-            .enumerate()
-            .map(|(i, name)| (name, i))
-            .collect();
+        let named_lumps = NameIndex::build(lumps.iter().map(|lump| lump.name));
         Self {
             wtype,
+            info: vec![None; lumps.len()],
             lumps,
             named_lumps,
         }
     }
 
+    pub fn new(wtype: Type) -> Self {
+        Self::build_from_lumps(wtype, Vec::new())
+    }
+
     pub fn parse(file: &'a [u8]) -> OnlyResult<Self> {
-        let (_, (wtype, dir_num, dir_offset)) = tuple((
-            map(alt((tag(b"PWAD"), tag(b"IWAD"))), Type::from),
-            le_i32,
-            le_i32,
-        ))(file)?;
+        let (wtype, lumps) = run(
+            |i| {
+                let (dir_offset_i, (wtype, dir_num)) = tuple((
+                    map(
+                        alt((tag(b"PWAD"), tag(b"IWAD"), tag(b"WAD2"), tag(b"WAD3"))),
+                        Type::from,
+                    ),
+                    map_res(le_i32, usize::try_from),
+                ))(i)?;
+                let (i, dir_offset) = map_res(le_i32, usize::try_from)(dir_offset_i)?;
+                let (_, dir_i) = seek(file, dir_offset, dir_offset_i)?;
+                if dir_i.len() / wtype.dir_entry_size() < dir_num {
+                    return ParseError::fail(dir_i, ErrorKind::Truncated);
+                }
+                let (_, lumps) = count(|i| Lump::parse(i, file, wtype), dir_num)(dir_i)?;
+                Ok((i, (wtype, lumps)))
+            },
+            file,
+        )?;
+        let (lumps, info): (Vec<_>, Vec<_>) = lumps
+            .into_iter()
+            .collect::<OnlyResult<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        Ok(Self {
+            info,
+            ..Self::build_from_lumps(wtype, lumps)
+        })
+    }
 
-        let (dir_i, _) = take(dir_offset as usize)(file)?;
-        let (_, lumps): (_, Vec<_>) =
-            map(count(|i| Lump::parse(i, file), dir_num as usize), |vec| {
-                vec.into_iter().collect()
-            })(dir_i)?;
-        Ok(Self::build_from_lumps(wtype, lumps))
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Lump<'a>> + '_ {
+        self.lumps.iter().copied()
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &Lump> {
-        self.lumps.iter()
+    pub fn len(&self) -> usize {
+        self.lumps.len()
     }
 
-    pub fn get_by_index(&self, i: usize) -> Option<&Lump> {
-        self.lumps.get(i)
+    pub fn is_empty(&self) -> bool {
+        self.lumps.is_empty()
     }
 
-    pub fn get_by_name<S: AsRef<str>>(&self, s: S) -> Option<&Lump> {
+    pub fn get_by_index(&self, i: usize) -> Option<Lump<'a>> {
+        self.lumps.get(i).copied()
+    }
+
+    /// Index of the last lump with such name, i.e. the one overriding all previous.
+    pub fn index_of<S: AsRef<str>>(&self, s: S) -> Option<usize> {
+        self.named_lumps.last(s.as_ref())
+    }
+
+    /// Directory entry fields of WAD2/WAD3 lump.
+    pub fn entry_info(&self, i: usize) -> Option<EntryInfo> {
+        self.info.get(i).copied().flatten()
+    }
+
+    pub fn get_by_name<S: AsRef<str>>(&self, s: S) -> Option<Lump<'a>> {
+        self.index_of(s).and_then(|index| self.get_by_index(index))
+    }
+
+    /// All lumps with such name in directory order, e.g. `THINGS` of every level.
+    pub fn get_all_by_name<S: AsRef<str>>(&self, s: S) -> impl Iterator<Item = Lump<'a>> + '_ {
         self.named_lumps
-            .get(s.as_ref())
-            .and_then(|&index| self.get_by_index(index))
+            .all(s.as_ref())
+            .iter()
+            .map(move |&index| self.lumps[index])
+    }
+
+    pub fn namespace(&self, namespace: Namespace) -> NamespaceLumps<'a> {
+        namespace.collect(self.iter())
     }
 
+    /// Appends lump to the end, so it overrides any previous lump with the same name.
     pub fn add_lump(&mut self, lump: Lump<'a>) {
-        if let Some(&index) = self.named_lumps.get(lump.name) {
-            self.lumps[index] = lump;
-        } else {
-            self.named_lumps.insert(lump.name, self.lumps.len() - 1);
-            self.lumps.push(lump);
-        }
+        self.named_lumps.insert(lump.name, self.lumps.len());
+        self.lumps.push(lump);
+        self.info.push(None);
+    }
+
+    /// Replaces lump in place, returning the old one or `None` if index is out of range.
+    pub fn replace_lump(&mut self, i: usize, lump: Lump<'a>) -> Option<Lump<'a>> {
+        let old = std::mem::replace(self.lumps.get_mut(i)?, lump);
+        self.info[i] = None;
+        self.named_lumps.remove(old.name, i);
+        self.named_lumps.insert(lump.name, i);
+        Some(old)
+    }
+
+    /// Serializes archive into a WAD file with the lumps in their current order.
+    pub fn write_to<W: Write>(&self, w: W) -> io::Result<()> {
+        write_wad(
+            w,
+            self.wtype,
+            self.iter()
+                .zip(&self.info)
+                .map(|(lump, &info)| (lump.name, lump.data, info)),
+        )
+    }
+}
+
+impl Archive<'_> {
+    /// Loads the file as [`OwnedArchive`], since this one borrows data of the caller.
+    /// See [`OwnedArchive::open`] for how it's loaded.
+    pub fn open<P: AsRef<Path>>(path: P) -> OnlyResult<OwnedArchive> {
+        OwnedArchive::open(path)
     }
 }
 
@@ -119,12 +353,279 @@ impl<'a> IntoIterator for Archive<'a> {
     }
 }
 
+impl Container for Archive<'_> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get_by_index(&self, i: usize) -> Option<Lump<'_>> {
+        self.get_by_index(i)
+    }
+
+    fn get_by_name(&self, name: &str) -> Option<Lump<'_>> {
+        self.get_by_name(name)
+    }
+
+    fn get_all_by_name(&self, name: &str) -> Vec<Lump<'_>> {
+        self.get_all_by_name(name).collect()
+    }
+}
+
+#[derive(Clone)]
+enum Storage {
+    Shared(Arc<[u8]>),
+    #[cfg(feature = "mmap")]
+    Mapped(Arc<memmap2::Mmap>),
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Shared(data) => data,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    range: Range<usize>,
+    info: Option<EntryInfo>,
+}
+
+/// Archive owning its data, so it may be stored without borrowing a file buffer.
+/// Lumps are still handed out as slices of the shared data, nothing is copied.
+#[derive(Clone)]
+pub struct OwnedArchive {
+    pub wtype: Type,
+    data: Storage,
+    entries: Vec<Entry>,
+    named_entries: NameIndex<String>,
+}
+
+impl OwnedArchive {
+    fn build_from_storage(data: Storage) -> OnlyResult<Self> {
+        let archive = Archive::parse(&data)?;
+        let entries: Vec<_> = archive
+            .iter()
+            .zip(&archive.info)
+            .map(|(lump, &info)| {
+                let start = data.offset(lump.data);
+                Entry {
+                    name: lump.name.to_owned(),
+                    range: start..start + lump.data.len(),
+                    info,
+                }
+            })
+            .collect();
+        let named_entries = NameIndex::build(entries.iter().map(|entry| entry.name.clone()));
+        Ok(Self {
+            wtype: archive.wtype,
+            entries,
+            named_entries,
+            data,
+        })
+    }
+
+    pub fn from_data<D: Into<Arc<[u8]>>>(data: D) -> OnlyResult<Self> {
+        Self::build_from_storage(Storage::Shared(data.into()))
+    }
+
+    /// Reads the whole file into memory.
+    pub fn read<P: AsRef<Path>>(path: P) -> OnlyResult<Self> {
+        Self::from_data(fs::read(path)?)
+    }
+
+    /// Loads the file, mapping it into memory with `mmap` feature and reading it otherwise.
+    ///
+    /// Mapped file is expected to stay the same while the archive (or any of its clones)
+    /// is alive, as resources of a running game do. Files that may be rewritten
+    /// or truncated meanwhile (e.g. by an editor) should be loaded with [`read`](Self::read).
+    pub fn open<P: AsRef<Path>>(path: P) -> OnlyResult<Self> {
+        #[cfg(feature = "mmap")]
+        {
+            let file = fs::File::open(path)?;
+            // SAFETY: the only way to map files. Mapping stays read-only and lives
+            // as long as the storage, changes made to the file by others are
+            // excluded by the contract of `open` above.
+            let mmap = unsafe { memmap2::Mmap::map(&file)? };
+            Self::build_from_storage(Storage::Mapped(Arc::new(mmap)))
+        }
+        #[cfg(not(feature = "mmap"))]
+        Self::read(path)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Lump<'_>> {
+        (0..self.len()).filter_map(move |i| self.get_by_index(i))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get_by_index(&self, i: usize) -> Option<Lump<'_>> {
+        self.entries.get(i).map(|entry| Lump {
+            name: &entry.name,
+            data: &self.data[entry.range.clone()],
+        })
+    }
+
+    pub fn index_of<S: AsRef<str>>(&self, s: S) -> Option<usize> {
+        self.named_entries.last(s.as_ref())
+    }
+
+    pub fn get_by_name<S: AsRef<str>>(&self, s: S) -> Option<Lump<'_>> {
+        self.index_of(s).and_then(|index| self.get_by_index(index))
+    }
+
+    pub fn entry_info(&self, i: usize) -> Option<EntryInfo> {
+        self.entries.get(i).and_then(|entry| entry.info)
+    }
+
+    pub fn get_all_by_name<S: AsRef<str>>(&self, s: S) -> impl Iterator<Item = Lump<'_>> {
+        self.named_entries
+            .all(s.as_ref())
+            .iter()
+            .filter_map(move |&index| self.get_by_index(index))
+    }
+
+    pub fn namespace(&self, namespace: Namespace) -> NamespaceLumps<'_> {
+        namespace.collect(self.iter())
+    }
+
+    /// Borrowed view of the archive, e.g. for merging.
+    pub fn as_archive(&self) -> Archive<'_> {
+        Archive {
+            info: self.entries.iter().map(|entry| entry.info).collect(),
+            ..Archive::build_from_lumps(self.wtype, self.iter().collect())
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, w: W) -> io::Result<()> {
+        write_wad(
+            w,
+            self.wtype,
+            self.entries.iter().map(|entry| {
+                (
+                    entry.name.as_str(),
+                    &self.data[entry.range.clone()],
+                    entry.info,
+                )
+            }),
+        )
+    }
+}
+
+impl Container for OwnedArchive {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get_by_index(&self, i: usize) -> Option<Lump<'_>> {
+        self.get_by_index(i)
+    }
+
+    fn get_by_name(&self, name: &str) -> Option<Lump<'_>> {
+        self.get_by_name(name)
+    }
+
+    fn get_all_by_name(&self, name: &str) -> Vec<Lump<'_>> {
+        self.get_all_by_name(name).collect()
+    }
+}
+
+/// Builder of a WAD file made of owned lumps, e.g. generated or patched ones.
+pub struct ArchiveBuilder {
+    wtype: Type,
+    lumps: Vec<(String, Vec<u8>, Option<EntryInfo>)>,
+}
+
+impl ArchiveBuilder {
+    pub fn new(wtype: Type) -> Self {
+        Self {
+            wtype,
+            lumps: Vec::new(),
+        }
+    }
+
+    pub fn lump<S: Into<String>, D: Into<Vec<u8>>>(mut self, name: S, data: D) -> Self {
+        self.add_lump(name, data);
+        self
+    }
+
+    /// Adds WAD2/WAD3 lump of the given [`EntryInfo`] type.
+    pub fn typed_lump<S: Into<String>, D: Into<Vec<u8>>>(
+        mut self,
+        name: S,
+        ltype: u8,
+        data: D,
+    ) -> Self {
+        let data = data.into();
+        let info = EntryInfo::new(ltype, data.len());
+        self.lumps.push((name.into(), data, Some(info)));
+        self
+    }
+
+    pub fn marker<S: Into<String>>(self, name: S) -> Self {
+        self.lump(name, Vec::new())
+    }
+
+    pub fn add_lump<S: Into<String>, D: Into<Vec<u8>>>(&mut self, name: S, data: D) {
+        self.lumps.push((name.into(), data.into(), None));
+    }
+
+    pub fn write_to<W: Write>(&self, w: W) -> io::Result<()> {
+        write_wad(
+            w,
+            self.wtype,
+            self.lumps
+                .iter()
+                .map(|(name, data, info)| (name.as_str(), data.as_slice(), *info)),
+        )
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        self.write_to(&mut output)?;
+        Ok(output)
+    }
+}
+
+impl From<&Archive<'_>> for ArchiveBuilder {
+    fn from(archive: &Archive<'_>) -> Self {
+        let lumps = archive
+            .iter()
+            .zip(&archive.info)
+            .map(|(lump, &info)| (lump.name.to_owned(), lump.data.to_vec(), info))
+            .collect();
+        Self {
+            wtype: archive.wtype,
+            lumps,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{Archive, ArchiveBuilder, OwnedArchive, Type};
+    use crate::error::ErrorKind;
+
     #[test]
     fn print_lump_names() {
         let file = std::fs::read(env!("TEST_WAD")).expect("Error reading wad file");
-        let archive = super::Archive::parse(&file).expect("Wad file parser error");
+        let archive = Archive::parse(&file).expect("Wad file parser error");
 
         println!("Wad type: {:?}", archive.wtype);
         archive
@@ -132,4 +633,173 @@ mod tests {
             .enumerate()
             .for_each(|(i, lump)| println!("Lump {} named {}", i, lump.name));
     }
+
+    #[test]
+    fn write_parse_roundtrip() {
+        let wad = ArchiveBuilder::new(Type::PWAD)
+            .marker("MAP01")
+            .lump("THINGS", vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10])
+            .marker("F_START")
+            .lump("FLAT1", vec![0xAB; 64 * 64])
+            .marker("F_END")
+            .lump("LONGNAME", b"text".to_vec())
+            .to_bytes()
+            .expect("Error writing wad");
+
+        let archive = Archive::parse(&wad).expect("Wad file parser error");
+        assert_eq!(archive.wtype, Type::PWAD);
+        let lumps: Vec<_> = archive.iter().map(|lump| (lump.name, lump.data)).collect();
+        assert_eq!(
+            lumps,
+            [
+                ("MAP01", &[][..]),
+                ("THINGS", &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10][..]),
+                ("F_START", &[][..]),
+                ("FLAT1", &[0xAB; 64 * 64][..]),
+                ("F_END", &[][..]),
+                ("LONGNAME", &b"text"[..]),
+            ]
+        );
+
+        let mut rewritten = Vec::new();
+        archive.write_to(&mut rewritten).expect("Error writing wad");
+        assert_eq!(wad, rewritten);
+    }
+
+    #[test]
+    fn rewrite_test_wad() {
+        let file = std::fs::read(env!("TEST_WAD")).expect("Error reading wad file");
+        let archive = Archive::parse(&file).expect("Wad file parser error");
+        let rewritten = ArchiveBuilder::from(&archive)
+            .to_bytes()
+            .expect("Error writing wad");
+        let reparsed = Archive::parse(&rewritten).expect("Wad file parser error");
+
+        assert_eq!(archive.wtype, reparsed.wtype);
+        assert!(archive
+            .iter()
+            .zip(reparsed.iter())
+            .all(|(a, b)| a.name == b.name && a.data == b.data));
+        assert_eq!(archive.iter().count(), reparsed.iter().count());
+    }
+
+    #[test]
+    fn reject_long_lump_name() {
+        assert!(ArchiveBuilder::new(Type::PWAD)
+            .marker("TOOLONGNAME")
+            .to_bytes()
+            .is_err());
+    }
+
+    #[test]
+    fn owned_archive_shares_data() {
+        let wad = ArchiveBuilder::new(Type::IWAD)
+            .lump("PLAYPAL", vec![1; 768])
+            .marker("E1M1")
+            .to_bytes()
+            .expect("Error writing wad");
+
+        let archive = OwnedArchive::from_data(wad).expect("Wad file parser error");
+        let playpal = archive.get_by_name("PLAYPAL").expect("PLAYPAL not found");
+        let data_range = archive.data().as_ptr_range();
+        assert!(data_range.contains(&playpal.data.as_ptr()));
+        assert_eq!(playpal.data, &[1; 768][..]);
+        assert_eq!(archive.wtype, Type::IWAD);
+        assert_eq!(
+            archive.iter().map(|lump| lump.name).collect::<Vec<_>>(),
+            ["PLAYPAL", "E1M1"]
+        );
+    }
+
+    #[test]
+    fn open_test_wad() {
+        let file = std::fs::read(env!("TEST_WAD")).expect("Error reading wad file");
+        let opened = Archive::open(env!("TEST_WAD")).expect("Error opening wad file");
+        let read = OwnedArchive::read(env!("TEST_WAD")).expect("Error reading wad file");
+
+        assert_eq!(opened.data(), &file[..]);
+        assert!(opened
+            .iter()
+            .eq(Archive::parse(&file).expect("Wad file parser error").iter()));
+        assert!(opened.iter().eq(read.iter()));
+    }
+
+    #[test]
+    fn report_broken_archives() {
+        let wad = ArchiveBuilder::new(Type::PWAD)
+            .lump("THINGS", vec![0; 20])
+            .to_bytes()
+            .expect("Error writing wad");
+        let kind_of = |data: &[u8]| Archive::parse(data).err().and_then(|e| e.kind());
+
+        assert_eq!(kind_of(b"JUNK\0\0\0\0\0\0\0\0"), Some(ErrorKind::BadMagic));
+        assert_eq!(kind_of(&wad[..wad.len() - 1]), Some(ErrorKind::Truncated));
+
+        let mut broken = wad.clone();
+        broken[32..36].copy_from_slice(&1000i32.to_le_bytes());
+        let error = Archive::parse(&broken).err().expect("Broken wad parsed");
+        assert_eq!(error.kind(), Some(ErrorKind::OutOfBounds));
+        assert_eq!(error.lump(), Some("THINGS"));
+        assert_eq!(error.offset(), Some(32));
+    }
+
+    fn lump(name: &'static str) -> super::Lump<'static> {
+        super::Lump {
+            name,
+            data: name.as_bytes(),
+        }
+    }
+
+    #[test]
+    fn lookup_duplicate_names() {
+        let mut archive = Archive::build_from_lumps(
+            Type::PWAD,
+            vec![
+                lump("MAP01"),
+                lump("THINGS"),
+                lump("MAP02"),
+                lump("THINGS"),
+                lump("PLAYPAL"),
+            ],
+        );
+        assert_eq!(archive.index_of("THINGS"), Some(3));
+        assert_eq!(archive.get_all_by_name("THINGS").count(), 2);
+        assert_eq!(archive.get_all_by_name("NOTHING").count(), 0);
+
+        archive.add_lump(lump("THINGS"));
+        assert_eq!(archive.index_of("THINGS"), Some(5));
+        assert_eq!(archive.get_all_by_name("THINGS").count(), 3);
+
+        let old = archive.replace_lump(5, lump("COLORMAP"));
+        assert_eq!(old.map(|lump| lump.name), Some("THINGS"));
+        assert_eq!(archive.index_of("THINGS"), Some(3));
+        assert_eq!(archive.index_of("COLORMAP"), Some(5));
+
+        archive.replace_lump(1, lump("PLAYPAL"));
+        assert_eq!(archive.index_of("PLAYPAL"), Some(4));
+        assert_eq!(
+            archive
+                .get_all_by_name("PLAYPAL")
+                .map(|lump| lump.data)
+                .collect::<Vec<_>>(),
+            [&b"PLAYPAL"[..], &b"PLAYPAL"[..]]
+        );
+        assert!(archive.replace_lump(6, lump("PLAYPAL")).is_none());
+    }
+
+    #[test]
+    fn add_to_empty_archive() {
+        let mut archive = Archive::new(Type::PWAD);
+        assert!(archive.get_by_name("THINGS").is_none());
+        archive.add_lump(lump("THINGS"));
+        assert_eq!(archive.index_of("THINGS"), Some(0));
+
+        let wad = ArchiveBuilder::from(&archive)
+            .lump("THINGS", vec![1; 10])
+            .to_bytes()
+            .expect("Error writing wad");
+        let owned = OwnedArchive::from_data(wad).expect("Wad file parser error");
+        assert_eq!(owned.index_of("THINGS"), Some(1));
+        assert_eq!(owned.get_all_by_name("THINGS").count(), 2);
+    }
 }
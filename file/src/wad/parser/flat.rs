@@ -1,9 +1,19 @@
-use std::{array::TryFromSliceError, convert::TryInto};
+use super::types::OnlyResult;
+use crate::error::{Error, ErrorKind};
+use std::convert::TryInto;
 
-pub type Flat<'a> = &'a [u8; 64 * 64];
+const FLAT_SIZE: usize = 64 * 64;
 
-pub fn parse_flat(i: &[u8]) -> Result<Flat, TryFromSliceError> {
-    i.try_into()
+pub type Flat<'a> = &'a [u8; FLAT_SIZE];
+
+pub fn parse_flat(i: &[u8]) -> OnlyResult<Flat<'_>> {
+    i.try_into().map_err(|_| {
+        if i.len() < FLAT_SIZE {
+            Error::new(ErrorKind::Truncated, i.len())
+        } else {
+            Error::new(ErrorKind::Malformed, FLAT_SIZE)
+        }
+    })
 }
 
 #[cfg(test)]
@@ -14,11 +24,10 @@ mod tests {
         let archive =
             crate::wad::parser::file::Archive::parse(&file).expect("Wad file parser error");
         let flat = archive
+            .namespace(crate::wad::namespace::Namespace::Flats)
             .iter()
-            .skip_while(|lump| lump.name != "F_START")
-            .filter(|lump| !lump.is_virtual())
             .next()
-            .expect("Next flat to F_START not found");
+            .expect("No flats found");
 
         assert!(super::parse_flat(flat.data).is_ok());
     }
@@ -0,0 +1,285 @@
+use super::{
+    file::Lump,
+    level::{Node, SubSector},
+    types::{run, Input, OnlyResult, ParseResult},
+    znodes::ExtendedFormat,
+};
+use nom::{
+    bytes::complete::tag,
+    combinator::{map, opt},
+    multi::many0,
+    number::complete::{le_i16, le_i32, le_u16, le_u32},
+    sequence::{preceded, tuple},
+};
+
+const NONE_16: u16 = 0xFFFF;
+const NONE_32: u32 = 0xFFFF_FFFF;
+
+/// Version of GL nodes, glBSP ones told apart by magic of `GL_VERT` and `GL_SEGS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GlVersion {
+    /// Integer vertices and 16-bit indices
+    V1,
+    /// `gNd2` fixed-point vertices
+    V2,
+    /// `gNd3` segs and subsectors with 32-bit indices
+    V3,
+    /// `gNd4`, 32-bit indices everywhere
+    V4,
+    /// `gNd5`, 32-bit indices everywhere
+    V5,
+    /// ZDoom GL nodes stored in a single lump
+    Zdoom(ExtendedFormat),
+}
+
+impl GlVersion {
+    /// Flag of seg vertex index referring to `GL_VERT` rather than `VERTEXES`.
+    const fn vertex_flag(self) -> u32 {
+        match self {
+            Self::V1 | Self::V2 => 0x8000,
+            Self::V3 => 0x4000_0000,
+            Self::V4 | Self::V5 => 0x8000_0000,
+            // New vertices simply follow the level's ones
+            Self::Zdoom(_) => 0,
+        }
+    }
+
+    fn detect(vertices: &[u8], segs: &[u8]) -> Self {
+        match vertices.get(..4) {
+            Some(b"gNd5") => Self::V5,
+            Some(b"gNd4") => Self::V4,
+            Some(b"gNd2") | Some(b"gNd3") if segs.starts_with(b"gNd3") => Self::V3,
+            Some(b"gNd2") | Some(b"gNd3") => Self::V2,
+            _ => Self::V1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlVertexRef {
+    /// Index into level's `VERTEXES`
+    Normal(u32),
+    /// Index into `GL_VERT` or new vertices of ZDoom nodes
+    Gl(u32),
+}
+
+pub struct GlSeg {
+    pub start: GlVertexRef,
+    pub end: GlVertexRef,
+    /// `None` for minisegs running along no linedef
+    pub linedef: Option<u32>,
+    pub side: u16,
+    /// Seg on the other side of the same line, if any
+    pub partner: Option<u32>,
+}
+
+/// 16.16 fixed-point coordinates.
+pub type GlVertex = (i32, i32);
+
+/// Nodes built by glBSP, which has closed convex subsectors made of segs and minisegs.
+pub struct GlNodes {
+    pub version: GlVersion,
+    pub vertices: Vec<GlVertex>,
+    pub segs: Vec<GlSeg>,
+    pub subsectors: Vec<SubSector>,
+    pub nodes: Vec<Node>,
+}
+
+fn parse_vertices(i: Input<'_>, version: GlVersion) -> OnlyResult<Vec<GlVertex>> {
+    match version {
+        GlVersion::V1 => run(
+            many0(map(tuple((le_i16, le_i16)), |(x, y)| {
+                (i32::from(x) << 16, i32::from(y) << 16)
+            })),
+            i,
+        ),
+        _ => run(preceded(opt_magic, many0(tuple((le_i32, le_i32)))), i),
+    }
+}
+
+/// Skips `gNdX` magic.
+fn opt_magic(i: Input<'_>) -> ParseResult<'_, Option<Input<'_>>> {
+    opt(preceded(tag(b"gNd"), nom::bytes::complete::take(1usize)))(i)
+}
+
+fn vertex_ref(index: u32, version: GlVersion) -> GlVertexRef {
+    let flag = version.vertex_flag();
+    if index & flag != 0 {
+        GlVertexRef::Gl(index & !flag)
+    } else {
+        GlVertexRef::Normal(index)
+    }
+}
+
+fn parse_seg(version: GlVersion) -> impl FnMut(Input<'_>) -> ParseResult<'_, GlSeg> {
+    move |i| {
+        let (i, (start, end, linedef, side, partner)) = match version {
+            GlVersion::V1 | GlVersion::V2 => {
+                let (i, (start, end, linedef, side, partner)) =
+                    tuple((le_u16, le_u16, le_u16, le_u16, le_u16))(i)?;
+                let partner = if partner == NONE_16 {
+                    NONE_32
+                } else {
+                    partner.into()
+                };
+                (i, (start.into(), end.into(), linedef, side, partner))
+            }
+            _ => tuple((le_u32, le_u32, le_u16, le_u16, le_u32))(i)?,
+        };
+        Ok((
+            i,
+            GlSeg {
+                start: vertex_ref(start, version),
+                end: vertex_ref(end, version),
+                linedef: Some(linedef)
+                    .filter(|&linedef| linedef != NONE_16)
+                    .map(u32::from),
+                side,
+                partner: Some(partner).filter(|&partner| partner != NONE_32),
+            },
+        ))
+    }
+}
+
+fn parse_subsector(version: GlVersion) -> fn(Input<'_>) -> ParseResult<'_, SubSector> {
+    match version {
+        GlVersion::V1 | GlVersion::V2 => SubSector::parse,
+        _ => SubSector::parse_extended,
+    }
+}
+
+fn parse_node(version: GlVersion) -> fn(Input<'_>) -> ParseResult<'_, Node> {
+    match version {
+        GlVersion::V4 | GlVersion::V5 => Node::parse_extended,
+        _ => Node::parse,
+    }
+}
+
+fn parse_gl_lump<'a, O, P>(lump: Option<Lump<'a>>, parser: P) -> OnlyResult<Vec<O>>
+where
+    P: FnOnce(&'a [u8]) -> OnlyResult<Vec<O>>,
+{
+    match lump {
+        Some(lump) => parser(lump.data).map_err(|e| e.with_lump(lump.name)),
+        None => Ok(Vec::new()),
+    }
+}
+
+impl GlNodes {
+    /// Parses GL lumps found among `lumps`, e.g. the ones of a level or following
+    /// `GL_<map>` marker in a GWA file. `None` if there's no `GL_VERT`.
+    pub fn parse<'a, I>(lumps: I) -> Option<OnlyResult<Self>>
+    where
+        I: IntoIterator<Item = Lump<'a>>,
+    {
+        let mut found = [None; 4];
+        for lump in lumps {
+            let kind = ["GL_VERT", "GL_SEGS", "GL_SSECT", "GL_NODES"]
+                .iter()
+                .position(|&name| name == lump.name);
+            if let Some(slot) = kind.and_then(|kind| found.get_mut(kind)) {
+                slot.get_or_insert(lump);
+            }
+        }
+        let [vertices, segs, subsectors, nodes] = found;
+        let vertices = vertices?;
+        let version = GlVersion::detect(vertices.data, segs.map_or(&[], |lump| lump.data));
+        let parse = || {
+            Ok(Self {
+                version,
+                vertices: parse_gl_lump(Some(vertices), |i| parse_vertices(i, version))?,
+                segs: parse_gl_lump(segs, |i| {
+                    run(preceded(opt_magic, many0(parse_seg(version))), i)
+                })?,
+                subsectors: parse_gl_lump(subsectors, |i| {
+                    run(preceded(opt_magic, many0(parse_subsector(version))), i)
+                })?,
+                nodes: parse_gl_lump(nodes, |i| run(many0(parse_node(version)), i))?,
+            })
+        };
+        Some(parse())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GlNodes, GlVersion, GlVertexRef};
+    use crate::wad::parser::{file::Lump, level::SUBSECTOR_FLAG};
+
+    fn bytes(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[test]
+    fn parse_versions() {
+        let v2_vert = bytes(&[
+            b"gNd2",
+            &(-1i32 << 16).to_le_bytes(),
+            &0x8000i32.to_le_bytes(),
+        ]);
+        let v2_segs = bytes(&[
+            &0x8000u16.to_le_bytes(),
+            &1u16.to_le_bytes(),
+            &0xFFFFu16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &0xFFFFu16.to_le_bytes(),
+        ]);
+        let v2_nodes = bytes(&[&[0; 24], &0x8001u16.to_le_bytes(), &2u16.to_le_bytes()]);
+        let lumps = [
+            Lump {
+                name: "GL_VERT",
+                data: &v2_vert,
+            },
+            Lump {
+                name: "GL_SEGS",
+                data: &v2_segs,
+            },
+            Lump {
+                name: "GL_NODES",
+                data: &v2_nodes,
+            },
+        ];
+        let gl = GlNodes::parse(lumps.iter().copied())
+            .expect("No GL nodes")
+            .expect("Error parsing GL nodes");
+        assert_eq!(gl.version, GlVersion::V2);
+        assert_eq!(gl.vertices, [(-65536, 0x8000)]);
+        assert_eq!(gl.segs[0].start, GlVertexRef::Gl(0));
+        assert_eq!(gl.segs[0].end, GlVertexRef::Normal(1));
+        assert_eq!((gl.segs[0].linedef, gl.segs[0].partner), (None, None));
+        assert_eq!(gl.nodes[0].children, [SUBSECTOR_FLAG | 1, 2]);
+
+        let v5_vert = bytes(&[b"gNd5", &[0; 8]]);
+        let v5_segs = bytes(&[
+            &0x8000_0002u32.to_le_bytes(),
+            &7u32.to_le_bytes(),
+            &3u16.to_le_bytes(),
+            &1u16.to_le_bytes(),
+            &9u32.to_le_bytes(),
+        ]);
+        let v5_ssect = bytes(&[&1u32.to_le_bytes(), &0u32.to_le_bytes()]);
+        let lumps = [
+            Lump {
+                name: "GL_VERT",
+                data: &v5_vert,
+            },
+            Lump {
+                name: "GL_SEGS",
+                data: &v5_segs,
+            },
+            Lump {
+                name: "GL_SSECT",
+                data: &v5_ssect,
+            },
+        ];
+        let gl = GlNodes::parse(lumps.iter().copied())
+            .expect("No GL nodes")
+            .expect("Error parsing GL nodes");
+        assert_eq!(gl.version, GlVersion::V5);
+        assert_eq!(gl.segs[0].start, GlVertexRef::Gl(2));
+        assert_eq!(gl.segs[0].linedef, Some(3));
+        assert_eq!(gl.segs[0].partner, Some(9));
+        assert_eq!(gl.subsectors[0].numsegs, 1);
+        assert!(GlNodes::parse(lumps[1..].iter().copied()).is_none());
+    }
+}
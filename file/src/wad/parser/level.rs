@@ -1,30 +1,38 @@
 use super::{
+    blockmap::Blockmap,
     file::Lump,
-    name::parse_name,
-    types::{OnlyResult, ParseResult},
+    glnodes::GlNodes,
+    name::{parse_name, write_name},
+    reject::Reject,
+    types::{run, OnlyResult, ParseResult},
+    udmf::{TextMap, UdmfExtra},
+    znodes::ExtendedNodes,
 };
-use crate::wad::utils::is_level_name;
+use crate::error::{Error, ErrorKind};
 use nom::{
+    bytes::complete::tag,
     multi::many0,
-    number::complete::{le_i16, le_u16},
-    sequence::tuple,
+    number::complete::{le_i16, le_u16, le_u32, le_u8},
+    sequence::{preceded, tuple},
 };
+use std::{convert::TryFrom, io};
 
-const LEVEL_LUMPS: [&str; 10] = [
+/// Lumps of a binary level, in any order after its marker.
+const LEVEL_LUMPS: [&str; 12] = [
     "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
-    "BLOCKMAP",
+    "BLOCKMAP", "BEHAVIOR", "SCRIPTS",
 ];
-const THINGS_OFFSET: usize = 1;
-const LINEDEFS_OFFSET: usize = 2;
-const SIDEDEFS_OFFSET: usize = 3;
-const VERTICES_OFFSET: usize = 4;
-const SEGMENTS_OFFSET: usize = 5;
-const SUBSECTORS_OFFSET: usize = 6;
-const NODES_OFFSET: usize = 7;
-const SECTORS_OFFSET: usize = 8;
-// unused
-// const REJECT_OFFSET: usize = 9;
-// const BLOCKMAP_OFFSET: usize = 10;
+/// Lumps of GL nodes following `GL_<marker>` (or `GL_LEVEL` for long names) right after the level.
+const GL_LUMPS: [&str; 5] = ["GL_VERT", "GL_SEGS", "GL_SSECT", "GL_NODES", "GL_PVS"];
+/// Lumps every binary level must have, the rest can be rebuilt or are optional.
+const REQUIRED_LUMPS: [&str; 5] = ["THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SECTORS"];
+/// Flag of a node child referring to a subsector, the high bit of 32-bit index in every format.
+pub const SUBSECTOR_FLAG: u32 = 0x8000_0000;
+/// Header of `NODES` built by DeePBSP, which widens indices of `SEGS`, `SSECTORS` and `NODES`.
+const DEEPBSP_MAGIC: &[u8] = b"xNd4\0\0\0\0";
+const BEHAVIOR: &str = "BEHAVIOR";
+const UDMF_START: &str = "TEXTMAP";
+const UDMF_END: &str = "ENDMAP";
 
 pub struct BoundingBox {
     pub top: i16,
@@ -34,7 +42,7 @@ pub struct BoundingBox {
 }
 
 impl BoundingBox {
-    fn parse(i: &[u8]) -> ParseResult<Self> {
+    pub(super) fn parse(i: &[u8]) -> ParseResult<'_, Self> {
         let (i, (top, bottom, left, right)) = tuple((le_i16, le_i16, le_i16, le_i16))(i)?;
         Ok((
             i,
@@ -48,26 +56,61 @@ impl BoundingBox {
     }
 }
 
+fn parse_args(i: &[u8]) -> ParseResult<'_, [u8; 5]> {
+    let (i, (a0, a1, a2, a3, a4)) = tuple((le_u8, le_u8, le_u8, le_u8, le_u8))(i)?;
+    Ok((i, [a0, a1, a2, a3, a4]))
+}
+
+/// Thing of any binary format, fields missing in Doom one are zeroed.
 pub struct Thing {
+    pub tid: i16,
     pub x_pos: i16,
     pub y_pos: i16,
+    /// Height above the floor, Hexen only
+    pub z_pos: i16,
     pub angle: i16,
     pub ttype: i16,
     pub options: i16,
+    pub special: u8,
+    pub args: [u8; 5],
 }
 
 impl Thing {
-    fn parse(i: &[u8]) -> ParseResult<Self> {
+    fn parse_doom(i: &[u8]) -> ParseResult<'_, Self> {
         let (i, (x_pos, y_pos, angle, ttype, options)) =
             tuple((le_i16, le_i16, le_i16, le_i16, le_i16))(i)?;
         Ok((
             i,
             Self {
+                tid: 0,
                 x_pos,
                 y_pos,
+                z_pos: 0,
                 angle,
                 ttype,
                 options,
+                special: 0,
+                args: [0; 5],
+            },
+        ))
+    }
+
+    fn parse_hexen(i: &[u8]) -> ParseResult<'_, Self> {
+        let (i, (tid, x_pos, y_pos, z_pos, angle, ttype, options, special, args)) = tuple((
+            le_i16, le_i16, le_i16, le_i16, le_i16, le_i16, le_i16, le_u8, parse_args,
+        ))(i)?;
+        Ok((
+            i,
+            Self {
+                tid,
+                x_pos,
+                y_pos,
+                z_pos,
+                angle,
+                ttype,
+                options,
+                special,
+                args,
             },
         ))
     }
@@ -75,46 +118,100 @@ impl Thing {
 
 pub struct Things;
 impl Things {
-    fn parse(i: &[u8]) -> OnlyResult<Vec<Thing>> {
-        let (_, things) = many0(Thing::parse)(i)?;
-        Ok(things)
+    fn parse(i: &[u8], format: LevelFormat) -> OnlyResult<Vec<Thing>> {
+        match format {
+            LevelFormat::Hexen => run(many0(Thing::parse_hexen), i),
+            _ => run(many0(Thing::parse_doom), i),
+        }
     }
 }
 
+/// Linedef of any binary format: Doom one has a tag and no args,
+/// Hexen one has args (the first of them often being a tag) and zero tag.
+/// Indices are unsigned like limit-removing ports read them, `0xFFFF` meaning no sidedef.
 pub struct Linedef {
-    pub vertex_start: i16,
-    pub vertex_end: i16,
+    pub vertex_start: u32,
+    pub vertex_end: u32,
     pub flags: i16,
     pub function: i16,
     pub tag: i16,
-    pub sidedef_right: i16,
-    pub sidedef_left: i16,
+    pub args: [u8; 5],
+    pub sidedef_right: Option<u32>,
+    pub sidedef_left: Option<u32>,
+}
+
+const NO_SIDEDEF: u16 = 0xFFFF;
+
+fn sidedef_index(index: u16) -> Option<u32> {
+    Some(index)
+        .filter(|&index| index != NO_SIDEDEF)
+        .map(u32::from)
 }
 
 impl Linedef {
-    fn parse(i: &[u8]) -> ParseResult<Self> {
+    fn parse_doom(i: &[u8]) -> ParseResult<'_, Self> {
         let (i, (vertex_start, vertex_end, flags, function, tag, sidedef_right, sidedef_left)) =
-            tuple((le_i16, le_i16, le_i16, le_i16, le_i16, le_i16, le_i16))(i)?;
+            tuple((le_u16, le_u16, le_i16, le_i16, le_i16, le_u16, le_u16))(i)?;
         Ok((
             i,
             Self {
-                vertex_start,
-                vertex_end,
+                vertex_start: vertex_start.into(),
+                vertex_end: vertex_end.into(),
                 flags,
                 function,
                 tag,
-                sidedef_right,
-                sidedef_left,
+                args: [0; 5],
+                sidedef_right: sidedef_index(sidedef_right),
+                sidedef_left: sidedef_index(sidedef_left),
+            },
+        ))
+    }
+
+    fn parse_hexen(i: &[u8]) -> ParseResult<'_, Self> {
+        let (i, (vertex_start, vertex_end, flags, function, args, sidedef_right, sidedef_left)) =
+            tuple((le_u16, le_u16, le_i16, le_u8, parse_args, le_u16, le_u16))(i)?;
+        Ok((
+            i,
+            Self {
+                vertex_start: vertex_start.into(),
+                vertex_end: vertex_end.into(),
+                flags,
+                function: function.into(),
+                tag: 0,
+                args,
+                sidedef_right: sidedef_index(sidedef_right),
+                sidedef_left: sidedef_index(sidedef_left),
             },
         ))
     }
 }
 
+impl Linedef {
+    /// Gets start and end vertices.
+    pub fn ends(&self, vertices: &[Vertex]) -> Option<(Vertex, Vertex)> {
+        let start = vertices.get(self.vertex_start as usize)?;
+        let end = vertices.get(self.vertex_end as usize)?;
+        Some((*start, *end))
+    }
+
+    /// Gets sector on the right side and the left one if it's two-sided.
+    pub fn sectors(&self, sidedefs: &[Sidedef<'_>]) -> (Option<usize>, Option<usize>) {
+        let sector = |side: Option<u32>| {
+            sidedefs
+                .get(side? as usize)
+                .map(|sidedef| usize::from(sidedef.sector_ref as u16))
+        };
+        (sector(self.sidedef_right), sector(self.sidedef_left))
+    }
+}
+
 pub struct Linedefs;
 impl Linedefs {
-    fn parse(i: &[u8]) -> OnlyResult<Vec<Linedef>> {
-        let (_, linedefs) = many0(Linedef::parse)(i)?;
-        Ok(linedefs)
+    fn parse(i: &[u8], format: LevelFormat) -> OnlyResult<Vec<Linedef>> {
+        match format {
+            LevelFormat::Hexen => run(many0(Linedef::parse_hexen), i),
+            _ => run(many0(Linedef::parse_doom), i),
+        }
     }
 }
 
@@ -128,7 +225,7 @@ pub struct Sidedef<'a> {
 }
 
 impl<'a> Sidedef<'a> {
-    fn parse(i: &'a [u8]) -> ParseResult<Self> {
+    fn parse(i: &'a [u8]) -> ParseResult<'a, Self> {
         let (i, (x_offset, y_offset, upper_texture, lower_texture, mid_texture, sector_ref)) =
             tuple((le_i16, le_i16, parse_name, parse_name, parse_name, le_i16))(i)?;
         Ok((
@@ -147,40 +244,57 @@ impl<'a> Sidedef<'a> {
 
 pub struct Sidedefs;
 impl Sidedefs {
-    fn parse(i: &[u8]) -> OnlyResult<Vec<Sidedef>> {
-        let (_, sidedefs) = many0(Sidedef::parse)(i)?;
-        Ok(sidedefs)
+    fn parse(i: &[u8]) -> OnlyResult<Vec<Sidedef<'_>>> {
+        run(many0(Sidedef::parse), i)
     }
 }
 
 pub type Vertex = (i16, i16);
 fn parse_vertices(i: &[u8]) -> OnlyResult<Vec<Vertex>> {
-    let (_, vertices) = many0(tuple((le_i16, le_i16)))(i)?;
-    Ok(vertices)
+    run(many0(tuple((le_i16, le_i16))), i)
 }
 
 pub struct Segment {
-    pub vertex_start: i16,
-    pub vertex_end: i16,
+    pub vertex_start: u32,
+    pub vertex_end: u32,
     pub bams: i16,
-    pub line_num: i16,
+    pub line_num: u32,
     pub segside: i16,
     pub segoffset: i16,
 }
 
 impl Segment {
-    fn parse(i: &[u8]) -> ParseResult<Self> {
+    fn parse(i: &[u8]) -> ParseResult<'_, Self> {
         let (i, (vertex_start, vertex_end, bams, line_num, segside, segoffset)) =
-            tuple((le_i16, le_i16, le_i16, le_i16, le_i16, le_i16))(i)?;
+            tuple((le_u16, le_u16, le_i16, le_u16, le_i16, le_i16))(i)?;
+        Ok((
+            i,
+            Self {
+                vertex_start: vertex_start.into(),
+                vertex_end: vertex_end.into(),
+                bams,
+                line_num: line_num.into(),
+                segside,
+                segoffset,
+            },
+        ))
+    }
+}
+
+impl Segment {
+    /// Parses seg of DeePBSP nodes with 32-bit vertices.
+    fn parse_deep(i: &[u8]) -> ParseResult<'_, Self> {
+        let (i, (vertex_start, vertex_end, bams, line_num, segside, segoffset)) =
+            tuple((le_u32, le_u32, le_i16, le_u16, le_i16, le_u16))(i)?;
         Ok((
             i,
             Self {
                 vertex_start,
                 vertex_end,
                 bams,
-                line_num,
+                line_num: line_num.into(),
                 segside,
-                segoffset,
+                segoffset: segoffset as i16,
             },
         ))
     }
@@ -188,29 +302,59 @@ impl Segment {
 
 pub struct Segments;
 impl Segments {
-    fn parse(i: &[u8]) -> OnlyResult<Vec<Segment>> {
-        let (_, segments) = many0(Segment::parse)(i)?;
-        Ok(segments)
+    fn parse(i: &[u8], deep: bool) -> OnlyResult<Vec<Segment>> {
+        if deep {
+            run(many0(Segment::parse_deep), i)
+        } else {
+            run(many0(Segment::parse), i)
+        }
     }
 }
 
 pub struct SubSector {
-    pub numsegs: i16,
-    pub start_seg: i16,
+    pub numsegs: u32,
+    pub start_seg: u32,
 }
 
 impl SubSector {
-    fn parse(i: &[u8]) -> ParseResult<Self> {
-        let (i, (numsegs, start_seg)) = tuple((le_i16, le_i16))(i)?;
+    pub(super) fn parse(i: &[u8]) -> ParseResult<'_, Self> {
+        let (i, (numsegs, start_seg)) = tuple((le_u16, le_u16))(i)?;
+        Ok((
+            i,
+            Self {
+                numsegs: numsegs.into(),
+                start_seg: start_seg.into(),
+            },
+        ))
+    }
+
+    /// Parses subsector of DeePBSP nodes with 32-bit first seg.
+    fn parse_deep(i: &[u8]) -> ParseResult<'_, Self> {
+        let (i, (numsegs, start_seg)) = tuple((le_u16, le_u32))(i)?;
+        Ok((
+            i,
+            Self {
+                numsegs: numsegs.into(),
+                start_seg,
+            },
+        ))
+    }
+
+    /// Parses subsector of glBSP V3+ nodes with 32-bit fields.
+    pub(super) fn parse_extended(i: &[u8]) -> ParseResult<'_, Self> {
+        let (i, (numsegs, start_seg)) = tuple((le_u32, le_u32))(i)?;
         Ok((i, Self { numsegs, start_seg }))
     }
 }
 
 pub struct SubSectors;
 impl SubSectors {
-    fn parse(i: &[u8]) -> OnlyResult<Vec<SubSector>> {
-        let (_, ssectors) = many0(SubSector::parse)(i)?;
-        Ok(ssectors)
+    fn parse(i: &[u8], deep: bool) -> OnlyResult<Vec<SubSector>> {
+        if deep {
+            run(many0(SubSector::parse_deep), i)
+        } else {
+            run(many0(SubSector::parse), i)
+        }
     }
 }
 
@@ -220,11 +364,21 @@ pub struct Node {
     pub dx: i16,
     pub dy: i16,
     pub bbox: [BoundingBox; 2],
-    pub children: [u16; 2],
+    /// Node indices, subsectors flagged with [`SUBSECTOR_FLAG`]
+    pub children: [u32; 2],
+}
+
+/// Widens vanilla 16-bit child, flagged with `0x8000` when it's a subsector.
+fn widen_child(child: u16) -> u32 {
+    if child & 0x8000 != 0 {
+        u32::from(child & 0x7FFF) | SUBSECTOR_FLAG
+    } else {
+        child.into()
+    }
 }
 
 impl Node {
-    fn parse(i: &[u8]) -> ParseResult<Self> {
+    pub(super) fn parse(i: &[u8]) -> ParseResult<'_, Self> {
         let (i, (x, y, dx, dy, bbox1, bbox2, child1, child2)) = tuple((
             le_i16,
             le_i16,
@@ -235,6 +389,31 @@ impl Node {
             le_u16,
             le_u16,
         ))(i)?;
+        Ok((
+            i,
+            Self {
+                x,
+                y,
+                dx,
+                dy,
+                bbox: [bbox1, bbox2],
+                children: [widen_child(child1), widen_child(child2)],
+            },
+        ))
+    }
+
+    /// Parses node with 32-bit children of glBSP V4+ and ZDoom nodes.
+    pub(super) fn parse_extended(i: &[u8]) -> ParseResult<'_, Self> {
+        let (i, (x, y, dx, dy, bbox1, bbox2, child1, child2)) = tuple((
+            le_i16,
+            le_i16,
+            le_i16,
+            le_i16,
+            BoundingBox::parse,
+            BoundingBox::parse,
+            le_u32,
+            le_u32,
+        ))(i)?;
         Ok((
             i,
             Self {
@@ -249,11 +428,52 @@ impl Node {
     }
 }
 
+/// Child of a node, decoded from its flagged index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeChild {
+    Node(u32),
+    Subsector(u32),
+}
+
+impl From<u32> for NodeChild {
+    fn from(index: u32) -> Self {
+        if index & SUBSECTOR_FLAG != 0 {
+            Self::Subsector(index & !SUBSECTOR_FLAG)
+        } else {
+            Self::Node(index)
+        }
+    }
+}
+
+impl From<NodeChild> for u32 {
+    fn from(child: NodeChild) -> Self {
+        match child {
+            NodeChild::Node(index) => index,
+            NodeChild::Subsector(index) => index | SUBSECTOR_FLAG,
+        }
+    }
+}
+
+impl Node {
+    /// Child on the right (front) side of the partition line.
+    pub fn front(&self) -> NodeChild {
+        self.children[0].into()
+    }
+
+    /// Child on the left (back) side of the partition line.
+    pub fn back(&self) -> NodeChild {
+        self.children[1].into()
+    }
+}
+
 pub struct Nodes;
 impl Nodes {
     fn parse(i: &[u8]) -> OnlyResult<Vec<Node>> {
-        let (_, nodes) = many0(Node::parse)(i)?;
-        Ok(nodes)
+        if i.starts_with(DEEPBSP_MAGIC) {
+            run(preceded(tag(DEEPBSP_MAGIC), many0(Node::parse_extended)), i)
+        } else {
+            run(many0(Node::parse), i)
+        }
     }
 }
 
@@ -268,7 +488,7 @@ pub struct Sector<'a> {
 }
 
 impl<'a> Sector<'a> {
-    fn parse(i: &'a [u8]) -> ParseResult<Self> {
+    fn parse(i: &'a [u8]) -> ParseResult<'a, Self> {
         let (
             i,
             (
@@ -301,13 +521,305 @@ impl<'a> Sector<'a> {
 pub struct Sectors;
 impl Sectors {
     fn parse<'a>(i: &'a [u8]) -> OnlyResult<Vec<Sector<'a>>> {
-        let (_, sectors) = many0(Sector::parse)(i)?;
-        Ok(sectors)
+        run(many0(Sector::parse), i)
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelFormat {
+    /// Binary lumps starting with `THINGS`
+    Doom,
+    /// Binary lumps with `BEHAVIOR` among them
+    Hexen,
+    /// `TEXTMAP`...`ENDMAP`
+    Udmf,
+}
+
+/// Marker of a level and the lumps belonging to it, found by names rather than positions.
+#[derive(Clone, Debug)]
+pub struct LevelLumps<'a> {
+    pub marker: Lump<'a>,
+    pub format: LevelFormat,
+    /// Lumps following the marker in directory order, GL nodes marker included.
+    pub lumps: Vec<Lump<'a>>,
+}
+
+impl<'a> LevelLumps<'a> {
+    /// Checks whether `lumps` start with a level, i.e. any marker immediately followed
+    /// by `THINGS` or `TEXTMAP`. Binary level lasts while its lumps don't repeat,
+    /// UDMF one must be closed by `ENDMAP`.
+    pub fn at(lumps: &[Lump<'a>]) -> Option<OnlyResult<Self>> {
+        let (&marker, rest) = lumps.split_first()?;
+        let (format, len) = match rest.first()?.name {
+            "THINGS" => {
+                let len = Self::binary_len(marker.name, rest);
+                if rest[..len].iter().any(|lump| lump.name == BEHAVIOR) {
+                    (LevelFormat::Hexen, len)
+                } else {
+                    (LevelFormat::Doom, len)
+                }
+            }
+            UDMF_START => {
+                let len = rest
+                    .iter()
+                    .position(|lump| lump.name == UDMF_END)
+                    .map(|pos| pos + 1)
+                    .ok_or_else(|| Error::new(ErrorKind::Malformed, 0).with_lump(marker.name));
+                match len {
+                    Ok(len) => (LevelFormat::Udmf, len),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            _ => return None,
+        };
+        Some(Ok(Self {
+            marker,
+            format,
+            lumps: rest[..len].to_vec(),
+        }))
+    }
+
+    fn binary_len(name: &str, lumps: &[Lump<'_>]) -> usize {
+        let is_gl_marker = |lump: &Lump<'_>| {
+            lump.name
+                .strip_prefix("GL_")
+                .map_or(false, |gl_name| gl_name == name || gl_name == "LEVEL")
+        };
+        let mut seen = Vec::new();
+        let mut len = 0;
+        for lump in lumps {
+            let known = LEVEL_LUMPS.contains(&lump.name)
+                || GL_LUMPS.contains(&lump.name)
+                || is_gl_marker(lump);
+            if !known || seen.contains(&lump.name) {
+                break;
+            }
+            seen.push(lump.name);
+            len += 1;
+        }
+        len
+    }
+
+    /// Finds every level of the directory.
+    pub fn find<I>(lumps: I) -> OnlyResult<Vec<Self>>
+    where
+        I: IntoIterator<Item = Lump<'a>>,
+    {
+        let lumps: Vec<_> = lumps.into_iter().collect();
+        let mut levels = Vec::new();
+        let mut i = 0;
+        while i < lumps.len() {
+            match Self::at(&lumps[i..]) {
+                Some(level) => {
+                    let level = level?;
+                    i += level.lump_count();
+                    levels.push(level);
+                }
+                None => i += 1,
+            }
+        }
+        Ok(levels)
+    }
+
+    /// Number of lumps taken by the level, marker included.
+    pub fn lump_count(&self) -> usize {
+        self.lumps.len() + 1
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.marker.name
+    }
+
+    pub fn get<S: AsRef<str>>(&self, s: S) -> Option<Lump<'a>> {
+        let name = s.as_ref();
+        self.lumps.iter().copied().find(|lump| lump.name == name)
+    }
+
+    fn required(&self, name: &str) -> OnlyResult<Lump<'a>> {
+        self.get(name)
+            .ok_or_else(|| Error::new(ErrorKind::Malformed, 0).with_lump(self.name()))
+    }
+}
+
+fn parse_lump<'a, O, P>(lump: &Lump<'a>, parser: P) -> OnlyResult<O>
+where
+    P: FnOnce(&'a [u8]) -> OnlyResult<O>,
+{
+    parser(lump.data).map_err(|e| e.with_lump(lump.name))
+}
+
+/// Parses lump if it's present, the missing one is the same as an empty one.
+fn parse_optional<'a, O, P>(lump: Option<Lump<'a>>, parser: P) -> OnlyResult<Vec<O>>
+where
+    P: FnOnce(&'a [u8]) -> OnlyResult<Vec<O>>,
+{
+    lump.map_or_else(|| Ok(Vec::new()), |lump| parse_lump(&lump, parser))
+}
+
+fn write_words(w: &mut Vec<u8>, words: &[i16]) {
+    for word in words {
+        w.extend_from_slice(&word.to_le_bytes());
+    }
+}
+
+/// Narrows index into 16 bits of binary lumps, it must be below `limit`.
+fn narrow(index: u32, limit: u32, lump: &str) -> io::Result<i16> {
+    if index < limit {
+        Ok(index as u16 as i16)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} index {} doesn't fit into binary format", lump, index),
+        ))
+    }
+}
+
+fn write_lump<T, F>(items: &[T], mut write: F) -> io::Result<Vec<u8>>
+where
+    F: FnMut(&T, &mut Vec<u8>) -> io::Result<()>,
+{
+    let mut w = Vec::new();
+    for item in items {
+        write(item, &mut w)?;
+    }
+    Ok(w)
+}
+
+impl Thing {
+    fn write(&self, w: &mut Vec<u8>, format: LevelFormat) -> io::Result<()> {
+        if format == LevelFormat::Hexen {
+            write_words(
+                w,
+                &[
+                    self.tid,
+                    self.x_pos,
+                    self.y_pos,
+                    self.z_pos,
+                    self.angle,
+                    self.ttype,
+                    self.options,
+                ],
+            );
+            w.push(self.special);
+            w.extend_from_slice(&self.args);
+        } else {
+            write_words(
+                w,
+                &[self.x_pos, self.y_pos, self.angle, self.ttype, self.options],
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Linedef {
+    fn write(&self, w: &mut Vec<u8>, format: LevelFormat) -> io::Result<()> {
+        let vertex = |index| narrow(index, 0x1_0000, "VERTEXES");
+        let sidedef = |index: Option<u32>| {
+            index.map_or(Ok(NO_SIDEDEF as i16), |index| {
+                narrow(index, NO_SIDEDEF.into(), "SIDEDEFS")
+            })
+        };
+        let (start, end) = (vertex(self.vertex_start)?, vertex(self.vertex_end)?);
+        if format == LevelFormat::Hexen {
+            let special = u8::try_from(self.function).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Hexen special must fit a byte")
+            })?;
+            write_words(w, &[start, end, self.flags]);
+            w.push(special);
+            w.extend_from_slice(&self.args);
+        } else {
+            write_words(w, &[start, end, self.flags, self.function, self.tag]);
+        }
+        write_words(
+            w,
+            &[sidedef(self.sidedef_right)?, sidedef(self.sidedef_left)?],
+        );
+        Ok(())
+    }
+}
+
+impl Sidedef<'_> {
+    fn write(&self, w: &mut Vec<u8>) -> io::Result<()> {
+        write_words(w, &[self.x_offset, self.y_offset]);
+        write_name(&mut *w, self.upper_texture)?;
+        write_name(&mut *w, self.lower_texture)?;
+        write_name(&mut *w, self.mid_texture)?;
+        write_words(w, &[self.sector_ref]);
+        Ok(())
+    }
+}
+
+impl Segment {
+    fn write(&self, w: &mut Vec<u8>) -> io::Result<()> {
+        let vertex = |index| narrow(index, 0x1_0000, "VERTEXES");
+        // Minisegs of extended nodes have no linedef
+        let line_num = if self.line_num == u32::MAX {
+            -1
+        } else {
+            narrow(self.line_num, 0xFFFF, "LINEDEFS")?
+        };
+        write_words(
+            w,
+            &[
+                vertex(self.vertex_start)?,
+                vertex(self.vertex_end)?,
+                self.bams,
+                line_num,
+                self.segside,
+                self.segoffset,
+            ],
+        );
+        Ok(())
+    }
+}
+
+impl SubSector {
+    fn write(&self, w: &mut Vec<u8>) -> io::Result<()> {
+        write_words(
+            w,
+            &[
+                narrow(self.numsegs, 0x1_0000, "SEGS")?,
+                narrow(self.start_seg, 0x1_0000, "SEGS")?,
+            ],
+        );
+        Ok(())
+    }
+}
+
+impl Node {
+    fn write(&self, w: &mut Vec<u8>) -> io::Result<()> {
+        let child = |child| match child {
+            NodeChild::Node(index) => narrow(index, 0x8000, "NODES"),
+            NodeChild::Subsector(index) => {
+                narrow(index, 0x8000, "SSECTORS").map(|index| index | i16::MIN)
+            }
+        };
+        write_words(w, &[self.x, self.y, self.dx, self.dy]);
+        for bbox in &self.bbox {
+            write_words(w, &[bbox.top, bbox.bottom, bbox.left, bbox.right]);
+        }
+        write_words(w, &[child(self.front())?, child(self.back())?]);
+        Ok(())
+    }
+}
+
+impl Sector<'_> {
+    fn write(&self, w: &mut Vec<u8>) -> io::Result<()> {
+        write_words(w, &[self.floor_height, self.ceiling_height]);
+        write_name(&mut *w, self.floor_pic)?;
+        write_name(&mut *w, self.ceiling_pic)?;
+        write_words(w, &[self.light_level, self.special_sector, self.tag]);
+        Ok(())
+    }
+}
+
+/// Level of any format, binary or UDMF.
 pub struct Level<'a> {
     pub name: &'a str,
+    pub format: LevelFormat,
+    /// Compiled ACS scripts of Hexen level
+    pub behavior: Option<&'a [u8]>,
     pub things: Vec<Thing>,
     pub linedefs: Vec<Linedef>,
     pub sidedefs: Vec<Sidedef<'a>>,
@@ -316,37 +828,178 @@ pub struct Level<'a> {
     pub subsectors: Vec<SubSector>,
     pub nodes: Vec<Node>,
     pub sectors: Vec<Sector<'a>>,
+    pub reject: Option<Reject>,
+    pub blockmap: Option<Blockmap>,
+    /// glBSP nodes following the level
+    pub gl_nodes: Option<GlNodes>,
+    /// Fields of UDMF level the model lacks
+    pub udmf: Option<UdmfExtra<'a>>,
 }
 
 impl<'a> Level<'a> {
-    fn parse(level_lumps: &[&Lump<'a>]) -> OnlyResult<'a, Self> {
-        Ok(Self {
-            name: level_lumps[0].name,
-            things: Things::parse(level_lumps[THINGS_OFFSET].data)?,
-            linedefs: Linedefs::parse(level_lumps[LINEDEFS_OFFSET].data)?,
-            sidedefs: Sidedefs::parse(level_lumps[SIDEDEFS_OFFSET].data)?,
-            vertices: parse_vertices(level_lumps[VERTICES_OFFSET].data)?,
-            segments: Segments::parse(level_lumps[SEGMENTS_OFFSET].data)?,
-            subsectors: SubSectors::parse(level_lumps[SUBSECTORS_OFFSET].data)?,
-            nodes: Nodes::parse(level_lumps[NODES_OFFSET].data)?,
-            sectors: Sectors::parse(level_lumps[SECTORS_OFFSET].data)?,
-        })
+    /// Parses level, missing required lump is reported as [`ErrorKind::Malformed`]
+    /// of the marker, while missing (or empty) nodes, reject and blockmap are left empty.
+    /// Short reject is padded and broken blockmap is left empty as well.
+    /// ZDoom extended nodes take place of the regular (or GL) ones.
+    pub fn parse(level: &LevelLumps<'a>) -> OnlyResult<Self> {
+        let format = level.format;
+        let behavior = level.get(BEHAVIOR).map(|lump| lump.data);
+        if format == LevelFormat::Udmf {
+            let textmap = parse_lump(&level.required(UDMF_START)?, TextMap::parse)?;
+            let mut parsed = Self {
+                behavior,
+                gl_nodes: GlNodes::parse(level.lumps.iter().copied()).transpose()?,
+                ..Self::from_textmap(level.name(), textmap)
+            };
+            if let Some((lump, nodes)) = Self::extended_nodes(level, &["ZNODES"]) {
+                parsed.apply_extended(lump, nodes)?;
+            }
+            return Ok(parsed);
+        }
+        let [things, linedefs, sidedefs, vertices, sectors] = REQUIRED_LUMPS;
+        let linedefs = parse_lump(&level.required(linedefs)?, |i| Linedefs::parse(i, format))?;
+        let sectors = parse_lump(&level.required(sectors)?, Sectors::parse)?;
+        let reject = level
+            .get("REJECT")
+            .map(|lump| Reject::parse(lump.data, sectors.len()));
+        // Broken blockmap is left out for the caller to rebuild, rather than losing the level
+        let blockmap = level
+            .get("BLOCKMAP")
+            .filter(|lump| !lump.is_virtual())
+            .and_then(|lump| Blockmap::parse(lump.data, linedefs.len()).ok());
+        let extended = Self::extended_nodes(level, &["NODES", "SSECTORS"]);
+        let extended_lump = extended.as_ref().map(|(lump, _)| lump.name);
+        let vanilla = |name: &str| {
+            level
+                .get(name)
+                .filter(|lump| Some(lump.name) != extended_lump)
+        };
+        let deep = vanilla("NODES").map_or(false, |lump| lump.data.starts_with(DEEPBSP_MAGIC));
+        let mut parsed = Self {
+            name: level.name(),
+            format,
+            behavior,
+            things: parse_lump(&level.required(things)?, |i| Things::parse(i, format))?,
+            linedefs,
+            sidedefs: parse_lump(&level.required(sidedefs)?, Sidedefs::parse)?,
+            vertices: parse_lump(&level.required(vertices)?, parse_vertices)?,
+            segments: parse_optional(vanilla("SEGS"), |i| Segments::parse(i, deep))?,
+            subsectors: parse_optional(vanilla("SSECTORS"), |i| SubSectors::parse(i, deep))?,
+            nodes: parse_optional(vanilla("NODES"), Nodes::parse)?,
+            sectors,
+            reject,
+            blockmap,
+            gl_nodes: GlNodes::parse(level.lumps.iter().copied()).transpose()?,
+            udmf: None,
+        };
+        if let Some((lump, nodes)) = extended {
+            parsed.apply_extended(lump, nodes)?;
+        }
+        Ok(parsed)
+    }
+
+    /// Builds blockmap and reject the level was loaded without (missing or broken lumps),
+    /// with `sight` unset every sector sees the others instead of checking lines of sight.
+    pub fn build_missing(&mut self, sight: bool) {
+        if self.blockmap.is_none() {
+            self.blockmap = Some(Blockmap::build(&self.vertices, &self.linedefs));
+        }
+        if self.reject.is_none() {
+            self.reject = Some(if sight {
+                Reject::build(self)
+            } else {
+                Reject::visible(self.sectors.len())
+            });
+        }
+    }
+
+    /// Writes lumps of the level in its format, marker first. Binary level gets vanilla nodes
+    /// and empty `REJECT` or `BLOCKMAP` if it has none, UDMF one is written as `TEXTMAP`,
+    /// GL nodes are left out. Fails with [`io::ErrorKind::InvalidInput`] when an index
+    /// or a name doesn't fit into binary format.
+    pub fn to_lumps(&self) -> io::Result<Vec<(&'a str, Vec<u8>)>> {
+        let mut lumps = vec![(self.name, Vec::new())];
+        let format = self.format;
+        if format == LevelFormat::Udmf {
+            let mut textmap = Vec::new();
+            self.to_textmap().write_to(&mut textmap)?;
+            lumps.push((UDMF_START, textmap));
+            if let Some(behavior) = self.behavior {
+                lumps.push((BEHAVIOR, behavior.to_vec()));
+            }
+            lumps.push((UDMF_END, Vec::new()));
+            return Ok(lumps);
+        }
+
+        let mut blockmap = Vec::new();
+        if let Some(map) = &self.blockmap {
+            map.write_to(&mut blockmap, true)?;
+        }
+        let vertices = write_lump(&self.vertices, |&(x, y), w| {
+            write_words(w, &[x, y]);
+            Ok(())
+        })?;
+        lumps.extend(vec![
+            (
+                "THINGS",
+                write_lump(&self.things, |thing, w| thing.write(w, format))?,
+            ),
+            (
+                "LINEDEFS",
+                write_lump(&self.linedefs, |line, w| line.write(w, format))?,
+            ),
+            ("SIDEDEFS", write_lump(&self.sidedefs, Sidedef::write)?),
+            ("VERTEXES", vertices),
+            ("SEGS", write_lump(&self.segments, Segment::write)?),
+            ("SSECTORS", write_lump(&self.subsectors, SubSector::write)?),
+            ("NODES", write_lump(&self.nodes, Node::write)?),
+            ("SECTORS", write_lump(&self.sectors, Sector::write)?),
+            (
+                "REJECT",
+                self.reject
+                    .as_ref()
+                    .map_or_else(Vec::new, |reject| reject.data().to_vec()),
+            ),
+            ("BLOCKMAP", blockmap),
+        ]);
+        if format == LevelFormat::Hexen {
+            lumps.push((BEHAVIOR, self.behavior.unwrap_or_default().to_vec()));
+        }
+        Ok(lumps)
+    }
+
+    /// Finds the first of lumps holding ZDoom extended nodes.
+    fn extended_nodes(
+        level: &LevelLumps<'a>,
+        names: &[&str],
+    ) -> Option<(Lump<'a>, OnlyResult<ExtendedNodes>)> {
+        names
+            .iter()
+            .filter_map(|&name| level.get(name))
+            .find_map(|lump| ExtendedNodes::parse(lump.data).map(|nodes| (lump, nodes)))
+    }
+
+    fn apply_extended(
+        &mut self,
+        lump: Lump<'_>,
+        nodes: OnlyResult<ExtendedNodes>,
+    ) -> OnlyResult<()> {
+        nodes
+            .and_then(|nodes| nodes.apply_to(self))
+            .map_err(|e| e.with_lump(lump.name))
     }
 }
 
 pub struct Levels;
 impl Levels {
-    pub fn parse<'a, I>(lumps_iter: I) -> OnlyResult<'a, Vec<Level<'a>>>
+    /// Parses every level of the directory.
+    pub fn parse<'a, I>(lumps_iter: I) -> OnlyResult<Vec<Level<'a>>>
     where
-        I: IntoIterator<Item = &'a Lump<'a>>,
+        I: IntoIterator<Item = Lump<'a>>,
     {
-        let level_lumps: Vec<_> = lumps_iter
-            .into_iter()
-            .filter(|lump| is_level_name(lump.name) || LEVEL_LUMPS.contains(&lump.name))
-            .collect();
-        level_lumps
-            .chunks(LEVEL_LUMPS.len() + 1)
-            .map(|lumps| Level::parse(lumps))
+        LevelLumps::find(lumps_iter)?
+            .iter()
+            .map(Level::parse)
             .collect()
     }
 }
@@ -371,4 +1024,259 @@ mod tests {
             println!("    {:4} sectors", level.sectors.len());
         });
     }
+
+    #[test]
+    fn find_levels_by_lump_names() {
+        use super::{LevelFormat, LevelLumps, Levels};
+        use crate::{
+            error::ErrorKind,
+            wad::parser::file::{Archive, ArchiveBuilder, Type},
+        };
+
+        let mut builder = ArchiveBuilder::new(Type::PWAD).lump("PLAYPAL", vec![0; 768]);
+        for (marker, thing_size, linedef_size) in [("TESTMAP", 10, 14), ("E4M1", 20, 16)] {
+            builder = builder
+                .marker(marker)
+                .lump("THINGS", vec![0; thing_size])
+                .lump("VERTEXES", vec![0; 8])
+                .lump("LINEDEFS", vec![0; linedef_size])
+                .lump("SIDEDEFS", vec![0; 30])
+                .lump("SECTORS", vec![0; 26]);
+        }
+        let wad = builder
+            .lump("BEHAVIOR", b"ACS\0".to_vec())
+            .marker("GL_E4M1")
+            .lump("GL_VERT", b"gNd2".to_vec())
+            .marker("MAP01")
+            .lump("TEXTMAP", b"namespace = \"zdoom\";".to_vec())
+            .lump("ZNODES", vec![0; 4])
+            .marker("ENDMAP")
+            .marker("MAP02")
+            .lump("THINGS", vec![0; 10])
+            .lump("DEMO1", vec![0; 4])
+            .to_bytes()
+            .expect("Error writing wad");
+        let archive = Archive::parse(&wad).expect("Wad file parser error");
+
+        let levels = LevelLumps::find(archive.iter()).expect("Error finding levels");
+        let summary: Vec<_> = levels
+            .iter()
+            .map(|level| (level.name(), level.format, level.lump_count()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("TESTMAP", LevelFormat::Doom, 6),
+                ("E4M1", LevelFormat::Hexen, 9),
+                ("MAP01", LevelFormat::Udmf, 4),
+                ("MAP02", LevelFormat::Doom, 2),
+            ]
+        );
+        assert_eq!(
+            levels[1].get("GL_VERT").map(|lump| lump.data),
+            Some(&b"gNd2"[..])
+        );
+
+        let error = match Levels::parse(archive.iter()) {
+            Err(error) => error,
+            Ok(_) => panic!("Level without linedefs parsed"),
+        };
+        assert_eq!(error.kind(), Some(ErrorKind::Malformed));
+        assert_eq!(error.lump(), Some("MAP02"));
+
+        let level = Levels::parse(archive.iter().take(15)).expect("Error parsing levels");
+        assert_eq!(level.len(), 2);
+        assert_eq!(level[0].things.len(), 1);
+        assert_eq!(level[1].things.len(), 1);
+        assert_eq!(level[1].linedefs.len(), 1);
+        assert_eq!(level[1].behavior, Some(&b"ACS\0"[..]));
+        assert!(level[1].nodes.is_empty());
+
+        let unterminated = LevelLumps::find(archive.iter().take(18));
+        assert_eq!(
+            unterminated.err().and_then(|e| e.lump().map(str::to_owned)),
+            Some("MAP01".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_hexen_and_doom_fields() {
+        use super::{LevelFormat, Levels};
+        use crate::wad::parser::file::{Archive, ArchiveBuilder, Type};
+
+        let words = |words: &[i16]| -> Vec<u8> {
+            words.iter().flat_map(|word| word.to_le_bytes()).collect()
+        };
+        let hexen_thing = [
+            &words(&[7, -64, 128, 24, 90, 3001, 0x0107])[..],
+            &[80, 1, 2, 3, 4, 5],
+        ]
+        .concat();
+        let hexen_linedef = [
+            &words(&[1, 0, 0x0201])[..],
+            &[12, 6, 7, 8, 9, 10],
+            &words(&[0, -1]),
+        ]
+        .concat();
+        let wad = ArchiveBuilder::new(Type::PWAD)
+            .marker("MAP01")
+            .lump("THINGS", hexen_thing)
+            .lump("LINEDEFS", hexen_linedef)
+            .lump("SIDEDEFS", vec![0; 30])
+            .lump("VERTEXES", vec![0; 8])
+            .lump("SECTORS", vec![0; 26])
+            .lump("BEHAVIOR", b"ACS\0".to_vec())
+            .marker("MAP02")
+            .lump("THINGS", words(&[-32, 256, 180, 9, 0x0007]))
+            .lump("LINEDEFS", words(&[1, 0, 0x0004, 46, 5, 0, -1]))
+            .lump("SIDEDEFS", vec![0; 30])
+            .lump("VERTEXES", vec![0; 8])
+            .lump("SECTORS", vec![0; 26])
+            .to_bytes()
+            .expect("Error writing wad");
+        let archive = Archive::parse(&wad).expect("Wad file parser error");
+        let levels = Levels::parse(archive.iter()).expect("Error parsing levels");
+        assert_eq!(levels[0].format, LevelFormat::Hexen);
+        assert_eq!(levels[1].format, LevelFormat::Doom);
+
+        let thing = &levels[0].things[0];
+        assert_eq!(
+            (
+                thing.tid,
+                thing.x_pos,
+                thing.y_pos,
+                thing.z_pos,
+                thing.angle
+            ),
+            (7, -64, 128, 24, 90)
+        );
+        assert_eq!((thing.ttype, thing.options), (3001, 0x0107));
+        assert_eq!((thing.special, thing.args), (80, [1, 2, 3, 4, 5]));
+        let linedef = &levels[0].linedefs[0];
+        assert_eq!((linedef.vertex_start, linedef.vertex_end), (1, 0));
+        assert_eq!(
+            (linedef.flags, linedef.function, linedef.tag),
+            (0x0201, 12, 0)
+        );
+        assert_eq!(linedef.args, [6, 7, 8, 9, 10]);
+        assert_eq!(
+            (linedef.sidedef_right, linedef.sidedef_left),
+            (Some(0), None)
+        );
+
+        let thing = &levels[1].things[0];
+        assert_eq!(
+            (
+                thing.x_pos,
+                thing.y_pos,
+                thing.angle,
+                thing.ttype,
+                thing.options
+            ),
+            (-32, 256, 180, 9, 0x0007)
+        );
+        assert_eq!((thing.tid, thing.z_pos, thing.special), (0, 0, 0));
+        let linedef = &levels[1].linedefs[0];
+        assert_eq!(
+            (linedef.flags, linedef.function, linedef.tag),
+            (0x0004, 46, 5)
+        );
+        assert_eq!(linedef.args, [0; 5]);
+        assert_eq!(levels[1].behavior, None);
+    }
+
+    #[test]
+    fn load_broken_reject_and_blockmap() {
+        use super::Levels;
+        use crate::wad::parser::file::{Archive, ArchiveBuilder, Type};
+
+        let wad = ArchiveBuilder::new(Type::PWAD)
+            .marker("MAP01")
+            .lump("THINGS", vec![0; 10])
+            .lump("LINEDEFS", vec![0; 14])
+            .lump("SIDEDEFS", vec![0; 30])
+            .lump("VERTEXES", vec![0; 8])
+            .lump("SECTORS", vec![0; 26 * 4])
+            .lump("REJECT", vec![0xFF])
+            .lump("BLOCKMAP", vec![1, 2, 3])
+            .to_bytes()
+            .expect("Error writing wad");
+        let archive = Archive::parse(&wad).expect("Wad file parser error");
+        let mut levels = Levels::parse(archive.iter()).expect("Error parsing levels");
+
+        let reject = levels[0].reject.as_ref().expect("Reject not read");
+        assert!(!reject.can_see(0, 1));
+        assert!(reject.can_see(3, 3));
+        assert!(levels[0].blockmap.is_none());
+        levels[0].build_missing(true);
+        let blockmap = levels[0].blockmap.as_ref().expect("Blockmap not built");
+        assert_eq!(blockmap.cell(0, 0), Some(&[0][..]));
+    }
+
+    #[test]
+    fn parse_unsigned_and_deepbsp_indices() {
+        use super::{Linedef, NodeChild, Nodes, Segments, SubSectors, DEEPBSP_MAGIC};
+
+        let linedef = [&40000u16.to_le_bytes()[..], &[0; 8], &[0xFF; 4]].concat();
+        let (_, linedef) = Linedef::parse_doom(&linedef).expect("Error parsing linedef");
+        assert_eq!(linedef.vertex_start, 40000);
+        assert_eq!((linedef.sidedef_right, linedef.sidedef_left), (None, None));
+
+        let seg = [&70000u32.to_le_bytes()[..], &1u32.to_le_bytes(), &[0; 8]].concat();
+        let segs = Segments::parse(&seg, true).expect("Error parsing segs");
+        assert_eq!((segs[0].vertex_start, segs[0].vertex_end), (70000, 1));
+        let subsector = [&3u16.to_le_bytes()[..], &70000u32.to_le_bytes()].concat();
+        let subsectors = SubSectors::parse(&subsector, true).expect("Error parsing subsectors");
+        assert_eq!((subsectors[0].numsegs, subsectors[0].start_seg), (3, 70000));
+
+        let children = [&0x8000_0005u32.to_le_bytes()[..], &300u32.to_le_bytes()].concat();
+        let deep = [DEEPBSP_MAGIC, &[0; 24], &children].concat();
+        let vanilla = [
+            &[0; 24][..],
+            &0x8005u16.to_le_bytes(),
+            &300u16.to_le_bytes(),
+        ]
+        .concat();
+        for data in &[deep, vanilla] {
+            let nodes = Nodes::parse(data).expect("Error parsing nodes");
+            assert_eq!(nodes[0].front(), NodeChild::Subsector(5));
+            assert_eq!(nodes[0].back(), NodeChild::Node(300));
+        }
+    }
+
+    #[test]
+    fn write_levels_back() {
+        use super::Levels;
+        use crate::wad::parser::file::{Archive, ArchiveBuilder, Type};
+
+        let file = std::fs::read(env!("TEST_WAD")).expect("Error reading wad file");
+        let archive = Archive::parse(&file).expect("Wad file parser error");
+        let levels = Levels::parse(archive.iter()).expect("Error parsing levels");
+        let mut builder = ArchiveBuilder::new(Type::PWAD);
+        let mut written = Vec::new();
+        for level in &levels {
+            let lumps = level.to_lumps().expect("Error writing level");
+            assert_eq!(lumps[0].0, level.name);
+            for (name, data) in &lumps {
+                builder.add_lump(*name, data.clone());
+            }
+            written.push(lumps);
+        }
+        let things = written
+            .first()
+            .and_then(|lumps| lumps.iter().find(|(name, _)| *name == "THINGS"));
+        assert_eq!(
+            things.map(|(_, data)| data.as_slice()),
+            archive.get_by_name("THINGS").map(|lump| lump.data)
+        );
+
+        let wad = builder.to_bytes().expect("Error writing wad");
+        let archive = Archive::parse(&wad).expect("Wad file parser error");
+        let levels = Levels::parse(archive.iter()).expect("Error parsing written levels");
+        let rewritten: Vec<_> = levels
+            .iter()
+            .map(|level| level.to_lumps().expect("Error writing level"))
+            .collect();
+        assert_eq!(rewritten, written);
+    }
 }
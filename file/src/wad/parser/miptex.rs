@@ -0,0 +1,133 @@
+use super::{
+    name::parse_long_name,
+    playpal::{parse_embedded_pallete, Rgb},
+    types::{run, seek, OnlyResult, ParseError},
+};
+use crate::error::ErrorKind;
+use nom::{
+    bytes::complete::take,
+    combinator::{map_res, opt},
+    multi::count,
+    number::complete::le_u32,
+    sequence::tuple,
+};
+use std::convert::TryFrom;
+
+const MIP_LEVELS: usize = 4;
+
+/// Mipmapped wall texture of Quake's WAD2 and Half-Life's WAD3.
+pub struct MipTexture<'a> {
+    pub name: &'a str,
+    pub width: usize,
+    pub height: usize,
+    /// Row-major pixels of the full size image followed by 3 mip levels, each one halved
+    pub mips: [&'a [u8]; MIP_LEVELS],
+    /// Half-Life textures carry their own palette, Quake ones use the `PALETTE` lump
+    pub palette: Option<Vec<Rgb>>,
+}
+
+impl<'a> MipTexture<'a> {
+    pub fn parse(lump_i: &'a [u8]) -> OnlyResult<Self> {
+        run(
+            |i| {
+                let (i, (name, width, height)) = tuple((
+                    parse_long_name,
+                    map_res(le_u32, usize::try_from),
+                    map_res(le_u32, usize::try_from),
+                ))(i)?;
+                let (i, offsets) = count(map_res(le_u32, usize::try_from), MIP_LEVELS)(i)?;
+
+                let mut mips = [&lump_i[..0]; MIP_LEVELS];
+                let mut end = i;
+                for (level, &offset) in offsets.iter().enumerate() {
+                    let size = match (width >> level).checked_mul(height >> level) {
+                        Some(size) => size,
+                        None => return ParseError::fail(i, ErrorKind::Malformed),
+                    };
+                    let (_, mip_i) = seek(lump_i, offset, i)?;
+                    let (rest, mip) = take(size)(mip_i)?;
+                    mips[level] = mip;
+                    end = rest;
+                }
+                let (_, palette) = opt(parse_embedded_pallete)(end)?;
+                Ok((
+                    end,
+                    Self {
+                        name,
+                        width,
+                        height,
+                        mips,
+                        palette,
+                    },
+                ))
+            },
+            lump_i,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MipTexture;
+    use crate::wad::parser::file::{Archive, ArchiveBuilder, EntryInfo, Type};
+
+    fn miptex(name: &str, width: u32, height: u32, palette: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut raw_name = [0; 16];
+        raw_name[..name.len()].copy_from_slice(name.as_bytes());
+        data.extend_from_slice(&raw_name);
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        let mut offset = 40u32;
+        for level in 0..4 {
+            data.extend_from_slice(&offset.to_le_bytes());
+            offset += (width >> level) * (height >> level);
+        }
+        for level in 0..4 {
+            let size = (width >> level) * (height >> level);
+            data.resize(data.len() + size as usize, level as u8);
+        }
+        if palette {
+            data.extend_from_slice(&256u16.to_le_bytes());
+            data.extend((0..=255).flat_map(|x| vec![x, x, x]));
+            data.extend_from_slice(&[0, 0]);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_wad2_and_wad3_textures() {
+        for (wtype, ltype, palette) in [
+            (Type::WAD2, EntryInfo::MIPTEX, false),
+            (Type::WAD3, EntryInfo::MIPTEX_HL, true),
+        ] {
+            let wad = ArchiveBuilder::new(wtype)
+                .typed_lump(
+                    "+0BUTTON_LONG",
+                    ltype,
+                    miptex("+0button_long", 16, 8, palette),
+                )
+                .to_bytes()
+                .expect("Error writing wad");
+            let archive = Archive::parse(&wad).expect("Wad file parser error");
+            assert_eq!(archive.wtype, wtype);
+            assert_eq!(archive.entry_info(0).map(|info| info.ltype), Some(ltype));
+
+            let lump = archive
+                .get_by_name("+0BUTTON_LONG")
+                .expect("Texture not found");
+            let texture = MipTexture::parse(lump.data).expect("Error parsing miptex");
+            assert_eq!(texture.name, "+0button_long");
+            assert_eq!((texture.width, texture.height), (16, 8));
+            assert_eq!(
+                texture.mips.iter().map(|mip| mip.len()).collect::<Vec<_>>(),
+                [128, 32, 8, 2]
+            );
+            assert!(texture.mips[3].iter().all(|&x| x == 3));
+            assert_eq!(
+                texture.palette.map(|palette| palette[128]),
+                if palette { Some((128, 128, 128)) } else { None }
+            );
+        }
+    }
+}
@@ -1,16 +1,113 @@
+pub mod blockmap;
 pub mod colormap;
 pub mod file;
 pub mod flat;
+pub mod glnodes;
 pub mod level;
+pub mod miptex;
 pub mod name;
 pub mod picture;
 pub mod playpal;
 pub mod pnames;
+pub mod qpic;
+pub mod reject;
 pub mod texture;
+pub mod udmf;
+pub mod znodes;
+pub mod ztextures;
 
 mod types {
+    use crate::error::{Error, ErrorKind};
+    use nom::{
+        error::{self, FromExternalError},
+        Offset,
+    };
+    use std::convert::TryFrom;
+
     pub type Input<'a> = &'a [u8];
-    pub type ParseError<'a> = nom::error::VerboseError<Input<'a>>;
     pub type ParseResult<'a, O> = nom::IResult<Input<'a>, O, ParseError<'a>>;
-    pub type OnlyResult<'a, O> = Result<O, nom::Err<ParseError<'a>>>;
+    pub type OnlyResult<O> = crate::error::Result<O>;
+
+    /// Innermost parser error, converted into [`Error`] once the parsed input is known.
+    #[derive(Debug)]
+    pub struct ParseError<'a> {
+        pub input: Input<'a>,
+        pub kind: ErrorKind,
+    }
+
+    impl<'a> ParseError<'a> {
+        pub const fn new(input: Input<'a>, kind: ErrorKind) -> Self {
+            Self { input, kind }
+        }
+
+        pub fn fail<O>(input: Input<'a>, kind: ErrorKind) -> ParseResult<'a, O> {
+            Err(nom::Err::Error(Self::new(input, kind)))
+        }
+
+        fn into_error(self, base: Input<'a>) -> Error {
+            let base_range = base.as_ptr_range();
+            let offset = if base_range.contains(&self.input.as_ptr()) {
+                base.offset(self.input)
+            } else {
+                base.len()
+            };
+            Error::new(self.kind, offset)
+        }
+    }
+
+    impl<'a> error::ParseError<Input<'a>> for ParseError<'a> {
+        fn from_error_kind(input: Input<'a>, kind: error::ErrorKind) -> Self {
+            let kind = match kind {
+                error::ErrorKind::Eof => ErrorKind::Truncated,
+                error::ErrorKind::Tag => ErrorKind::BadMagic,
+                _ => ErrorKind::Malformed,
+            };
+            Self::new(input, kind)
+        }
+
+        fn append(_: Input<'a>, _: error::ErrorKind, other: Self) -> Self {
+            other
+        }
+    }
+
+    impl<'a, E> FromExternalError<Input<'a>, E> for ParseError<'a> {
+        fn from_external_error(input: Input<'a>, _: error::ErrorKind, _: E) -> Self {
+            Self::new(input, ErrorKind::Malformed)
+        }
+    }
+
+    /// Returns `base` starting at `offset`, failing on `at` when offset lies outside.
+    pub fn seek<'a>(base: Input<'a>, offset: usize, at: Input<'a>) -> ParseResult<'a, Input<'a>> {
+        match base.get(offset..) {
+            Some(i) => Ok((at, i)),
+            None => ParseError::fail(at, ErrorKind::OutOfBounds),
+        }
+    }
+
+    /// Parses a number of items following it, which can't be negative or exceed the rest of input.
+    pub fn item_count<'a, N, P>(mut parser: P) -> impl FnMut(Input<'a>) -> ParseResult<'a, usize>
+    where
+        P: FnMut(Input<'a>) -> ParseResult<'a, N>,
+        usize: TryFrom<N>,
+    {
+        move |i| {
+            let (rest, n) = parser(i)?;
+            match usize::try_from(n) {
+                Ok(n) if n <= rest.len() => Ok((rest, n)),
+                _ => ParseError::fail(i, ErrorKind::Malformed),
+            }
+        }
+    }
+
+    /// Runs parser on the input and keeps only its output.
+    pub fn run<'a, O, P>(mut parser: P, i: Input<'a>) -> OnlyResult<O>
+    where
+        P: FnMut(Input<'a>) -> ParseResult<'a, O>,
+    {
+        match parser(i) {
+            Ok((_, out)) => Ok(out),
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e.into_error(i)),
+            Err(nom::Err::Incomplete(_)) => Err(Error::new(ErrorKind::Truncated, i.len())),
+        }
+    }
 }
@@ -1,17 +1,38 @@
-use super::types::ParseResult;
-use nom::{
-    bytes::complete::{take, take_till},
-    combinator::map_res,
-};
+use super::types::{ParseError, ParseResult};
+use crate::error::ErrorKind;
+use nom::bytes::complete::take;
+use std::io::{self, Write};
 
 const NAME_LEN: usize = 8;
+const LONG_NAME_LEN: usize = 16;
 
-fn take_cstr(i: &[u8], size: usize) -> ParseResult<&str> {
-    let (i, cstr) = take(size)(i)?;
-    let (_, cstr) = map_res(take_till(|x| x == 0), std::str::from_utf8)(cstr)?;
-    Ok((i, cstr))
+fn take_cstr(i: &[u8], size: usize) -> ParseResult<'_, &str> {
+    let (rest, cstr): (_, &[u8]) = take(size)(i)?;
+    let len = cstr.iter().position(|&x| x == 0).unwrap_or(size);
+    match std::str::from_utf8(&cstr[..len]) {
+        Ok(cstr) => Ok((rest, cstr)),
+        Err(_) => ParseError::fail(i, ErrorKind::InvalidName),
+    }
 }
 
-pub fn parse_name(i: &[u8]) -> ParseResult<&str> {
+pub fn parse_name(i: &[u8]) -> ParseResult<'_, &str> {
     take_cstr(i, NAME_LEN)
 }
+
+/// Writes name padded with zeros, failing on the one longer than 8 bytes.
+pub fn write_name<W: Write>(mut w: W, name: &str) -> io::Result<()> {
+    if name.len() > NAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid name: {:?}", name),
+        ));
+    }
+    let mut raw = [0; NAME_LEN];
+    raw[..name.len()].copy_from_slice(name.as_bytes());
+    w.write_all(&raw)
+}
+
+/// Parses 16-byte name used by Quake and Half-Life formats.
+pub fn parse_long_name(i: &[u8]) -> ParseResult<'_, &str> {
+    take_cstr(i, LONG_NAME_LEN)
+}
@@ -1,22 +1,70 @@
-use super::types::{OnlyResult, ParseResult};
+use super::types::{run, seek, OnlyResult, ParseResult};
 use nom::{
-    bytes::complete::take,
-    combinator::{map_res, verify},
+    combinator::verify,
     multi::{count, many0},
     number::complete::{le_i16, le_i32, le_u8},
     sequence::tuple,
 };
+use std::{collections::HashMap, convert::TryFrom, io};
+
+/// Longest post written, as vanilla tools do.
+const MAX_POST_LENGTH: usize = 128;
+/// Highest `rowstart`, 255 ends the column.
+const MAX_ROWSTART: usize = 254;
 
 struct Post {
-    rowstart: u8,
+    /// Row of the first pixel
+    top: usize,
     pixels: Vec<u8>,
 }
 
 impl Post {
-    fn parse(i: &[u8]) -> ParseResult<Self> {
+    fn parse(i: &[u8]) -> ParseResult<'_, (u8, Vec<u8>)> {
         let (i, (rowstart, num_pixels, _)) = tuple((le_u8, le_u8, le_u8))(i)?;
         let (i, (pixels, _)) = tuple((count(le_u8, num_pixels as usize), le_u8))(i)?;
-        Ok((i, Self { rowstart, pixels }))
+        Ok((i, (rowstart, pixels)))
+    }
+
+    /// Resolves `rowstart`s of the column, one not below the previous post's top being
+    /// relative to it as in DeePsea tall patches.
+    fn column(posts: Vec<(u8, Vec<u8>)>) -> Vec<Self> {
+        let mut top = None;
+        posts
+            .into_iter()
+            .map(|(rowstart, pixels)| {
+                let rowstart = usize::from(rowstart);
+                let post_top = match top {
+                    Some(top) if rowstart <= top => top + rowstart,
+                    _ => rowstart,
+                };
+                top = Some(post_top);
+                Self {
+                    top: post_top,
+                    pixels,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Appends a post of `pixels` starting at row `start`, preceded by empty posts
+/// when the row can't be reached from the previous `top`.
+fn write_post(column: &mut Vec<u8>, top: &mut Option<usize>, start: usize, pixels: &[u8]) {
+    loop {
+        let (rowstart, post_top, last) = match *top {
+            _ if start <= MAX_ROWSTART => (start, start, true),
+            Some(top) if start - top <= top.min(MAX_ROWSTART) => (start - top, start, true),
+            Some(top) if top >= MAX_ROWSTART => (MAX_ROWSTART, top + MAX_ROWSTART, false),
+            _ => (MAX_ROWSTART, MAX_ROWSTART, false),
+        };
+        *top = Some(post_top);
+        let pixels = if last { pixels } else { &[] };
+        column.extend_from_slice(&[rowstart as u8, pixels.len() as u8, 0]);
+        column.extend_from_slice(pixels);
+        column.push(0);
+        if last {
+            return;
+        }
     }
 }
 
@@ -30,35 +78,111 @@ pub struct Picture {
 
 impl Picture {
     pub fn parse(lump_i: &[u8]) -> OnlyResult<Self> {
-        let (i, (width, height, left_offset, top_offset)) =
-            tuple((le_i16, le_i16, le_i16, le_i16))(lump_i)?;
-        let (_, columns) = count(
-            map_res(le_i32, |offset| {
-                let (data_i, _) = take(offset as usize)(lump_i)?;
-                many0(verify(Post::parse, |post| post.rowstart != 255))(data_i)
-                    .map(|(_, post)| post)
-            }),
-            width as usize,
-        )(i)?;
-        Ok(Self {
-            width,
-            height,
-            left_offset,
-            top_offset,
-            columns,
-        })
+        run(
+            |i| {
+                let (i, (width, height, left_offset, top_offset)) =
+                    tuple((verify(le_i16, |&x| x >= 0), le_i16, le_i16, le_i16))(i)?;
+                let (i, columns) = count(
+                    |i| {
+                        let (i, offset) = le_i32(i)?;
+                        let (_, data_i) = seek(lump_i, offset as usize, i)?;
+                        let (_, column) =
+                            many0(verify(Post::parse, |&(rowstart, _)| rowstart != 255))(data_i)?;
+                        Ok((i, Post::column(column)))
+                    },
+                    width as usize,
+                )(i)?;
+                Ok((
+                    i,
+                    Self {
+                        width,
+                        height,
+                        left_offset,
+                        top_offset,
+                        columns,
+                    },
+                ))
+            },
+            lump_i,
+        )
+    }
+
+    /// Encodes column-major `pixels`, `None` being transparent, into a patch with offsets
+    /// `(left, top)`. Identical columns are stored once, rows past 254 use DeePsea tall
+    /// patch posts.
+    pub fn encode(
+        width: usize,
+        height: usize,
+        offsets: (i16, i16),
+        pixels: &[Option<u8>],
+    ) -> io::Result<Vec<u8>> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let width16 = i16::try_from(width).map_err(|_| invalid("Picture is too wide"))?;
+        let height16 = i16::try_from(height).map_err(|_| invalid("Picture is too tall"))?;
+        if pixels.len() != width * height {
+            return Err(invalid("Pixel count doesn't match picture size"));
+        }
+
+        let mut data = Vec::new();
+        for &value in &[width16, height16, offsets.0, offsets.1] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        let header_size = data.len() + 4 * width;
+        let mut columns = Vec::new();
+        let mut known: HashMap<Vec<u8>, usize> = HashMap::new();
+        for x in 0..width {
+            let source = &pixels[x * height..(x + 1) * height];
+            let mut column = Vec::new();
+            let mut top = None;
+            let mut row = 0;
+            while row < height {
+                if source[row].is_none() {
+                    row += 1;
+                    continue;
+                }
+                let post: Vec<u8> = source[row..]
+                    .iter()
+                    .take(MAX_POST_LENGTH)
+                    .map_while(|&pixel| pixel)
+                    .collect();
+                write_post(&mut column, &mut top, row, &post);
+                row += post.len();
+            }
+            column.push(0xFF);
+
+            let offset = match known.get(&column) {
+                Some(&offset) => offset,
+                None => {
+                    let offset = header_size + columns.len();
+                    columns.extend_from_slice(&column);
+                    known.insert(column, offset);
+                    offset
+                }
+            };
+            let offset = i32::try_from(offset).map_err(|_| invalid("Picture is too large"))?;
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data.extend(columns);
+        Ok(data)
+    }
+
+    /// Posts of the column as their starting rows and pixels.
+    pub(crate) fn posts(&self, column: usize) -> impl Iterator<Item = (usize, &[u8])> {
+        self.columns
+            .get(column)
+            .into_iter()
+            .flatten()
+            .map(|post| (post.top, post.pixels.as_slice()))
     }
 
     pub fn into_matrix(self) -> Vec<Vec<u8>> {
         let (width, height) = (self.width as usize, self.height as usize);
         let mut output = vec![vec![!0; height]; width];
-        for x in 0..width {
-            let out_column = &mut output[x];
-            let column = &self.columns[x];
-            column.into_iter().for_each(|post| {
-                let pixels = post.pixels.iter().cloned();
-                let rowstart = post.rowstart as usize;
-                out_column.splice(rowstart..rowstart + pixels.len(), pixels);
+        for (out_column, column) in output.iter_mut().zip(&self.columns) {
+            column.iter().for_each(|post| {
+                let rows = out_column.iter_mut().skip(post.top);
+                rows.zip(&post.pixels)
+                    .for_each(|(out, &pixel)| *out = pixel);
             })
         }
         output
@@ -76,11 +200,7 @@ mod tests {
             .expect("Error getting output dir's path");
         let archive =
             crate::wad::parser::file::Archive::parse(&file).expect("Wad file parser error");
-        let sprites = archive
-            .iter()
-            .skip_while(|lump| lump.name != "S_START")
-            .take_while(|lump| lump.name != "S_END")
-            .filter(|lump| !lump.is_virtual());
+        let sprites = archive.namespace(crate::wad::namespace::Namespace::Sprites);
 
         let playpal_lump = archive.get_by_name("PLAYPAL").expect("PLAYPAL not found");
         let playpal = crate::wad::parser::playpal::parse_playpal(playpal_lump.data)
@@ -89,14 +209,13 @@ mod tests {
 
         sprites.into_iter().for_each(|lump| {
             let image = super::Picture::parse(lump.data)
-                .expect(format!("Error parsing {}", lump.name).as_str());
+                .unwrap_or_else(|_| panic!("Error parsing {}", lump.name));
             let matrix = image.into_matrix();
 
             let mut img_buf = image::ImageBuffer::new(matrix.len() as u32, matrix[0].len() as u32);
-            for x in 0..matrix.len() {
-                for y in 0..matrix[0].len() {
+            for (x, column) in matrix.iter().enumerate() {
+                for (y, &index) in column.iter().enumerate() {
                     let pixel = img_buf.get_pixel_mut(x as u32, y as u32);
-                    let index = matrix[x][y];
                     let color = if index == !0 {
                         (0, 0, 0, 0)
                     } else {
@@ -110,11 +229,43 @@ mod tests {
             let output_path = output_dir.join(format!("{}.png", lump.name));
             img_buf
                 .save(&output_path)
-                .expect(&format!("Error saving {}", lump.name));
+                .unwrap_or_else(|_| panic!("Error saving {}", lump.name));
             println!(
                 "Saved {}",
                 output_path.to_str().expect("Error converting path to str")
             );
         });
     }
+
+    #[test]
+    fn encode_tall_and_repeated_columns() {
+        let (width, height) = (3, 600);
+        let mut pixels = vec![None; width * height];
+        for x in 0..2 {
+            for y in (10..300).chain(550..560) {
+                pixels[x * height + y] = Some((y % 256) as u8);
+            }
+        }
+        pixels[2 * height + 254] = Some(7);
+
+        let data = super::Picture::encode(width, height, (4, -2), &pixels)
+            .expect("Error encoding picture");
+        let offset = |x: usize| &data[8 + 4 * x..12 + 4 * x];
+        assert_eq!(offset(0), offset(1));
+
+        let picture = super::Picture::parse(&data).expect("Error parsing picture");
+        assert_eq!((picture.left_offset, picture.top_offset), (4, -2));
+        assert!(picture.posts(0).all(|(_, post)| post.len() <= 128));
+        let mut decoded = vec![None; width * height];
+        for x in 0..width {
+            for (top, post) in picture.posts(x) {
+                for (y, &pixel) in post.iter().enumerate() {
+                    decoded[x * height + top + y] = Some(pixel);
+                }
+            }
+        }
+        assert_eq!(decoded, pixels);
+
+        assert!(super::Picture::encode(2, 2, (0, 0), &[None; 3]).is_err());
+    }
 }
@@ -1,20 +1,36 @@
-use super::types::{OnlyResult, ParseResult};
-use nom::{combinator::map_res, multi::count, number::complete::le_u8, sequence::tuple};
+use super::types::{run, OnlyResult, ParseResult};
+use nom::{
+    combinator::{map, map_res},
+    multi::{count, length_count},
+    number::complete::{le_u16, le_u8},
+    sequence::tuple,
+};
 use std::convert::TryInto;
 
 pub type Rgb = (u8, u8, u8);
 pub type Pallete = [Rgb; 256];
 pub type PlayPal = [Pallete; 14];
 
-fn parse_pallete(i: &[u8]) -> ParseResult<Pallete> {
-    map_res(count(tuple((le_u8, le_u8, le_u8)), 256), |res| {
-        res.try_into()
-    })(i)
+fn parse_rgb(i: &[u8]) -> ParseResult<'_, Rgb> {
+    tuple((le_u8, le_u8, le_u8))(i)
+}
+
+fn parse_pallete(i: &[u8]) -> ParseResult<'_, Pallete> {
+    map_res(count(parse_rgb, 256), |res| res.try_into())(i)
+}
+
+/// Parses palette of variable size prefixed with its number of colors as Half-Life does.
+pub(super) fn parse_embedded_pallete(i: &[u8]) -> ParseResult<'_, Vec<Rgb>> {
+    length_count(map(le_u16, usize::from), parse_rgb)(i)
 }
 
 pub fn parse_playpal(i: &[u8]) -> OnlyResult<PlayPal> {
-    let (_, out) = map_res(count(parse_pallete, 14), |res| res.try_into())(i)?;
-    Ok(out)
+    run(map_res(count(parse_pallete, 14), |res| res.try_into()), i)
+}
+
+/// Parses single palette lump like Quake's `PALETTE`.
+pub fn parse_single_pallete(i: &[u8]) -> OnlyResult<Pallete> {
+    run(parse_pallete, i)
 }
 
 #[cfg(test)]
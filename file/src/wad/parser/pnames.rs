@@ -1,10 +1,12 @@
 // TODO : merge into another parser
-use super::{name::parse_name, types::OnlyResult};
-use nom::{combinator::map, multi::length_count, number::complete::le_i32};
+use super::{
+    name::parse_name,
+    types::{item_count, run, OnlyResult},
+};
+use nom::{multi::length_count, number::complete::le_i32};
 
 pub fn parse_pnames(i: &[u8]) -> OnlyResult<Vec<&str>> {
-    let (_, pnames) = length_count(map(le_i32, |x| x as usize), parse_name)(i)?;
-    Ok(pnames)
+    run(length_count(item_count(le_i32), parse_name), i)
 }
 
 #[cfg(test)]
@@ -0,0 +1,72 @@
+use super::{
+    playpal::{parse_embedded_pallete, Rgb},
+    types::{run, OnlyResult},
+};
+use nom::{
+    bytes::complete::take,
+    combinator::{map_res, opt},
+    number::complete::le_u32,
+    sequence::tuple,
+};
+use std::convert::TryFrom;
+
+/// Plain picture of Quake's WAD2 and Half-Life's WAD3, e.g. status bar graphics.
+pub struct QPic<'a> {
+    pub width: usize,
+    pub height: usize,
+    /// Row-major pixels
+    pub pixels: &'a [u8],
+    /// Present in Half-Life pictures only
+    pub palette: Option<Vec<Rgb>>,
+}
+
+impl<'a> QPic<'a> {
+    pub fn parse(lump_i: &'a [u8]) -> OnlyResult<Self> {
+        run(
+            |i| {
+                let (i, (width, height)) = tuple((
+                    map_res(le_u32, usize::try_from),
+                    map_res(le_u32, usize::try_from),
+                ))(i)?;
+                let (i, pixels) = take(width.saturating_mul(height))(i)?;
+                let (i, palette) = opt(parse_embedded_pallete)(i)?;
+                Ok((
+                    i,
+                    Self {
+                        width,
+                        height,
+                        pixels,
+                        palette,
+                    },
+                ))
+            },
+            lump_i,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn parse_qpic_with_palette() {
+        let mut lump = Vec::new();
+        lump.extend_from_slice(&3u32.to_le_bytes());
+        lump.extend_from_slice(&2u32.to_le_bytes());
+        lump.extend_from_slice(&[0, 1, 2, 3, 4, 5]);
+        lump.extend_from_slice(&2u16.to_le_bytes());
+        lump.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+
+        let qpic = super::QPic::parse(&lump[..14]).expect("Error parsing qpic");
+        assert_eq!((qpic.width, qpic.height), (3, 2));
+        assert_eq!(qpic.pixels, [0, 1, 2, 3, 4, 5]);
+        assert!(qpic.palette.is_none());
+
+        let qpic = super::QPic::parse(&lump).expect("Error parsing qpic");
+        assert_eq!(qpic.palette, Some(vec![(255, 0, 0), (0, 0, 255)]));
+
+        let error = super::QPic::parse(&lump[..10]).err();
+        assert_eq!(error.and_then(|e| e.kind()), Some(ErrorKind::Truncated));
+    }
+}
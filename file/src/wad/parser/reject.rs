@@ -0,0 +1,462 @@
+use super::level::Level;
+
+/// Distance in map units within which points count as lying on a line.
+const EPSILON: f64 = 1e-3;
+/// Portals passed while flowing from one sector before giving up on it,
+/// every sector connected to it is visible then.
+const MAX_FLOW_STEPS: usize = 1 << 12;
+
+type Point = (f64, f64);
+type Segment = (Point, Point);
+
+/// Signed distance from the line through `a` and `b` to `p`, positive on its left.
+fn side((a, b): Segment, p: Point) -> f64 {
+    let length = (b.0 - a.0).hypot(b.1 - a.1);
+    if length == 0.0 {
+        return 0.0;
+    }
+    ((b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)) / length
+}
+
+/// Keeps the part of `segment` at least `margin` to the left of `line` (or to the right
+/// with `left` unset), `None` if nothing of non-zero length is left.
+fn clip(segment: Segment, line: Segment, left: bool, margin: f64) -> Option<Segment> {
+    let sign = if left { 1.0 } else { -1.0 };
+    let (a, b) = segment;
+    let (da, db) = (sign * side(line, a) - margin, sign * side(line, b) - margin);
+    let at = |t: f64| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+    let clipped = match (da >= 0.0, db >= 0.0) {
+        (true, true) => segment,
+        (false, false) => return None,
+        (true, false) => (a, at(da / (da - db))),
+        (false, true) => (at(da / (da - db)), b),
+    };
+    let (a, b) = clipped;
+    Some(clipped).filter(|_| (b.0 - a.0).hypot(b.1 - a.1) > EPSILON)
+}
+
+/// Part of `target` some line passing `source` and then `pass` may reach: lines through
+/// an end of each one with the other ends on opposite sides bound them.
+fn separate(mut target: Segment, source: Segment, pass: Segment) -> Option<Segment> {
+    for &(s, other_s) in &[(source.0, source.1), (source.1, source.0)] {
+        for &(p, other_p) in &[(pass.0, pass.1), (pass.1, pass.0)] {
+            let separator = (s, p);
+            let (source_side, pass_side) = (side(separator, other_s), side(separator, other_p));
+            if source_side.abs() > EPSILON
+                && pass_side.abs() > EPSILON
+                && (source_side > 0.0) != (pass_side > 0.0)
+            {
+                target = clip(target, separator, pass_side > 0.0, -EPSILON)?;
+            }
+        }
+    }
+    Some(target)
+}
+
+/// Finds the root of the set in disjoint set forest.
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Portals of every sector, oriented to have it on the right, with sectors behind them.
+struct Flow {
+    portals: Vec<Vec<(Segment, usize)>>,
+    visible: Vec<bool>,
+    path: Vec<bool>,
+    steps: usize,
+}
+
+impl Flow {
+    /// Marks sectors behind portals of `sector` which lines passing `source` and `pass`
+    /// reach, returns `false` once out of steps.
+    fn run(&mut self, sector: usize, source: Segment, pass: Segment) -> bool {
+        for i in 0..self.portals[sector].len() {
+            let (portal, next) = self.portals[sector][i];
+            if self.path[next] {
+                continue;
+            }
+            self.steps += 1;
+            if self.steps > MAX_FLOW_STEPS {
+                return false;
+            }
+            let target = clip(portal, pass, true, EPSILON)
+                .and_then(|target| clip(target, source, true, EPSILON))
+                .and_then(|target| separate(target, source, pass));
+            if let Some(target) = target {
+                self.visible[next] = true;
+                self.path[next] = true;
+                let finished = self.run(next, source, target);
+                self.path[next] = false;
+                if !finished {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Bit matrix of sector pairs, set bit meaning the monster in the first sector
+/// can't see anything in the second one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reject {
+    sectors: usize,
+    data: Vec<u8>,
+}
+
+impl Reject {
+    /// Size of the matrix for the given number of sectors in bytes.
+    pub const fn size(sectors: usize) -> usize {
+        (sectors * sectors + 7) / 8
+    }
+
+    /// Matrix of the given size with every sector seeing the others.
+    pub fn visible(sectors: usize) -> Self {
+        Self {
+            sectors,
+            data: vec![0; Self::size(sectors)],
+        }
+    }
+
+    /// Reads matrix of the level with `sectors` sectors, lump is kept as given
+    /// with padding some nodebuilders add. Pairs past the end of a short lump reject
+    /// nothing like in source ports, see [`is_complete`](Self::is_complete).
+    pub fn parse(i: &[u8], sectors: usize) -> Self {
+        Self {
+            sectors,
+            data: i.to_vec(),
+        }
+    }
+
+    /// Checks whether the matrix covers every pair of sectors.
+    pub fn is_complete(&self) -> bool {
+        self.data.len() >= Self::size(self.sectors)
+    }
+
+    /// Builds matrix by sight checks between sectors, heights ignored.
+    /// Every line of sight from one sector to another passes a chain of two-sided lines,
+    /// so chains some straight line may pass are followed from every sector, narrowing
+    /// each next line to the part reachable from the first and the last one passed.
+    /// Walls inside sectors aren't taken into account and sectors flowing through too
+    /// many chains see their whole area, so the matrix never rejects a visible sector.
+    pub fn build(level: &Level<'_>) -> Self {
+        let sectors = level.sectors.len();
+        let mut reject = Self::visible(sectors);
+
+        let mut parents: Vec<_> = (0..sectors).collect();
+        let mut portals = vec![Vec::new(); sectors];
+        for linedef in &level.linedefs {
+            let ((ax, ay), (bx, by)) = match linedef.ends(&level.vertices) {
+                Some(ends) => ends,
+                None => continue,
+            };
+            let (a, b): (Point, Point) = ((ax.into(), ay.into()), (bx.into(), by.into()));
+            if let (Some(front), Some(back)) = linedef.sectors(&level.sidedefs) {
+                if front != back && front.max(back) < sectors {
+                    portals[front].push(((a, b), back));
+                    portals[back].push(((b, a), front));
+                    let (front_root, back_root) =
+                        (root(&mut parents, front), root(&mut parents, back));
+                    parents[front_root] = back_root;
+                }
+            }
+        }
+
+        let mut flow = Flow {
+            portals,
+            visible: vec![false; sectors],
+            path: vec![false; sectors],
+            steps: 0,
+        };
+        for from in 0..sectors {
+            flow.visible.iter_mut().for_each(|visible| *visible = false);
+            flow.visible[from] = true;
+            flow.path[from] = true;
+            flow.steps = 0;
+            let mut finished = true;
+            for i in 0..flow.portals[from].len() {
+                let (source, next) = flow.portals[from][i];
+                flow.visible[next] = true;
+                flow.path[next] = true;
+                finished = flow.run(next, source, source);
+                flow.path[next] = false;
+                if !finished {
+                    break;
+                }
+            }
+            flow.path[from] = false;
+            let from_root = root(&mut parents, from);
+            for to in 0..sectors {
+                let visible = if finished {
+                    flow.visible[to]
+                } else {
+                    root(&mut parents, to) == from_root
+                };
+                reject.set_visible(from, to, visible);
+            }
+        }
+        // Sight is mutual, though flows from both ends narrow the lines differently
+        for from in 0..sectors {
+            for to in from + 1..sectors {
+                if reject.can_see(from, to) || reject.can_see(to, from) {
+                    reject.set_visible(from, to, true);
+                    reject.set_visible(to, from, true);
+                }
+            }
+        }
+        reject
+    }
+
+    pub const fn sectors(&self) -> usize {
+        self.sectors
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn bit(&self, from: usize, to: usize) -> Option<(usize, u8)> {
+        if from < self.sectors && to < self.sectors {
+            let bit = from * self.sectors + to;
+            Some((bit / 8, 1 << (bit % 8)))
+        } else {
+            None
+        }
+    }
+
+    /// Checks whether sector `to` is visible from `from`, sectors out of matrix are always visible.
+    pub fn can_see(&self, from: usize, to: usize) -> bool {
+        self.bit(from, to)
+            .and_then(|(byte, mask)| Some(self.data.get(byte)? & mask == 0))
+            .unwrap_or(true)
+    }
+
+    /// Marks sector `to` as visible or not from `from`, returns `false` if any is out of matrix.
+    pub fn set_visible(&mut self, from: usize, to: usize, visible: bool) -> bool {
+        match self.bit(from, to) {
+            Some((byte, mask)) => {
+                if visible {
+                    if let Some(data) = self.data.get_mut(byte) {
+                        *data &= !mask;
+                    }
+                } else {
+                    if byte >= self.data.len() {
+                        self.data.resize(Self::size(self.sectors), 0);
+                    }
+                    self.data[byte] |= mask;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reject;
+
+    #[test]
+    fn lookup_sector_pairs() {
+        // 3 sectors, sector 0 can't see sector 2 and sector 2 can't see sector 1
+        let reject = Reject::parse(&[0b1000_0100, 0, 0xFF], 3);
+        assert!(!reject.can_see(0, 2));
+        assert!(!reject.can_see(2, 1));
+        assert!(reject.can_see(2, 0));
+        assert!(reject.can_see(5, 0));
+        assert!(Reject::parse(&[], 3).can_see(0, 2));
+
+        let mut truncated = Reject::parse(&[0b1000_0100], 3);
+        assert!(!truncated.can_see(0, 2));
+        assert!(truncated.can_see(2, 2));
+        assert!(!truncated.is_complete() && reject.is_complete());
+        assert!(truncated.set_visible(2, 2, false));
+        assert!(!truncated.can_see(2, 2) && truncated.is_complete());
+
+        // Nothing is allocated for the missing part
+        let empty = Reject::parse(&[], 65535);
+        assert!(empty.data().is_empty());
+        assert!(empty.can_see(1000, 2000));
+    }
+
+    #[test]
+    fn build_from_sight_lines() {
+        use crate::wad::parser::{
+            file::{ArchiveBuilder, Type},
+            level::Levels,
+        };
+
+        let words = |words: &[i16]| -> Vec<u8> {
+            words.iter().flat_map(|word| word.to_le_bytes()).collect()
+        };
+        let sidedef = |sector: i16| {
+            let mut data = words(&[0, 0]);
+            data.extend(b"-\0\0\0\0\0\0\0-\0\0\0\0\0\0\0STARTAN3");
+            data.extend(words(&[sector]));
+            data
+        };
+        // Sectors 1 and 2 opening into sector 0 with solid wall between them,
+        // sector 3 closed on its own:
+        //  +---+   +---+
+        //  | 1 |   | 2 |
+        //  +---+---+---+
+        //  |     0     |
+        //  +-----------+
+        let vertices = words(&[
+            0, 0, 192, 0, 192, 64, 128, 64, 64, 64, 0, 64, 0, 128, 64, 128, 128, 128, 192, 128,
+            300, 0, 364, 0, 364, 64,
+        ]);
+        #[rustfmt::skip]
+        let linedefs = words(&[
+            0, 1, 0, 0, 0, 0, -1,
+            1, 2, 0, 0, 0, 1, -1,
+            2, 3, 4, 0, 0, 2, 3,
+            3, 4, 0, 0, 0, 4, -1,
+            4, 5, 4, 0, 0, 5, 6,
+            5, 0, 0, 0, 0, 7, -1,
+            4, 7, 0, 0, 0, 8, -1,
+            7, 6, 0, 0, 0, 9, -1,
+            6, 5, 0, 0, 0, 10, -1,
+            3, 8, 0, 0, 0, 11, -1,
+            8, 9, 0, 0, 0, 12, -1,
+            9, 2, 0, 0, 0, 13, -1,
+            10, 11, 0, 0, 0, 14, -1,
+            11, 12, 0, 0, 0, 15, -1,
+            12, 10, 0, 0, 0, 16, -1,
+        ]);
+        let sidedefs: Vec<u8> = [0, 0, 0, 2, 0, 0, 1, 0, 1, 1, 1, 2, 2, 2, 3, 3, 3]
+            .iter()
+            .flat_map(|&sector| sidedef(sector))
+            .collect();
+        let mut sectors = Vec::new();
+        for _ in 0..4 {
+            sectors.extend(words(&[0, 128]));
+            sectors.extend(b"FLOOR0_1CEIL1_1\0");
+            sectors.extend(words(&[160, 0, 0]));
+        }
+        let wad = ArchiveBuilder::new(Type::PWAD)
+            .marker("MAP01")
+            .lump("THINGS", Vec::new())
+            .lump("LINEDEFS", linedefs)
+            .lump("SIDEDEFS", sidedefs)
+            .lump("VERTEXES", vertices)
+            .lump("SECTORS", sectors)
+            .to_bytes()
+            .expect("Error writing wad");
+        let archive =
+            crate::wad::parser::file::Archive::parse(&wad).expect("Wad file parser error");
+        let level = Levels::parse(archive.iter())
+            .expect("Error parsing levels")
+            .remove(0);
+
+        let reject = Reject::build(&level);
+        assert!(reject.can_see(0, 1) && reject.can_see(1, 0));
+        assert!(reject.can_see(0, 2) && reject.can_see(2, 0));
+        assert!(!reject.can_see(1, 2) && !reject.can_see(2, 1));
+        assert!(!reject.can_see(3, 0));
+        assert!(reject.can_see(3, 3));
+    }
+
+    #[test]
+    fn build_for_large_map() {
+        use crate::wad::parser::level::{Level, LevelFormat, Linedef, Sector, Sidedef};
+
+        // Rows of 20 rooms open to each other, each row joined to the next one
+        // only by its first room
+        const COLUMNS: u32 = 20;
+        const ROWS: u32 = 15;
+        let room = |column: u32, row: u32| Some(row * COLUMNS + column);
+        let vertex = |column: u32, row: u32| row * (COLUMNS + 1) + column;
+        let vertices = (0..=ROWS)
+            .flat_map(|row| (0..=COLUMNS).map(move |column| (column as i16 * 64, row as i16 * 64)))
+            .collect();
+        let line = |vertex_start, vertex_end, sidedef_right, sidedef_left| Linedef {
+            vertex_start,
+            vertex_end,
+            flags: 0,
+            function: 0,
+            tag: 0,
+            args: [0; 5],
+            sidedef_right,
+            sidedef_left,
+        };
+        let mut linedefs = Vec::new();
+        for row in 0..ROWS {
+            linedefs.push(line(vertex(0, row), vertex(0, row + 1), room(0, row), None));
+            for column in 1..COLUMNS {
+                let (a, b) = (vertex(column, row), vertex(column, row + 1));
+                linedefs.push(line(a, b, room(column, row), room(column - 1, row)));
+            }
+            let (a, b) = (vertex(COLUMNS, row + 1), vertex(COLUMNS, row));
+            linedefs.push(line(a, b, room(COLUMNS - 1, row), None));
+        }
+        for column in 0..COLUMNS {
+            let (a, b) = (vertex(column + 1, 0), vertex(column, 0));
+            linedefs.push(line(a, b, room(column, 0), None));
+            for row in 1..ROWS {
+                let below = if column == 0 {
+                    room(column, row - 1)
+                } else {
+                    None
+                };
+                let (a, b) = (vertex(column + 1, row), vertex(column, row));
+                linedefs.push(line(a, b, room(column, row), below));
+            }
+            let (a, b) = (vertex(column, ROWS), vertex(column + 1, ROWS));
+            linedefs.push(line(a, b, room(column, ROWS - 1), None));
+        }
+        let sectors = (COLUMNS * ROWS) as usize;
+        let level = Level {
+            name: "MAP01",
+            format: LevelFormat::Doom,
+            behavior: None,
+            things: Vec::new(),
+            linedefs,
+            sidedefs: (0..sectors)
+                .map(|sector_ref| Sidedef {
+                    x_offset: 0,
+                    y_offset: 0,
+                    upper_texture: "-",
+                    lower_texture: "-",
+                    mid_texture: "STARTAN3",
+                    sector_ref: sector_ref as i16,
+                })
+                .collect(),
+            vertices,
+            segments: Vec::new(),
+            subsectors: Vec::new(),
+            nodes: Vec::new(),
+            sectors: (0..sectors)
+                .map(|_| Sector {
+                    floor_height: 0,
+                    ceiling_height: 128,
+                    floor_pic: "FLOOR0_1",
+                    ceiling_pic: "CEIL1_1",
+                    light_level: 160,
+                    special_sector: 0,
+                    tag: 0,
+                })
+                .collect(),
+            reject: None,
+            blockmap: None,
+            gl_nodes: None,
+            udmf: None,
+        };
+
+        let reject = Reject::build(&level);
+        let index = |column: u32, row: u32| (row * COLUMNS + column) as usize;
+        assert!(reject.can_see(index(5, 0), index(19, 0)));
+        assert!(reject.can_see(index(5, 0), index(0, 1)));
+        assert!(!reject.can_see(index(5, 0), index(5, 1)));
+        assert!(!reject.can_see(index(19, 14), index(19, 0)));
+        let rejected = (0..sectors)
+            .flat_map(|from| (0..sectors).map(move |to| (from, to)))
+            .filter(|&(from, to)| !reject.can_see(from, to))
+            .count();
+        assert!(rejected > sectors * sectors / 2);
+    }
+}
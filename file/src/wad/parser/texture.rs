@@ -1,25 +1,111 @@
 use super::{
-    name::parse_name,
-    types::{OnlyResult, ParseResult},
+    name::{parse_name, write_name},
+    types::{item_count, run, seek, OnlyResult, ParseResult},
 };
 use nom::{
-    bytes::complete::take,
-    combinator::{map, map_res},
+    combinator::{map, verify},
     multi::length_count,
-    number::complete::{le_i16, le_i32},
+    number::complete::{le_i16, le_i32, le_u16, le_u8},
     sequence::tuple,
 };
+use std::{
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+/// Layout of `TEXTURE1`/`TEXTURE2` entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// Name, header with columndirectory and 10-byte patch descriptors
+    Doom,
+    /// No columndirectory and 6-byte patch descriptors without `stepdir` and `colormap`
+    Strife,
+    /// Doom layout without names, found in early alpha data
+    Nameless,
+}
+
+impl TextureFormat {
+    const fn header_size(self) -> usize {
+        match self {
+            Self::Doom => 22,
+            Self::Strife => 18,
+            Self::Nameless => 14,
+        }
+    }
+
+    const fn descriptor_size(self) -> usize {
+        match self {
+            Self::Doom | Self::Nameless => 10,
+            Self::Strife => 6,
+        }
+    }
+
+    /// Tells the layout by sizes of entries, each of them must end right where the next one
+    /// (or the lump) starts. Doom layout is assumed when nothing fits, e.g. for padded lumps.
+    pub fn detect(lump: &[u8]) -> Self {
+        let offsets = match run(texture_offsets, lump) {
+            Ok(offsets) => offsets,
+            Err(_) => return Self::Doom,
+        };
+        let mut sorted = offsets.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let fits = |format: Self| {
+            sorted.iter().enumerate().all(|(i, &offset)| {
+                let count_at = offset + format.header_size() - 2;
+                let count = match lump.get(count_at..count_at + 2) {
+                    Some(count) => i16::from_le_bytes([count[0], count[1]]),
+                    None => return false,
+                };
+                let size = match usize::try_from(count) {
+                    Ok(count) => format.header_size() + count * format.descriptor_size(),
+                    Err(_) => return false,
+                };
+                offset + size == sorted.get(i + 1).copied().unwrap_or(lump.len())
+            })
+        };
+        [Self::Doom, Self::Strife, Self::Nameless]
+            .iter()
+            .copied()
+            .find(|&format| fits(format))
+            .unwrap_or(Self::Doom)
+    }
+}
+
+fn texture_offsets(i: &[u8]) -> ParseResult<'_, Vec<usize>> {
+    length_count(item_count(le_i32), |i| {
+        map(verify(le_i32, |&offset| offset >= 0), |offset| {
+            offset as usize
+        })(i)
+    })(i)
+}
 
 pub struct PatchDescriptor {
     pub x_offset: i16,
     pub y_offset: i16,
+    /// Index of the patch in `PNAMES`
     pub id: i16,
+    /// Unused by the engine, normally 1
     pub stepdir: i16,
+    /// Unused by the engine, normally 0
     pub colormap: i16,
 }
 
 impl PatchDescriptor {
-    fn parse(i: &[u8]) -> ParseResult<Self> {
+    fn parse(i: &[u8], format: TextureFormat) -> ParseResult<'_, Self> {
+        if format == TextureFormat::Strife {
+            let (i, (x_offset, y_offset, id)) = tuple((le_i16, le_i16, le_i16))(i)?;
+            return Ok((
+                i,
+                Self {
+                    x_offset,
+                    y_offset,
+                    id,
+                    stepdir: 1,
+                    colormap: 0,
+                },
+            ));
+        }
         let (i, (x_offset, y_offset, id, stepdir, colormap)) =
             tuple((le_i16, le_i16, le_i16, le_i16, le_i16))(i)?;
         Ok((
@@ -35,46 +121,166 @@ impl PatchDescriptor {
     }
 }
 
+impl PatchDescriptor {
+    fn write<W: Write>(&self, mut w: W, format: TextureFormat) -> io::Result<()> {
+        let fields = [
+            self.x_offset,
+            self.y_offset,
+            self.id,
+            self.stepdir,
+            self.colormap,
+        ];
+        let fields = if format == TextureFormat::Strife {
+            &fields[..3]
+        } else {
+            &fields[..]
+        };
+        for field in fields {
+            w.write_all(&field.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Flag of [`Texture::flags`] making ZDoom scale offsets of the texture along with it.
+pub const WORLD_PANNING: u16 = 0x8000;
+
 pub struct Texture<'a> {
+    /// Empty for [`TextureFormat::Nameless`] textures
     pub name: &'a str,
+    /// Low word of vanilla `masked` field (1 for masked textures), ZDoom keeps
+    /// [`WORLD_PANNING`] there
+    pub flags: u16,
+    /// High bytes of vanilla `masked` field, see [`Texture::x_scale`]
+    pub scale_x: u8,
+    pub scale_y: u8,
     pub width: i16,
     pub height: i16,
+    /// Obsolete `columndirectory`, zero in Strife layout
+    pub column_directory: i32,
     pub patch_descriptors: Vec<PatchDescriptor>,
 }
 
 impl<'a> Texture<'a> {
-    fn parse(i: &'a [u8]) -> OnlyResult<Self> {
-        let (_, (name, _, _, width, height, _, _, patch_descriptors)) = tuple((
-            parse_name,
-            le_i16,
-            le_i16,
-            le_i16,
-            le_i16,
-            le_i16,
-            le_i16,
-            length_count(map(le_i16, |x| x as usize), PatchDescriptor::parse),
-        ))(i)?;
-        Ok(Self {
-            name,
-            width,
-            height,
-            patch_descriptors,
-        })
+    fn parse(i: &'a [u8], format: TextureFormat) -> ParseResult<'a, Self> {
+        let (i, name) = if format == TextureFormat::Nameless {
+            (i, "")
+        } else {
+            parse_name(i)?
+        };
+        let (i, (flags, scale_x, scale_y, width, height)) =
+            tuple((le_u16, le_u8, le_u8, le_i16, le_i16))(i)?;
+        let (i, column_directory) = if format == TextureFormat::Strife {
+            (i, 0)
+        } else {
+            le_i32(i)?
+        };
+        let (i, patch_descriptors) =
+            length_count(item_count(le_i16), |i| PatchDescriptor::parse(i, format))(i)?;
+        Ok((
+            i,
+            Self {
+                name,
+                flags,
+                scale_x,
+                scale_y,
+                width,
+                height,
+                column_directory,
+                patch_descriptors,
+            },
+        ))
+    }
+
+    pub fn is_masked(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn world_panning(&self) -> bool {
+        self.flags & WORLD_PANNING != 0
+    }
+
+    /// Horizontal scale as ZDoom reads it: eighths of the texel size, zero meaning 1.
+    pub fn x_scale(&self) -> f64 {
+        scale(self.scale_x)
+    }
+
+    pub fn y_scale(&self) -> f64 {
+        scale(self.scale_y)
+    }
+
+    fn write<W: Write>(&self, mut w: W, format: TextureFormat) -> io::Result<()> {
+        if format != TextureFormat::Nameless {
+            write_name(&mut w, self.name)?;
+        }
+        w.write_all(&self.flags.to_le_bytes())?;
+        w.write_all(&[self.scale_x, self.scale_y])?;
+        w.write_all(&self.width.to_le_bytes())?;
+        w.write_all(&self.height.to_le_bytes())?;
+        if format != TextureFormat::Strife {
+            w.write_all(&self.column_directory.to_le_bytes())?;
+        }
+        let count = i16::try_from(self.patch_descriptors.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Too many patches in texture")
+        })?;
+        w.write_all(&count.to_le_bytes())?;
+        for descriptor in &self.patch_descriptors {
+            descriptor.write(&mut w, format)?;
+        }
+        Ok(())
+    }
+}
+
+fn scale(value: u8) -> f64 {
+    if value == 0 {
+        1.0
+    } else {
+        f64::from(value) / 8.0
     }
 }
 
 pub struct Textures;
 
 impl Textures {
-    pub fn parse<'a>(i: &'a [u8]) -> OnlyResult<Vec<Texture<'a>>> {
-        let (_, textures) = length_count(
-            map(le_i32, |x| x as usize),
-            map_res(le_i32, |offset| {
-                let (tex_i, _) = take(offset as usize)(i)?;
-                Texture::parse(tex_i)
+    /// Parses textures of the layout told by [`TextureFormat::detect`].
+    pub fn parse(lump_i: &[u8]) -> OnlyResult<Vec<Texture<'_>>> {
+        Self::parse_as(lump_i, TextureFormat::detect(lump_i))
+    }
+
+    /// Writes `TEXTURE1`/`TEXTURE2` lump of the layout, fields it lacks are dropped.
+    pub fn write_to<W: Write>(
+        textures: &[Texture<'_>],
+        mut w: W,
+        format: TextureFormat,
+    ) -> io::Result<()> {
+        let mut entries = Vec::new();
+        let mut offsets = Vec::with_capacity(textures.len());
+        let header_size = 4 + 4 * textures.len();
+        for texture in textures {
+            let offset = i32::try_from(header_size + entries.len()).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Texture lump is too large")
+            })?;
+            offsets.push(offset);
+            texture.write(&mut entries, format)?;
+        }
+        w.write_all(&(textures.len() as i32).to_le_bytes())?;
+        for offset in offsets {
+            w.write_all(&offset.to_le_bytes())?;
+        }
+        w.write_all(&entries)?;
+        w.flush()
+    }
+
+    pub fn parse_as(lump_i: &[u8], format: TextureFormat) -> OnlyResult<Vec<Texture<'_>>> {
+        run(
+            length_count(item_count(le_i32), |i| {
+                let (i, offset) = le_i32(i)?;
+                let (_, tex_i) = seek(lump_i, offset as usize, i)?;
+                let (_, texture) = Texture::parse(tex_i, format)?;
+                Ok((i, texture))
             }),
-        )(i)?;
-        Ok(textures)
+            lump_i,
+        )
     }
 }
 
@@ -91,4 +297,101 @@ mod tests {
             .iter()
             .for_each(|tex| println!("TEXTURE1 name: {}", tex.name));
     }
+
+    #[test]
+    fn detect_layouts() {
+        use super::{TextureFormat, Textures};
+
+        let words = |words: &[i16]| -> Vec<u8> {
+            words.iter().flat_map(|word| word.to_le_bytes()).collect()
+        };
+        for &format in &[
+            TextureFormat::Doom,
+            TextureFormat::Strife,
+            TextureFormat::Nameless,
+        ] {
+            let entries: Vec<Vec<u8>> = [("WALL1", 1), ("WALL2", 2)]
+                .iter()
+                .map(|&(name, patches)| {
+                    let mut entry = Vec::new();
+                    if format != TextureFormat::Nameless {
+                        entry.extend_from_slice(&[name.as_bytes(), &[0; 3]].concat());
+                    }
+                    entry.extend(words(&[0, 0, 64, 128]));
+                    if format != TextureFormat::Strife {
+                        entry.extend_from_slice(&[0; 4]);
+                    }
+                    entry.extend(words(&[patches]));
+                    for patch in 0..patches {
+                        entry.extend(words(&[patch * 32, -8, patch]));
+                        if format != TextureFormat::Strife {
+                            entry.extend(words(&[1, 0]));
+                        }
+                    }
+                    entry
+                })
+                .collect();
+            let mut lump = 2i32.to_le_bytes().to_vec();
+            let mut offset = 12;
+            for entry in &entries {
+                lump.extend_from_slice(&(offset as i32).to_le_bytes());
+                offset += entry.len();
+            }
+            lump.extend(entries.concat());
+
+            assert_eq!(TextureFormat::detect(&lump), format);
+            let textures = Textures::parse(&lump).expect("Error parsing textures");
+            let expected_name = if format == TextureFormat::Nameless {
+                ""
+            } else {
+                "WALL2"
+            };
+            assert_eq!(textures[1].name, expected_name);
+            assert_eq!((textures[1].width, textures[1].height), (64, 128));
+            let patch = &textures[1].patch_descriptors[1];
+            assert_eq!((patch.x_offset, patch.y_offset, patch.id), (32, -8, 1));
+            assert_eq!(patch.stepdir, 1);
+        }
+    }
+
+    #[test]
+    fn write_textures_back() {
+        use super::{PatchDescriptor, Texture, TextureFormat, Textures};
+
+        let textures = vec![Texture {
+            name: "SKY1",
+            flags: super::WORLD_PANNING | 1,
+            scale_x: 16,
+            scale_y: 0,
+            width: 256,
+            height: 128,
+            column_directory: 0,
+            patch_descriptors: vec![PatchDescriptor {
+                x_offset: -4,
+                y_offset: 2,
+                id: 7,
+                stepdir: 1,
+                colormap: 0,
+            }],
+        }];
+        for &format in &[
+            TextureFormat::Doom,
+            TextureFormat::Strife,
+            TextureFormat::Nameless,
+        ] {
+            let mut lump = Vec::new();
+            Textures::write_to(&textures, &mut lump, format).expect("Error writing textures");
+            let parsed = Textures::parse_as(&lump, format).expect("Error parsing textures");
+            let texture = &parsed[0];
+            assert!(texture.is_masked() && texture.world_panning());
+            assert_eq!((texture.x_scale(), texture.y_scale()), (2.0, 1.0));
+            assert_eq!((texture.width, texture.height), (256, 128));
+            let patch = &texture.patch_descriptors[0];
+            assert_eq!((patch.x_offset, patch.y_offset, patch.id), (-4, 2, 7));
+
+            let mut again = Vec::new();
+            Textures::write_to(&parsed, &mut again, format).expect("Error writing textures");
+            assert_eq!(again, lump);
+        }
+    }
 }
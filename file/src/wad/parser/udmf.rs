@@ -0,0 +1,1095 @@
+use super::{
+    level::{Level, LevelFormat, Linedef, Sector, Sidedef, Thing, Vertex},
+    types::{run, Input, OnlyResult, ParseError, ParseResult},
+};
+use crate::error::ErrorKind;
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::{self, Write},
+    str,
+};
+
+/// Value of an assignment, strings are kept as written with escape sequences in them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(&'a str),
+    /// Unquoted identifier other than `true` or `false`
+    Keyword(&'a str),
+}
+
+impl<'a> Value<'a> {
+    /// Integer value, floats without fractional part included.
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Self::Int(x) => Some(x),
+            Self::Float(x) if x.fract() == 0.0 && x.abs() < i64::MAX as f64 => Some(x as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            Self::Int(x) => Some(x as f64),
+            Self::Float(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Self::Bool(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            Self::Str(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(x) => write!(f, "{}", x),
+            Self::Float(x) => {
+                let x = x.to_string();
+                if x.contains('.') {
+                    f.write_str(&x)
+                } else {
+                    write!(f, "{}.0", x)
+                }
+            }
+            Self::Bool(x) => write!(f, "{}", x),
+            Self::Str(x) => write!(f, "\"{}\"", x),
+            Self::Keyword(x) => f.write_str(x),
+        }
+    }
+}
+
+/// Assignments in the order they're written.
+pub type Fields<'a> = Vec<(&'a str, Value<'a>)>;
+
+fn get<'f, 'a>(fields: &'f [(&'a str, Value<'a>)], key: &str) -> Option<&'f Value<'a>> {
+    fields
+        .iter()
+        .rev()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value)
+}
+
+/// Block like `thing { ... }` of the given kind.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block<'a> {
+    pub kind: &'a str,
+    pub fields: Fields<'a>,
+}
+
+/// Contents of `TEXTMAP` lump as written, see [`Level::from_textmap`] for the level model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextMap<'a> {
+    pub namespace: &'a str,
+    /// Global assignments other than `namespace`
+    pub globals: Fields<'a>,
+    pub blocks: Vec<Block<'a>>,
+}
+
+fn is_ident_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+pub(super) fn utf8(i: Input<'_>) -> Result<&str, nom::Err<ParseError<'_>>> {
+    str::from_utf8(i).map_err(|_| nom::Err::Error(ParseError::new(i, ErrorKind::Malformed)))
+}
+
+/// Skips whitespace, `//` and `/* */` comments.
+pub(super) fn skip(mut i: Input<'_>) -> ParseResult<'_, ()> {
+    loop {
+        let start = i.iter().position(|c| !c.is_ascii_whitespace());
+        i = &i[start.unwrap_or(i.len())..];
+        if i.starts_with(b"//") {
+            let end = i.iter().position(|&c| c == b'\n').unwrap_or(i.len());
+            i = &i[end..];
+        } else if i.starts_with(b"/*") {
+            match i.windows(2).skip(2).position(|w| w == b"*/") {
+                Some(end) => i = &i[end + 4..],
+                None => return ParseError::fail(i, ErrorKind::Truncated),
+            }
+        } else {
+            return Ok((i, ()));
+        }
+    }
+}
+
+pub(super) fn symbol(c: u8) -> impl Fn(Input<'_>) -> ParseResult<'_, ()> {
+    move |i| {
+        let (i, _) = skip(i)?;
+        match i.split_first() {
+            Some((&x, rest)) if x == c => Ok((rest, ())),
+            Some(_) => ParseError::fail(i, ErrorKind::Malformed),
+            None => ParseError::fail(i, ErrorKind::Truncated),
+        }
+    }
+}
+
+fn identifier(i: Input<'_>) -> ParseResult<'_, &str> {
+    let (i, _) = skip(i)?;
+    let len = i.iter().position(|&c| !is_ident_char(c)).unwrap_or(i.len());
+    match i.first() {
+        Some(c) if len > 0 && !c.is_ascii_digit() => Ok((&i[len..], utf8(&i[..len])?)),
+        Some(_) => ParseError::fail(i, ErrorKind::Malformed),
+        None => ParseError::fail(i, ErrorKind::Truncated),
+    }
+}
+
+pub(super) fn quoted(i: Input<'_>) -> ParseResult<'_, &str> {
+    let mut escaped = false;
+    for (pos, &c) in i.iter().enumerate().skip(1) {
+        match c {
+            b'"' if !escaped => return Ok((&i[pos + 1..], utf8(&i[1..pos])?)),
+            b'\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    ParseError::fail(i, ErrorKind::Truncated)
+}
+
+/// Parses decimal, octal or hexadecimal integer or a float with a dot or exponent.
+pub(super) fn number(i: Input<'_>) -> ParseResult<'_, Value<'_>> {
+    let len = i
+        .iter()
+        .enumerate()
+        .position(|(pos, &c)| {
+            let sign = (c == b'+' || c == b'-') && (pos == 0 || matches!(i[pos - 1], b'e' | b'E'));
+            !(sign || c == b'.' || c.is_ascii_alphanumeric())
+        })
+        .unwrap_or(i.len());
+    let (token, rest) = i.split_at(len);
+    let token = utf8(token)?;
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token.strip_prefix('+').unwrap_or(token)),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok().map(Value::Int)
+    } else if digits.contains(['.', 'e', 'E']) {
+        digits.parse().ok().map(Value::Float)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(digits, 8).ok().map(Value::Int)
+    } else {
+        digits.parse().ok().map(Value::Int)
+    };
+    match value {
+        Some(Value::Int(x)) if negative => Ok((rest, Value::Int(-x))),
+        Some(Value::Float(x)) if negative => Ok((rest, Value::Float(-x))),
+        Some(value) => Ok((rest, value)),
+        None => ParseError::fail(i, ErrorKind::Malformed),
+    }
+}
+
+fn value(i: Input<'_>) -> ParseResult<'_, Value<'_>> {
+    let (i, _) = skip(i)?;
+    match i.first() {
+        Some(b'"') => quoted(i).map(|(i, s)| (i, Value::Str(s))),
+        Some(c) if c.is_ascii_digit() || matches!(c, b'+' | b'-' | b'.') => number(i),
+        _ => identifier(i).map(|(i, keyword)| {
+            let value = if keyword.eq_ignore_ascii_case("true") {
+                Value::Bool(true)
+            } else if keyword.eq_ignore_ascii_case("false") {
+                Value::Bool(false)
+            } else {
+                Value::Keyword(keyword)
+            };
+            (i, value)
+        }),
+    }
+}
+
+/// Parses `= value;` following the key.
+fn assignment_value(i: Input<'_>) -> ParseResult<'_, Value<'_>> {
+    let (i, _) = symbol(b'=')(i)?;
+    let (i, value) = value(i)?;
+    let (i, _) = symbol(b';')(i)?;
+    Ok((i, value))
+}
+
+fn block_fields(mut i: Input<'_>) -> ParseResult<'_, Fields<'_>> {
+    let mut fields = Vec::new();
+    loop {
+        if let Ok((rest, _)) = symbol(b'}')(i) {
+            return Ok((rest, fields));
+        }
+        let (rest, key) = identifier(i)?;
+        let (rest, value) = assignment_value(rest)?;
+        fields.push((key, value));
+        i = rest;
+    }
+}
+
+impl<'a> TextMap<'a> {
+    fn parse_all(mut i: Input<'a>) -> ParseResult<'a, Self> {
+        let mut namespace = None;
+        let mut globals = Vec::new();
+        let mut blocks = Vec::new();
+        loop {
+            let (rest, _) = skip(i)?;
+            if rest.is_empty() {
+                break;
+            }
+            let (rest, name) = identifier(rest)?;
+            i = match symbol(b'{')(rest) {
+                Ok((rest, _)) => {
+                    let (rest, fields) = block_fields(rest)?;
+                    blocks.push(Block { kind: name, fields });
+                    rest
+                }
+                Err(_) => {
+                    let (rest, value) = assignment_value(rest)?;
+                    match value {
+                        Value::Str(value) if name.eq_ignore_ascii_case("namespace") => {
+                            namespace = Some(value)
+                        }
+                        _ => globals.push((name, value)),
+                    }
+                    rest
+                }
+            };
+        }
+        match namespace {
+            Some(namespace) => Ok((
+                i,
+                Self {
+                    namespace,
+                    globals,
+                    blocks,
+                },
+            )),
+            None => ParseError::fail(i, ErrorKind::Malformed),
+        }
+    }
+
+    pub fn parse(i: &'a [u8]) -> OnlyResult<Self> {
+        run(Self::parse_all, i)
+    }
+
+    /// Writes the text back, failing with [`io::ErrorKind::InvalidInput`] on infinite or NaN
+    /// floats which UDMF has no syntax for.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let values = self
+            .globals
+            .iter()
+            .chain(self.blocks.iter().flat_map(|block| &block.fields));
+        if let Some((key, _)) = values.clone().find(|(_, value)| match value {
+            Value::Float(x) => !x.is_finite(),
+            _ => false,
+        }) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Non-finite value of {:?}", key),
+            ));
+        }
+        writeln!(w, "namespace = \"{}\";", self.namespace)?;
+        for (key, value) in &self.globals {
+            writeln!(w, "{} = {};", key, value)?;
+        }
+        let mut counters: Vec<(&str, usize)> = Vec::new();
+        for block in &self.blocks {
+            let index = match counters.iter_mut().find(|(kind, _)| *kind == block.kind) {
+                Some((_, count)) => {
+                    *count += 1;
+                    *count - 1
+                }
+                None => {
+                    counters.push((block.kind, 1));
+                    0
+                }
+            };
+            writeln!(w, "\n{} // {}\n{{", block.kind, index)?;
+            for (key, value) in &block.fields {
+                writeln!(w, "{} = {};", key, value)?;
+            }
+            writeln!(w, "}}")?;
+        }
+        w.flush()
+    }
+}
+
+/// Keys of UDMF not represented by the [`Level`] model (or not losslessly), kept per element
+/// in parallel with its vectors, so the level can be written back without losing them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UdmfExtra<'a> {
+    pub namespace: &'a str,
+    pub globals: Fields<'a>,
+    pub things: Vec<Fields<'a>>,
+    pub vertices: Vec<Fields<'a>>,
+    pub linedefs: Vec<Fields<'a>>,
+    pub sidedefs: Vec<Fields<'a>>,
+    pub sectors: Vec<Fields<'a>>,
+    /// Blocks of kinds other than the level elements
+    pub blocks: Vec<Block<'a>>,
+}
+
+/// Checks whether specials and flags of the namespace follow Hexen rather than Doom.
+fn is_hexen_namespace(namespace: &str) -> bool {
+    !["doom", "heretic", "strife"]
+        .iter()
+        .any(|ns| ns.eq_ignore_ascii_case(namespace))
+}
+
+/// Flag key, its bit and whether the bit is set when the flag is `false`.
+type FlagBit = (&'static str, i16, bool);
+
+const DOOM_THING_FLAGS: [FlagBit; 10] = [
+    ("skill1", 0x1, false),
+    ("skill2", 0x1, false),
+    ("skill3", 0x2, false),
+    ("skill4", 0x4, false),
+    ("skill5", 0x4, false),
+    ("ambush", 0x8, false),
+    ("single", 0x10, true),
+    ("dm", 0x20, true),
+    ("coop", 0x40, true),
+    ("friend", 0x80, false),
+];
+
+const HEXEN_THING_FLAGS: [FlagBit; 13] = [
+    ("skill1", 0x1, false),
+    ("skill2", 0x1, false),
+    ("skill3", 0x2, false),
+    ("skill4", 0x4, false),
+    ("skill5", 0x4, false),
+    ("ambush", 0x8, false),
+    ("dormant", 0x10, false),
+    ("class1", 0x20, false),
+    ("class2", 0x40, false),
+    ("class3", 0x80, false),
+    ("single", 0x100, false),
+    ("coop", 0x200, false),
+    ("dm", 0x400, false),
+];
+
+const LINEDEF_FLAGS: [FlagBit; 9] = [
+    ("blocking", 0x1, false),
+    ("blockmonsters", 0x2, false),
+    ("twosided", 0x4, false),
+    ("dontpegtop", 0x8, false),
+    ("dontpegbottom", 0x10, false),
+    ("secret", 0x20, false),
+    ("blocksound", 0x40, false),
+    ("dontdraw", 0x80, false),
+    ("mapped", 0x100, false),
+];
+
+const DOOM_LINEDEF_FLAGS: [FlagBit; 1] = [("passuse", 0x200, false)];
+
+const HEXEN_LINEDEF_FLAGS: [FlagBit; 3] = [
+    ("repeatspecial", 0x200, false),
+    ("monsteractivate", 0x2000, false),
+    ("blockeverything", -0x8000, false),
+];
+
+/// Hexen activation types stored in bits 10-12 of linedef flags.
+const ACTIVATIONS: [&str; 6] = [
+    "playercross",
+    "playeruse",
+    "monstercross",
+    "impact",
+    "playerpush",
+    "missilecross",
+];
+const ACTIVATION_SHIFT: i16 = 10;
+const ACTIVATION_MASK: i16 = 0x7 << ACTIVATION_SHIFT;
+
+fn default_flags(table: &[FlagBit]) -> i16 {
+    table
+        .iter()
+        .filter(|&&(_, _, inverted)| inverted)
+        .fold(0, |flags, &(_, bit, _)| flags | bit)
+}
+
+/// Applies the flag to `flags` if the key is one of the table, several keys may share a bit.
+fn set_flag(table: &[FlagBit], flags: &mut i16, key: &str, value: &Value<'_>) -> bool {
+    match (
+        table
+            .iter()
+            .find(|(name, _, _)| name.eq_ignore_ascii_case(key)),
+        value.as_bool(),
+    ) {
+        (Some(&(_, bit, inverted)), Some(value)) => {
+            if value != inverted {
+                *flags |= bit;
+            } else if inverted {
+                *flags &= !bit;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+fn write_flags(table: &[FlagBit], flags: i16, fields: &mut Fields<'_>) {
+    for &(key, bit, inverted) in table {
+        if (flags & bit != 0) != inverted {
+            fields.push((key, Value::Bool(true)));
+        }
+    }
+}
+
+/// Stores integer value if it fits, returns whether it did exactly.
+fn set_int(value: &Value<'_>, target: &mut i16) -> bool {
+    if let Some(x) = value.as_int().and_then(|x| i16::try_from(x).ok()) {
+        *target = x;
+        true
+    } else if let Some(x) = value.as_float() {
+        *target = x.round().max(i16::MIN.into()).min(i16::MAX.into()) as i16;
+        false
+    } else {
+        false
+    }
+}
+
+fn set_index(value: &Value<'_>, target: &mut u32) -> bool {
+    match value.as_int().and_then(|x| u32::try_from(x).ok()) {
+        Some(x) => {
+            *target = x;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Sets sidedef index, -1 meaning no sidedef.
+fn set_sidedef(value: &Value<'_>, target: &mut Option<u32>) -> bool {
+    match value.as_int() {
+        Some(-1) => {
+            *target = None;
+            true
+        }
+        Some(_) => {
+            let mut index = 0;
+            let set = set_index(value, &mut index);
+            if set {
+                *target = Some(index);
+            }
+            set
+        }
+        None => false,
+    }
+}
+
+fn set_u8(value: &Value<'_>, target: &mut u8) -> bool {
+    match value.as_int().and_then(|x| u8::try_from(x).ok()) {
+        Some(x) => {
+            *target = x;
+            true
+        }
+        None => false,
+    }
+}
+
+fn set_str<'a>(value: &Value<'a>, target: &mut &'a str) -> bool {
+    match value.as_str() {
+        Some(x) => {
+            *target = x;
+            true
+        }
+        None => false,
+    }
+}
+
+fn arg_index(key: &str) -> Option<usize> {
+    match key.as_bytes() {
+        [b'a', b'r', b'g', n @ b'0'..=b'4'] => Some(usize::from(n - b'0')),
+        _ => None,
+    }
+}
+
+/// Splits fields into the ones applied by `apply` and the rest.
+fn apply_fields<'a, F>(fields: Fields<'a>, mut apply: F) -> Fields<'a>
+where
+    F: FnMut(&str, &Value<'a>) -> bool,
+{
+    fields
+        .into_iter()
+        .filter(|(key, value)| !apply(&key.to_ascii_lowercase(), value))
+        .collect()
+}
+
+fn read_thing<'a>(fields: Fields<'a>, hexen: bool) -> (Thing, Fields<'a>) {
+    let flags: &[FlagBit] = if hexen {
+        &HEXEN_THING_FLAGS
+    } else {
+        &DOOM_THING_FLAGS
+    };
+    let mut thing = Thing {
+        tid: 0,
+        x_pos: 0,
+        y_pos: 0,
+        z_pos: 0,
+        angle: 0,
+        ttype: 0,
+        options: default_flags(flags),
+        special: 0,
+        args: [0; 5],
+    };
+    let extra = apply_fields(fields, |key, value| match key {
+        "id" => set_int(value, &mut thing.tid),
+        "x" => set_int(value, &mut thing.x_pos),
+        "y" => set_int(value, &mut thing.y_pos),
+        "height" => set_int(value, &mut thing.z_pos),
+        "angle" => set_int(value, &mut thing.angle),
+        "type" => set_int(value, &mut thing.ttype),
+        "special" => set_u8(value, &mut thing.special),
+        _ => match arg_index(key) {
+            Some(n) => set_u8(value, &mut thing.args[n]),
+            None => set_flag(flags, &mut thing.options, key, value),
+        },
+    });
+    (thing, extra)
+}
+
+fn read_vertex<'a>(fields: Fields<'a>) -> (Vertex, Fields<'a>) {
+    let (mut x, mut y) = (0, 0);
+    let extra = apply_fields(fields, |key, value| match key {
+        "x" => set_int(value, &mut x),
+        "y" => set_int(value, &mut y),
+        _ => false,
+    });
+    ((x, y), extra)
+}
+
+fn read_linedef<'a>(fields: Fields<'a>, hexen: bool) -> (Linedef, Fields<'a>) {
+    let mut linedef = Linedef {
+        vertex_start: 0,
+        vertex_end: 0,
+        flags: 0,
+        function: 0,
+        tag: 0,
+        args: [0; 5],
+        sidedef_right: None,
+        sidedef_left: None,
+    };
+    let extra = apply_fields(fields, |key, value| match key {
+        "v1" => set_index(value, &mut linedef.vertex_start),
+        "v2" => set_index(value, &mut linedef.vertex_end),
+        "sidefront" => set_sidedef(value, &mut linedef.sidedef_right),
+        "sideback" => set_sidedef(value, &mut linedef.sidedef_left),
+        "special" => set_int(value, &mut linedef.function),
+        "arg0" if !hexen => set_int(value, &mut linedef.tag),
+        _ if hexen && value.as_bool() == Some(true) && ACTIVATIONS.contains(&key) => {
+            let activation = ACTIVATIONS.iter().position(|&a| a == key).unwrap_or(0) as i16;
+            linedef.flags = (linedef.flags & !ACTIVATION_MASK) | activation << ACTIVATION_SHIFT;
+            true
+        }
+        _ => match arg_index(key) {
+            Some(n) => set_u8(value, &mut linedef.args[n]),
+            None if hexen => {
+                set_flag(&LINEDEF_FLAGS, &mut linedef.flags, key, value)
+                    || set_flag(&HEXEN_LINEDEF_FLAGS, &mut linedef.flags, key, value)
+            }
+            None => {
+                set_flag(&LINEDEF_FLAGS, &mut linedef.flags, key, value)
+                    || set_flag(&DOOM_LINEDEF_FLAGS, &mut linedef.flags, key, value)
+            }
+        },
+    });
+    (linedef, extra)
+}
+
+fn read_sidedef<'a>(fields: Fields<'a>) -> (Sidedef<'a>, Fields<'a>) {
+    let mut sidedef = Sidedef {
+        x_offset: 0,
+        y_offset: 0,
+        upper_texture: NO_TEXTURE,
+        lower_texture: NO_TEXTURE,
+        mid_texture: NO_TEXTURE,
+        sector_ref: 0,
+    };
+    let extra = apply_fields(fields, |key, value| match key {
+        "offsetx" => set_int(value, &mut sidedef.x_offset),
+        "offsety" => set_int(value, &mut sidedef.y_offset),
+        "texturetop" => set_str(value, &mut sidedef.upper_texture),
+        "texturebottom" => set_str(value, &mut sidedef.lower_texture),
+        "texturemiddle" => set_str(value, &mut sidedef.mid_texture),
+        "sector" => set_int(value, &mut sidedef.sector_ref),
+        _ => false,
+    });
+    (sidedef, extra)
+}
+
+fn read_sector<'a>(fields: Fields<'a>) -> (Sector<'a>, Fields<'a>) {
+    let mut sector = Sector {
+        floor_height: 0,
+        ceiling_height: 0,
+        floor_pic: NO_TEXTURE,
+        ceiling_pic: NO_TEXTURE,
+        light_level: DEFAULT_LIGHT,
+        special_sector: 0,
+        tag: 0,
+    };
+    let extra = apply_fields(fields, |key, value| match key {
+        "heightfloor" => set_int(value, &mut sector.floor_height),
+        "heightceiling" => set_int(value, &mut sector.ceiling_height),
+        "texturefloor" => set_str(value, &mut sector.floor_pic),
+        "textureceiling" => set_str(value, &mut sector.ceiling_pic),
+        "lightlevel" => set_int(value, &mut sector.light_level),
+        "special" => set_int(value, &mut sector.special_sector),
+        "id" => set_int(value, &mut sector.tag),
+        _ => false,
+    });
+    (sector, extra)
+}
+
+const NO_TEXTURE: &str = "-";
+const DEFAULT_LIGHT: i16 = 160;
+
+/// Collects fields of an element, skipping ones overridden by the extra fields.
+struct FieldWriter<'a> {
+    fields: Fields<'a>,
+}
+
+impl<'a> FieldWriter<'a> {
+    fn new() -> Self {
+        Self { fields: Vec::new() }
+    }
+
+    fn int<T: Into<i64>>(mut self, key: &'a str, value: T) -> Self {
+        self.fields.push((key, Value::Int(value.into())));
+        self
+    }
+
+    fn float<T: Into<f64>>(mut self, key: &'a str, value: T) -> Self {
+        self.fields.push((key, Value::Float(value.into())));
+        self
+    }
+
+    fn string(mut self, key: &'a str, value: &'a str) -> Self {
+        self.fields.push((key, Value::Str(value)));
+        self
+    }
+
+    /// Adds the value unless it's the default one.
+    fn int_or<T: Into<i64> + PartialEq>(self, key: &'a str, value: T, default: T) -> Self {
+        if value == default {
+            self
+        } else {
+            self.int(key, value)
+        }
+    }
+
+    fn args(mut self, args: &[u8]) -> Self {
+        const KEYS: [&str; 5] = ["arg0", "arg1", "arg2", "arg3", "arg4"];
+        for (&key, &arg) in KEYS.iter().zip(args).filter(|(_, &arg)| arg != 0) {
+            self.fields.push((key, Value::Int(arg.into())));
+        }
+        self
+    }
+
+    fn flag(mut self, key: Option<&'a str>) -> Self {
+        if let Some(key) = key {
+            self.fields.push((key, Value::Bool(true)));
+        }
+        self
+    }
+
+    fn flags(mut self, table: &[FlagBit], flags: i16) -> Self {
+        write_flags(table, flags, &mut self.fields);
+        self
+    }
+
+    fn build(self, kind: &'a str, extra: Option<&Fields<'a>>) -> Block<'a> {
+        let extra = extra.map(Vec::as_slice).unwrap_or_default();
+        let mut fields: Fields<'a> = self
+            .fields
+            .into_iter()
+            .filter(|(key, _)| get(extra, key).is_none())
+            .collect();
+        fields.extend_from_slice(extra);
+        Block { kind, fields }
+    }
+}
+
+impl<'a> Level<'a> {
+    /// Builds level from UDMF, keys not fitting the model are kept in [`Level::udmf`].
+    /// Flags and specials follow Hexen unless the namespace is one of Doom, Heretic or Strife.
+    pub fn from_textmap(name: &'a str, textmap: TextMap<'a>) -> Self {
+        let hexen = is_hexen_namespace(textmap.namespace);
+        let mut extra = UdmfExtra {
+            namespace: textmap.namespace,
+            globals: textmap.globals,
+            ..UdmfExtra::default()
+        };
+        let mut level = Self {
+            name,
+            format: LevelFormat::Udmf,
+            behavior: None,
+            things: Vec::new(),
+            linedefs: Vec::new(),
+            sidedefs: Vec::new(),
+            vertices: Vec::new(),
+            segments: Vec::new(),
+            subsectors: Vec::new(),
+            nodes: Vec::new(),
+            sectors: Vec::new(),
+            reject: None,
+            blockmap: None,
+            gl_nodes: None,
+            udmf: None,
+        };
+        for block in textmap.blocks {
+            let fields = block.fields;
+            match block.kind.to_ascii_lowercase().as_str() {
+                "thing" => {
+                    let (thing, fields) = read_thing(fields, hexen);
+                    level.things.push(thing);
+                    extra.things.push(fields);
+                }
+                "vertex" => {
+                    let (vertex, fields) = read_vertex(fields);
+                    level.vertices.push(vertex);
+                    extra.vertices.push(fields);
+                }
+                "linedef" => {
+                    let (linedef, fields) = read_linedef(fields, hexen);
+                    level.linedefs.push(linedef);
+                    extra.linedefs.push(fields);
+                }
+                "sidedef" => {
+                    let (sidedef, fields) = read_sidedef(fields);
+                    level.sidedefs.push(sidedef);
+                    extra.sidedefs.push(fields);
+                }
+                "sector" => {
+                    let (sector, fields) = read_sector(fields);
+                    level.sectors.push(sector);
+                    extra.sectors.push(fields);
+                }
+                _ => extra.blocks.push(Block {
+                    kind: block.kind,
+                    fields,
+                }),
+            }
+        }
+        level.udmf = Some(extra);
+        level
+    }
+
+    /// Converts level of any format into UDMF, binary ones getting `doom` or `hexen` namespace.
+    pub fn to_textmap(&self) -> TextMap<'a> {
+        let udmf = self.udmf.as_ref();
+        let namespace = match (udmf, self.format) {
+            (Some(udmf), _) => udmf.namespace,
+            (None, LevelFormat::Hexen) => "hexen",
+            (None, _) => "doom",
+        };
+        let hexen = is_hexen_namespace(namespace);
+
+        let mut blocks = Vec::new();
+        for (i, thing) in self.things.iter().enumerate() {
+            let flags: &[FlagBit] = if hexen {
+                &HEXEN_THING_FLAGS
+            } else {
+                &DOOM_THING_FLAGS
+            };
+            let writer = FieldWriter::new()
+                .int_or("id", thing.tid, 0)
+                .float("x", thing.x_pos)
+                .float("y", thing.y_pos);
+            let writer = if thing.z_pos == 0 {
+                writer
+            } else {
+                writer.float("height", thing.z_pos)
+            };
+            let block = writer
+                .int_or("angle", thing.angle, 0)
+                .int("type", thing.ttype)
+                .int_or("special", thing.special, 0)
+                .args(&thing.args)
+                .flags(flags, thing.options)
+                .build("thing", udmf.and_then(|udmf| udmf.things.get(i)));
+            blocks.push(block);
+        }
+        for (i, &(x, y)) in self.vertices.iter().enumerate() {
+            let block = FieldWriter::new()
+                .float("x", x)
+                .float("y", y)
+                .build("vertex", udmf.and_then(|udmf| udmf.vertices.get(i)));
+            blocks.push(block);
+        }
+        for (i, linedef) in self.linedefs.iter().enumerate() {
+            let writer = FieldWriter::new()
+                .int("v1", linedef.vertex_start)
+                .int("v2", linedef.vertex_end)
+                .int("sidefront", linedef.sidedef_right.map_or(-1, i64::from))
+                .int_or("sideback", linedef.sidedef_left.map_or(-1, i64::from), -1)
+                .int_or("special", linedef.function, 0)
+                .flags(&LINEDEF_FLAGS, linedef.flags);
+            let writer = if hexen {
+                let activation = ((linedef.flags & ACTIVATION_MASK) >> ACTIVATION_SHIFT) as usize;
+                // Lines without special have zero activation, which isn't worth writing
+                let activation = ACTIVATIONS
+                    .get(activation)
+                    .filter(|_| linedef.function != 0 || activation != 0);
+                writer
+                    .args(&linedef.args)
+                    .flags(&HEXEN_LINEDEF_FLAGS, linedef.flags)
+                    .flag(activation.copied())
+            } else {
+                let mut args = linedef.args;
+                args[0] = 0;
+                writer
+                    .int_or("arg0", linedef.tag, 0)
+                    .args(&args)
+                    .flags(&DOOM_LINEDEF_FLAGS, linedef.flags)
+            };
+            blocks.push(writer.build("linedef", udmf.and_then(|udmf| udmf.linedefs.get(i))));
+        }
+        for (i, sidedef) in self.sidedefs.iter().enumerate() {
+            let mut writer = FieldWriter::new()
+                .int_or("offsetx", sidedef.x_offset, 0)
+                .int_or("offsety", sidedef.y_offset, 0);
+            for &(key, texture) in &[
+                ("texturetop", sidedef.upper_texture),
+                ("texturebottom", sidedef.lower_texture),
+                ("texturemiddle", sidedef.mid_texture),
+            ] {
+                if texture != NO_TEXTURE {
+                    writer = writer.string(key, texture);
+                }
+            }
+            let block = writer
+                .int("sector", sidedef.sector_ref)
+                .build("sidedef", udmf.and_then(|udmf| udmf.sidedefs.get(i)));
+            blocks.push(block);
+        }
+        for (i, sector) in self.sectors.iter().enumerate() {
+            let block = FieldWriter::new()
+                .int_or("heightfloor", sector.floor_height, 0)
+                .int_or("heightceiling", sector.ceiling_height, 0)
+                .string("texturefloor", sector.floor_pic)
+                .string("textureceiling", sector.ceiling_pic)
+                .int_or("lightlevel", sector.light_level, DEFAULT_LIGHT)
+                .int_or("special", sector.special_sector, 0)
+                .int_or("id", sector.tag, 0)
+                .build("sector", udmf.and_then(|udmf| udmf.sectors.get(i)));
+            blocks.push(block);
+        }
+        if let Some(udmf) = udmf {
+            blocks.extend(udmf.blocks.iter().cloned());
+        }
+
+        TextMap {
+            namespace,
+            globals: udmf.map(|udmf| udmf.globals.clone()).unwrap_or_default(),
+            blocks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get, Fields, TextMap, Value};
+    use crate::{error::ErrorKind, wad::parser::level::Level};
+
+    const TEXTMAP: &[u8] = br#"
+        // exported by some editor
+        namespace = "zdoom";
+        ignored_global = 0x10;
+
+        thing // 0
+        {
+            x = 32.0;
+            y = -64.5;
+            type = 1;
+            skill1 = true;
+            single = true;
+            arg0 = 3;
+            user_color = "Red";
+        }
+
+        vertex { x = 0.0; y = 0.0; }
+        vertex { x = 64.0; y = 0.0; }
+
+        linedef
+        {
+            v1 = 0; v2 = 1; sidefront = 0;
+            special = 80; arg0 = 1;
+            playeruse = true; repeatspecial = true;
+            /* not in the model */ alpha = 0.5;
+        }
+
+        sidedef { sector = 0; texturemiddle = "STARTAN3"; }
+
+        sector
+        {
+            texturefloor = "FLOOR0_1";
+            textureceiling = "CEIL1_1";
+            heightceiling = 128;
+            lightlevel = 192;
+        }
+
+        slope { a = 1; }
+    "#;
+
+    #[test]
+    fn roundtrip_textmap() {
+        let textmap = TextMap::parse(TEXTMAP).expect("Error parsing TEXTMAP");
+        assert_eq!(textmap.namespace, "zdoom");
+        assert_eq!(textmap.globals, [("ignored_global", Value::Int(16))]);
+
+        let level = Level::from_textmap("MAP01", textmap.clone());
+        assert_eq!(level.things[0].y_pos, -65);
+        assert_eq!(level.things[0].options, 0x101);
+        assert_eq!(level.things[0].args, [3, 0, 0, 0, 0]);
+        assert_eq!(level.vertices, [(0, 0), (64, 0)]);
+        assert_eq!(level.linedefs[0].flags, 0x600);
+        assert_eq!(level.sidedefs[0].mid_texture, "STARTAN3");
+        assert_eq!(level.sectors[0].light_level, 192);
+        let udmf = level.udmf.as_ref().expect("No UDMF fields");
+        assert_eq!(
+            udmf.things[0],
+            [
+                ("y", Value::Float(-64.5)),
+                ("user_color", Value::Str("Red"))
+            ]
+        );
+        assert_eq!(udmf.linedefs[0], [("alpha", Value::Float(0.5))]);
+
+        let mut output = Vec::new();
+        level
+            .to_textmap()
+            .write_to(&mut output)
+            .expect("Error writing TEXTMAP");
+        let reparsed = TextMap::parse(&output).expect("Error parsing written TEXTMAP");
+        let level = Level::from_textmap("MAP01", reparsed);
+        assert_eq!(
+            level.to_textmap(),
+            Level::from_textmap("MAP01", textmap).to_textmap()
+        );
+
+        let error = TextMap::parse(b"namespace = \"doom\"; thing { x = 1 }")
+            .err()
+            .and_then(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::Malformed));
+    }
+
+    #[test]
+    fn convert_binary_levels() {
+        use crate::wad::parser::{
+            file::{Archive, ArchiveBuilder, Type},
+            level::Levels,
+        };
+
+        let words = |words: &[i16]| -> Vec<u8> {
+            words.iter().flat_map(|word| word.to_le_bytes()).collect()
+        };
+        let sidedef = [
+            &words(&[0, 0])[..],
+            b"-\0\0\0\0\0\0\0-\0\0\0\0\0\0\0STARTAN3",
+            &words(&[0]),
+        ]
+        .concat();
+        let sector = [
+            &words(&[0, 128])[..],
+            b"FLOOR0_1CEIL1_1\0",
+            &words(&[160, 0, 0]),
+        ]
+        .concat();
+        let wad = ArchiveBuilder::new(Type::PWAD)
+            .marker("MAP01")
+            .lump(
+                "THINGS",
+                [
+                    &words(&[7, 32, 64, 0, 90, 3001, 0x0107])[..],
+                    &[80, 1, 2, 3, 4, 5],
+                ]
+                .concat(),
+            )
+            .lump(
+                "LINEDEFS",
+                [
+                    &words(&[1, 0, 0x0601])[..],
+                    &[12, 6, 7, 8, 9, 10],
+                    &words(&[0, -1]),
+                ]
+                .concat(),
+            )
+            .lump("SIDEDEFS", sidedef.clone())
+            .lump("VERTEXES", words(&[0, 0, 64, 0]))
+            .lump("SECTORS", sector.clone())
+            .lump("BEHAVIOR", b"ACS\0".to_vec())
+            .marker("MAP02")
+            .lump("THINGS", words(&[32, 64, 0, 1, 0x0007]))
+            .lump("LINEDEFS", words(&[1, 0, 0x0004, 46, 5, 0, -1]))
+            .lump("SIDEDEFS", sidedef)
+            .lump("VERTEXES", words(&[0, 0, 64, 0]))
+            .lump("SECTORS", sector)
+            .to_bytes()
+            .expect("Error writing wad");
+        let archive = Archive::parse(&wad).expect("Wad file parser error");
+        let levels = Levels::parse(archive.iter()).expect("Error parsing levels");
+
+        fn block<'a>(textmap: &TextMap<'a>, kind: &str) -> Fields<'a> {
+            let block = textmap.blocks.iter().find(|block| block.kind == kind);
+            block.expect("Block not written").fields.clone()
+        }
+        let hexen = levels[0].to_textmap();
+        assert_eq!(hexen.namespace, "hexen");
+        let thing = block(&hexen, "thing");
+        for &(key, value) in &[
+            ("id", Value::Int(7)),
+            ("type", Value::Int(3001)),
+            ("special", Value::Int(80)),
+            ("arg0", Value::Int(1)),
+            ("arg4", Value::Int(5)),
+            ("skill5", Value::Bool(true)),
+            ("single", Value::Bool(true)),
+        ] {
+            assert_eq!(get(&thing, key), Some(&value), "thing {}", key);
+        }
+        assert_eq!(get(&thing, "coop"), None);
+        let linedef = block(&hexen, "linedef");
+        for &(key, value) in &[
+            ("special", Value::Int(12)),
+            ("arg0", Value::Int(6)),
+            ("arg4", Value::Int(10)),
+            ("blocking", Value::Bool(true)),
+            ("repeatspecial", Value::Bool(true)),
+            ("playeruse", Value::Bool(true)),
+        ] {
+            assert_eq!(get(&linedef, key), Some(&value), "linedef {}", key);
+        }
+        assert_eq!(get(&linedef, "playercross"), None);
+
+        let doom = levels[1].to_textmap();
+        assert_eq!(doom.namespace, "doom");
+        let linedef = block(&doom, "linedef");
+        assert_eq!(get(&linedef, "special"), Some(&Value::Int(46)));
+        assert_eq!(get(&linedef, "arg0"), Some(&Value::Int(5)));
+        assert_eq!(get(&linedef, "twosided"), Some(&Value::Bool(true)));
+        assert_eq!(get(&linedef, "playeruse"), None);
+
+        let mut output = Vec::new();
+        hexen.write_to(&mut output).expect("Error writing TEXTMAP");
+        let reparsed = Level::from_textmap("MAP01", TextMap::parse(&output).expect("Bad TEXTMAP"));
+        assert_eq!(reparsed.linedefs[0].flags, 0x0601);
+        assert_eq!(reparsed.things[0].options, 0x0107);
+
+        let infinite = TextMap {
+            namespace: "doom",
+            globals: vec![("scale", Value::Float(f64::INFINITY))],
+            blocks: Vec::new(),
+        };
+        let error = infinite.write_to(Vec::new()).err().map(|e| e.kind());
+        assert_eq!(error, Some(std::io::ErrorKind::InvalidInput));
+    }
+}
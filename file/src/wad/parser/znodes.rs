@@ -0,0 +1,354 @@
+use super::{
+    glnodes::{GlNodes, GlSeg, GlVersion, GlVertex, GlVertexRef},
+    level::{BoundingBox, Level, Node, Segment, SubSector},
+    types::{item_count, run, Input, OnlyResult, ParseResult},
+};
+use crate::error::{Error, ErrorKind};
+use flate2::read::ZlibDecoder;
+use nom::{
+    bytes::complete::take,
+    multi::length_count,
+    number::complete::{le_i32, le_u16, le_u32, le_u8},
+    sequence::{preceded, tuple},
+};
+use std::{f64::consts::PI, io::Read};
+
+const NONE_16: u16 = 0xFFFF;
+const NONE_32: u32 = 0xFFFF_FFFF;
+
+/// Layout of ZDoom extended nodes, each one may be stored zlib-compressed as well.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExtendedFormat {
+    /// `XNOD`/`ZNOD`, regular segs with both vertices
+    Xnod,
+    /// `XGLN`/`ZGLN`, GL segs with 16-bit linedefs
+    Xgln,
+    /// `XGL2`/`ZGL2`, GL segs with 32-bit linedefs
+    Xgl2,
+    /// `XGL3`/`ZGL3`, fixed-point partition lines
+    Xgl3,
+}
+
+impl ExtendedFormat {
+    /// Format and compression told by the signature, `Z` standing for zlib.
+    pub fn detect(data: &[u8]) -> Option<(Self, bool)> {
+        let format = match data.get(1..4)? {
+            b"NOD" => Self::Xnod,
+            b"GLN" => Self::Xgln,
+            b"GL2" => Self::Xgl2,
+            b"GL3" => Self::Xgl3,
+            _ => return None,
+        };
+        match data[0] {
+            b'X' => Some((format, false)),
+            b'Z' => Some((format, true)),
+            _ => None,
+        }
+    }
+
+    pub fn is_gl(self) -> bool {
+        self != Self::Xnod
+    }
+}
+
+/// ZDoom nodes found in `NODES` (or `SSECTORS` for GL ones) of binary level
+/// and in `ZNODES` of UDMF one, with 32-bit indices throughout.
+pub struct ExtendedNodes {
+    pub format: ExtendedFormat,
+    pub compressed: bool,
+    /// Number of level's vertices the nodes were built for, the new ones follow them
+    pub original_vertices: u32,
+    /// Vertices made by splitting segs
+    pub vertices: Vec<GlVertex>,
+    pub subsectors: Vec<SubSector>,
+    /// Segs in subsector order, GL ones end where the next seg of the subsector starts
+    pub segs: Vec<GlSeg>,
+    pub nodes: Vec<Node>,
+}
+
+fn parse_seg(
+    format: ExtendedFormat,
+    original_vertices: u32,
+) -> impl FnMut(Input<'_>) -> ParseResult<'_, GlSeg> {
+    let vertex_ref = move |index: u32| match index.checked_sub(original_vertices) {
+        Some(new) => GlVertexRef::Gl(new),
+        None => GlVertexRef::Normal(index),
+    };
+    move |i| {
+        let (i, (start, other, linedef, side)) = match format {
+            ExtendedFormat::Xnod | ExtendedFormat::Xgln => {
+                let (i, (start, other, linedef, side)) = tuple((le_u32, le_u32, le_u16, le_u8))(i)?;
+                let linedef = if linedef == NONE_16 {
+                    NONE_32
+                } else {
+                    linedef.into()
+                };
+                (i, (start, other, linedef, side))
+            }
+            ExtendedFormat::Xgl2 | ExtendedFormat::Xgl3 => {
+                tuple((le_u32, le_u32, le_u32, le_u8))(i)?
+            }
+        };
+        // GL segs store their partner in place of the end
+        let (end, partner) = if format.is_gl() {
+            (start, Some(other).filter(|&partner| partner != NONE_32))
+        } else {
+            (other, None)
+        };
+        Ok((
+            i,
+            GlSeg {
+                start: vertex_ref(start),
+                end: vertex_ref(end),
+                linedef: Some(linedef).filter(|&linedef| linedef != NONE_32),
+                side: side.into(),
+                partner,
+            },
+        ))
+    }
+}
+
+/// Parses node of `XGL3` with fixed-point partition line, keeping its integer part.
+fn parse_fixed_node(i: Input<'_>) -> ParseResult<'_, Node> {
+    let (i, (x, y, dx, dy, bbox1, bbox2, child1, child2)) = tuple((
+        le_i32,
+        le_i32,
+        le_i32,
+        le_i32,
+        BoundingBox::parse,
+        BoundingBox::parse,
+        le_u32,
+        le_u32,
+    ))(i)?;
+    Ok((
+        i,
+        Node {
+            x: (x >> 16) as i16,
+            y: (y >> 16) as i16,
+            dx: (dx >> 16) as i16,
+            dy: (dy >> 16) as i16,
+            bbox: [bbox1, bbox2],
+            children: [child1, child2],
+        },
+    ))
+}
+
+fn parse_body(
+    format: ExtendedFormat,
+    compressed: bool,
+) -> impl FnMut(Input<'_>) -> ParseResult<'_, ExtendedNodes> {
+    move |i| {
+        let (i, original_vertices) = le_u32(i)?;
+        let (i, vertices) = length_count(item_count(le_u32), tuple((le_i32, le_i32)))(i)?;
+        let (i, counts) = length_count(item_count(le_u32), le_u32)(i)?;
+        let (i, mut segs) =
+            length_count(item_count(le_u32), parse_seg(format, original_vertices))(i)?;
+        let (i, nodes) = if format == ExtendedFormat::Xgl3 {
+            length_count(item_count(le_u32), parse_fixed_node)(i)?
+        } else {
+            length_count(item_count(le_u32), Node::parse_extended)(i)?
+        };
+
+        let mut subsectors = Vec::with_capacity(counts.len());
+        let mut start_seg = 0u32;
+        for numsegs in counts {
+            subsectors.push(SubSector { numsegs, start_seg });
+            start_seg = start_seg.wrapping_add(numsegs);
+        }
+        if format.is_gl() {
+            for subsector in &subsectors {
+                let start = subsector.start_seg as usize;
+                let count = subsector.numsegs as usize;
+                if let Some(segs) = segs.get_mut(start..start.saturating_add(count)) {
+                    for k in 0..segs.len() {
+                        segs[k].end = segs[(k + 1) % segs.len()].start;
+                    }
+                }
+            }
+        }
+
+        Ok((
+            i,
+            ExtendedNodes {
+                format,
+                compressed,
+                original_vertices,
+                vertices,
+                subsectors,
+                segs,
+                nodes,
+            },
+        ))
+    }
+}
+
+/// Most data compressed nodes may inflate to, far more than the largest maps need.
+const MAX_INFLATED_SIZE: u64 = 1 << 28;
+
+/// Inflates zlib data, `None` if it's broken or takes more than `limit` bytes.
+fn inflate(i: Input<'_>, limit: u64) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(i)
+        .take(limit + 1)
+        .read_to_end(&mut data)
+        .ok()?;
+    Some(data).filter(|data| data.len() as u64 <= limit)
+}
+
+impl ExtendedNodes {
+    /// Parses nodes of any extended format, `None` if data has no known signature.
+    /// Errors in compressed nodes are reported at offsets of the inflated data,
+    /// ones inflating past a sane size are [`ErrorKind::Malformed`].
+    pub fn parse(i: Input<'_>) -> Option<OnlyResult<Self>> {
+        let (format, compressed) = ExtendedFormat::detect(i)?;
+        if !compressed {
+            return Some(run(preceded(take(4usize), parse_body(format, false)), i));
+        }
+        match inflate(&i[4..], MAX_INFLATED_SIZE) {
+            Some(data) => Some(run(parse_body(format, true), &data)),
+            None => Some(Err(Error::new(ErrorKind::Malformed, 4))),
+        }
+    }
+
+    /// Puts nodes into the level: regular ones replace its segs, subsectors and nodes
+    /// with new vertices (rounded down) following the original ones, GL ones become its GL nodes.
+    /// Fails with [`ErrorKind::OutOfBounds`] when the level has fewer vertices than the nodes need.
+    pub fn apply_to(self, level: &mut Level<'_>) -> OnlyResult<()> {
+        if self.format.is_gl() {
+            level.gl_nodes = Some(GlNodes {
+                version: GlVersion::Zdoom(self.format),
+                vertices: self.vertices,
+                segs: self.segs,
+                subsectors: self.subsectors,
+                nodes: self.nodes,
+            });
+            return Ok(());
+        }
+        let original = self.original_vertices as usize;
+        if original > level.vertices.len() {
+            return Err(Error::new(ErrorKind::OutOfBounds, 4));
+        }
+        level.vertices.truncate(original);
+        level.vertices.extend(
+            self.vertices
+                .iter()
+                .map(|&(x, y)| ((x >> 16) as i16, (y >> 16) as i16)),
+        );
+
+        let vertices = &level.vertices;
+        let index = |vertex: GlVertexRef| match vertex {
+            GlVertexRef::Normal(index) => index,
+            GlVertexRef::Gl(index) => index.wrapping_add(original as u32),
+        };
+        let point = |index: u32| {
+            vertices
+                .get(index as usize)
+                .map(|&(x, y)| (f64::from(x), f64::from(y)))
+        };
+        level.segments = self
+            .segs
+            .iter()
+            .map(|seg| {
+                let (vertex_start, vertex_end) = (index(seg.start), index(seg.end));
+                let line_num = seg.linedef.unwrap_or(NONE_32);
+                let (mut bams, mut segoffset) = (0, 0);
+                if let (Some(a), Some(b)) = (point(vertex_start), point(vertex_end)) {
+                    let angle = (b.1 - a.1).atan2(b.0 - a.0).rem_euclid(2.0 * PI);
+                    bams = (angle / (2.0 * PI) * 65536.0) as u32 as u16 as i16;
+                    let from = level.linedefs.get(line_num as usize).and_then(|linedef| {
+                        let (start, end) = linedef.ends(vertices)?;
+                        Some(if seg.side == 0 { start } else { end })
+                    });
+                    if let Some((x, y)) = from {
+                        segoffset = (a.0 - f64::from(x)).hypot(a.1 - f64::from(y)).round() as i16;
+                    }
+                }
+                Segment {
+                    vertex_start,
+                    vertex_end,
+                    bams,
+                    line_num,
+                    segside: seg.side as i16,
+                    segoffset,
+                }
+            })
+            .collect();
+        level.subsectors = self.subsectors;
+        level.nodes = self.nodes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtendedFormat, ExtendedNodes};
+    use crate::wad::parser::{glnodes::GlVertexRef, level::SUBSECTOR_FLAG};
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn parse_plain_and_compressed() {
+        // 2 original vertices, 1 new, single subsector of a triangle with 3 GL segs
+        let mut body = u32s(&[2, 1, 5 << 16, 7 << 16, 1, 3, 3]);
+        for &(start, partner, linedef) in &[(0u32, 0xFFFF_FFFFu32, 0u16), (1, 0, 1), (2, 7, 0xFFFF)]
+        {
+            body.extend_from_slice(&u32s(&[start, partner]));
+            body.extend_from_slice(&linedef.to_le_bytes());
+            body.push(0);
+        }
+        body.extend_from_slice(&u32s(&[1]));
+        body.extend_from_slice(&[0; 24]);
+        body.extend_from_slice(&u32s(&[SUBSECTOR_FLAG, SUBSECTOR_FLAG]));
+
+        let plain = [&b"XGLN"[..], &body].concat();
+        let mut encoder = ZlibEncoder::new(b"ZGLN".to_vec(), Compression::default());
+        encoder.write_all(&body).expect("Error compressing nodes");
+        let compressed = encoder.finish().expect("Error compressing nodes");
+
+        for (data, is_compressed) in &[(plain, false), (compressed, true)] {
+            let nodes = ExtendedNodes::parse(data)
+                .expect("No extended nodes")
+                .expect("Error parsing extended nodes");
+            assert_eq!(nodes.format, ExtendedFormat::Xgln);
+            assert_eq!(nodes.compressed, *is_compressed);
+            assert_eq!(nodes.vertices, [(5 << 16, 7 << 16)]);
+            assert_eq!(nodes.subsectors[0].numsegs, 3);
+            let ends: Vec<_> = nodes.segs.iter().map(|seg| (seg.start, seg.end)).collect();
+            assert_eq!(
+                ends,
+                [
+                    (GlVertexRef::Normal(0), GlVertexRef::Normal(1)),
+                    (GlVertexRef::Normal(1), GlVertexRef::Gl(0)),
+                    (GlVertexRef::Gl(0), GlVertexRef::Normal(0)),
+                ]
+            );
+            assert_eq!(nodes.segs[1].partner, Some(0));
+            assert_eq!(nodes.segs[2].linedef, None);
+            assert_eq!(nodes.nodes[0].children, [SUBSECTOR_FLAG; 2]);
+        }
+        assert!(ExtendedNodes::parse(b"NODE").is_none());
+    }
+
+    #[test]
+    fn limit_inflated_size() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&[0; 4096])
+            .expect("Error compressing nodes");
+        let compressed = encoder.finish().expect("Error compressing nodes");
+
+        assert_eq!(
+            super::inflate(&compressed, 4096).map(|data| data.len()),
+            Some(4096)
+        );
+        assert!(super::inflate(&compressed, 4095).is_none());
+        assert!(super::inflate(&compressed[..8], 4096).is_none());
+    }
+}
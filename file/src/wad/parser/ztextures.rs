@@ -0,0 +1,819 @@
+use super::{
+    texture::Texture,
+    types::{run, Input, OnlyResult, ParseError, ParseResult},
+    udmf::{number, quoted, skip, symbol, utf8, Value},
+};
+use crate::error::ErrorKind;
+
+/// Token of ZDoom text definition lumps, numbers keep their sign.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token<'a> {
+    /// Unquoted keyword or name
+    Word(&'a str),
+    /// Quoted string without the quotes
+    Str(&'a str),
+    Int(i64),
+    Float(f64),
+    /// One of `{`, `}` and `,`
+    Symbol(u8),
+}
+
+fn is_word_char(c: u8) -> bool {
+    !c.is_ascii_whitespace() && !matches!(c, b'{' | b'}' | b',' | b'"')
+}
+
+/// Whether input goes on with a word rather than ending the token before it.
+fn continues_word(i: Input<'_>) -> bool {
+    match i {
+        [b'/', b'/', ..] | [b'/', b'*', ..] => false,
+        [c, ..] => is_word_char(*c),
+        [] => false,
+    }
+}
+
+fn word(i: Input<'_>) -> ParseResult<'_, &str> {
+    let len = i.iter().position(|&c| !is_word_char(c)).unwrap_or(i.len());
+    Ok((&i[len..], utf8(&i[..len])?))
+}
+
+/// Next token after whitespace and comments, `None` at the end of input.
+fn token(i: Input<'_>) -> ParseResult<'_, Option<Token<'_>>> {
+    let (i, _) = skip(i)?;
+    let (i, token) = match i.first() {
+        None => return Ok((i, None)),
+        Some(b'"') => quoted(i).map(|(i, s)| (i, Token::Str(s)))?,
+        Some(&c) if matches!(c, b'{' | b'}' | b',') => (&i[1..], Token::Symbol(c)),
+        Some(&c) if c.is_ascii_digit() || matches!(c, b'+' | b'-' | b'.') => match number(i) {
+            // Names like `-NOFLAT-` or `1_PATCH` aren't numbers
+            Ok((rest, _)) if continues_word(rest) => word(i).map(|(i, s)| (i, Token::Word(s)))?,
+            Ok((i, Value::Int(x))) => (i, Token::Int(x)),
+            Ok((i, Value::Float(x))) => (i, Token::Float(x)),
+            _ => word(i).map(|(i, s)| (i, Token::Word(s)))?,
+        },
+        Some(_) => word(i).map(|(i, s)| (i, Token::Word(s)))?,
+    };
+    Ok((i, Some(token)))
+}
+
+/// Splits the lump into tokens, skipping comments.
+pub fn tokenize(lump: &[u8]) -> OnlyResult<Vec<Token<'_>>> {
+    run(
+        |mut i| {
+            let mut tokens = Vec::new();
+            while let (rest, Some(token)) = token(i)? {
+                tokens.push(token);
+                i = rest;
+            }
+            Ok((i, tokens))
+        },
+        lump,
+    )
+}
+
+fn keyword(i: Input<'_>) -> ParseResult<'_, &str> {
+    match token(i)? {
+        (rest, Some(Token::Word(s))) => Ok((rest, s)),
+        (_, None) => ParseError::fail(i, ErrorKind::Truncated),
+        _ => ParseError::fail(i, ErrorKind::Malformed),
+    }
+}
+
+/// Quoted or bare name.
+fn name(i: Input<'_>) -> ParseResult<'_, &str> {
+    match token(i)? {
+        (rest, Some(Token::Word(s))) | (rest, Some(Token::Str(s))) => Ok((rest, s)),
+        (_, None) => ParseError::fail(i, ErrorKind::Truncated),
+        _ => ParseError::fail(i, ErrorKind::Malformed),
+    }
+}
+
+fn int(i: Input<'_>) -> ParseResult<'_, i64> {
+    match token(i)? {
+        (rest, Some(Token::Int(x))) => Ok((rest, x)),
+        (_, None) => ParseError::fail(i, ErrorKind::Truncated),
+        _ => ParseError::fail(i, ErrorKind::Malformed),
+    }
+}
+
+fn float(i: Input<'_>) -> ParseResult<'_, f64> {
+    match token(i)? {
+        (rest, Some(Token::Int(x))) => Ok((rest, x as f64)),
+        (rest, Some(Token::Float(x))) => Ok((rest, x)),
+        (_, None) => ParseError::fail(i, ErrorKind::Truncated),
+        _ => ParseError::fail(i, ErrorKind::Malformed),
+    }
+}
+
+/// Consumes `c` if it comes next.
+fn optional_symbol(c: u8, i: Input<'_>) -> (Input<'_>, bool) {
+    match symbol(c)(i) {
+        Ok((rest, _)) => (rest, true),
+        Err(_) => (i, false),
+    }
+}
+
+/// Parses `, value` if a comma comes next.
+fn optional_argument<'a, O, P>(parser: P, i: Input<'a>) -> ParseResult<'a, Option<O>>
+where
+    P: Fn(Input<'a>) -> ParseResult<'a, O>,
+{
+    match optional_symbol(b',', i) {
+        (rest, true) => parser(rest).map(|(rest, value)| (rest, Some(value))),
+        (rest, false) => Ok((rest, None)),
+    }
+}
+
+fn int_in<T: std::convert::TryFrom<i64>>(i: Input<'_>) -> ParseResult<'_, T> {
+    let (rest, x) = int(i)?;
+    match T::try_from(x) {
+        Ok(x) => Ok((rest, x)),
+        Err(_) => ParseError::fail(i, ErrorKind::Malformed),
+    }
+}
+
+/// Skips arguments of an unknown keyword along with a block following them.
+/// Any word ends the arguments, unless it follows a comma or it's the first one
+/// and the keyword is `named` (like top-level `Define`). Properties inside blocks
+/// aren't named, so unknown flags don't swallow the known ones after them.
+fn skip_statement(mut i: Input<'_>, named: bool) -> ParseResult<'_, ()> {
+    let mut first = named;
+    loop {
+        i = match token(i)? {
+            (rest, Some(Token::Str(_))) | (rest, Some(Token::Int(_))) => rest,
+            (rest, Some(Token::Float(_))) => rest,
+            (rest, Some(Token::Word(_))) if first => rest,
+            (rest, Some(Token::Symbol(b','))) => token(rest)?.0,
+            _ => break,
+        };
+        first = false;
+    }
+    let (mut i, block) = optional_symbol(b'{', i);
+    let mut depth = usize::from(block);
+    while depth > 0 {
+        i = match token(i)? {
+            (rest, Some(Token::Symbol(b'{'))) => {
+                depth += 1;
+                rest
+            }
+            (rest, Some(Token::Symbol(b'}'))) => {
+                depth -= 1;
+                rest
+            }
+            (rest, Some(_)) => rest,
+            (_, None) => return ParseError::fail(i, ErrorKind::Truncated),
+        };
+    }
+    Ok((i, ()))
+}
+
+/// Keywords skipped with their positions.
+type Unknown<'a> = Vec<(&'a str, Input<'a>)>;
+
+/// Kind of a top-level definition, telling the namespace it replaces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureKind {
+    Texture,
+    WallTexture,
+    Flat,
+    Sprite,
+    Graphic,
+}
+
+impl TextureKind {
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        [
+            ("Texture", Self::Texture),
+            ("WallTexture", Self::WallTexture),
+            ("Flat", Self::Flat),
+            ("Sprite", Self::Sprite),
+            ("Graphic", Self::Graphic),
+        ]
+        .iter()
+        .find(|(name, _)| keyword.eq_ignore_ascii_case(name))
+        .map(|&(_, kind)| kind)
+    }
+}
+
+/// Part of a palette remapped by `Translation "start:end=..."`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TranslationRange<'a> {
+    pub start: u8,
+    pub end: u8,
+    /// Destination indices, `None` for color forms (`[r,g,b]:[r,g,b]`, `%`, `#`, `@`)
+    /// kept as written in `target`
+    pub indices: Option<(u8, u8)>,
+    pub target: &'a str,
+}
+
+impl<'a> TranslationRange<'a> {
+    fn parse(range: &'a str) -> Option<Self> {
+        let (source, target) = range.split_once('=')?;
+        let indices = |s: &str| -> Option<(u8, u8)> {
+            let (start, end) = s.split_once(':')?;
+            Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+        };
+        let (start, end) = indices(source)?;
+        let target = target.trim();
+        Some(Self {
+            start,
+            end,
+            indices: indices(target),
+            target,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Translation<'a> {
+    /// Builtin translation like `Inverse`, `Gold`, `Red`, `Green` or `Ice`
+    Named(&'a str),
+    /// `Desaturate, amount`
+    Desaturate(i64),
+    Ranges(Vec<TranslationRange<'a>>),
+}
+
+impl Translation<'_> {
+    /// Palette remap of index ranges, `None` if the translation needs the palette colors.
+    pub fn index_map(&self) -> Option<[u8; 256]> {
+        let ranges = match self {
+            Self::Ranges(ranges) => ranges,
+            _ => return None,
+        };
+        let mut map = [0; 256];
+        map.iter_mut().enumerate().for_each(|(i, x)| *x = i as u8);
+        let mut any = false;
+        for range in ranges {
+            let (to_start, to_end) = match range.indices {
+                Some(indices) => indices,
+                None => continue,
+            };
+            any = true;
+            let (start, end) = (i32::from(range.start), i32::from(range.end));
+            let (to_start, to_end) = (i32::from(to_start), i32::from(to_end));
+            for index in start.min(end)..=start.max(end) {
+                let target = if start == end {
+                    to_start
+                } else {
+                    to_start + (to_end - to_start) * (index - start) / (end - start)
+                };
+                map[index as usize] = target as u8;
+            }
+        }
+        Some(map).filter(|_| any)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendColor<'a> {
+    /// Color name or `"rr gg bb"`/`"#rrggbb"` string
+    Named(&'a str),
+    Rgb([u8; 3]),
+}
+
+/// `Blend color[, alpha]`, colorizing the patch without alpha and tinting it with one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Blend<'a> {
+    pub color: BlendColor<'a>,
+    pub alpha: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderStyle {
+    Copy,
+    Translucent,
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Modulate,
+    CopyAlpha,
+    CopyNewAlpha,
+    Overlay,
+}
+
+impl RenderStyle {
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        [
+            ("Copy", Self::Copy),
+            ("Translucent", Self::Translucent),
+            ("Add", Self::Add),
+            ("Subtract", Self::Subtract),
+            ("ReverseSubtract", Self::ReverseSubtract),
+            ("Modulate", Self::Modulate),
+            ("CopyAlpha", Self::CopyAlpha),
+            ("CopyNewAlpha", Self::CopyNewAlpha),
+            ("Overlay", Self::Overlay),
+        ]
+        .iter()
+        .find(|(name, _)| keyword.eq_ignore_ascii_case(name))
+        .map(|&(_, style)| style)
+    }
+}
+
+/// Patch drawn into a texture, by name rather than `PNAMES` index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TexturePatch<'a> {
+    pub name: &'a str,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Clockwise, one of 0, 90, 180 and 270, applied after flipping
+    pub rotate: u16,
+    /// Shift the patch by its own offsets
+    pub use_offsets: bool,
+    pub translation: Option<Translation<'a>>,
+    pub blend: Option<Blend<'a>>,
+    pub alpha: f64,
+    pub style: RenderStyle,
+}
+
+impl<'a> TexturePatch<'a> {
+    fn new(name: &'a str, x_offset: i32, y_offset: i32) -> Self {
+        Self {
+            name,
+            x_offset,
+            y_offset,
+            flip_x: false,
+            flip_y: false,
+            rotate: 0,
+            use_offsets: false,
+            translation: None,
+            blend: None,
+            alpha: 1.0,
+            style: RenderStyle::Copy,
+        }
+    }
+
+    /// Parses `"name", x, y` and optional block of properties after the keyword.
+    fn parse(i: Input<'a>, unknown: &mut Unknown<'a>) -> ParseResult<'a, Self> {
+        let (i, name) = name(i)?;
+        let (i, _) = symbol(b',')(i)?;
+        let (i, x_offset) = int_in(i)?;
+        let (i, _) = symbol(b',')(i)?;
+        let (i, y_offset) = int_in(i)?;
+        let mut patch = Self::new(name, x_offset, y_offset);
+        let (mut i, block) = optional_symbol(b'{', i);
+        if !block {
+            return Ok((i, patch));
+        }
+        loop {
+            if let (rest, true) = optional_symbol(b'}', i) {
+                return Ok((rest, patch));
+            }
+            let (at, _) = skip(i)?;
+            let (rest, property) = keyword(at)?;
+            i = match property.to_ascii_lowercase().as_str() {
+                "flipx" => {
+                    patch.flip_x = true;
+                    rest
+                }
+                "flipy" => {
+                    patch.flip_y = true;
+                    rest
+                }
+                "useoffsets" => {
+                    patch.use_offsets = true;
+                    rest
+                }
+                "rotate" => {
+                    let (rest, angle) = int(rest)?;
+                    if angle % 90 != 0 {
+                        return ParseError::fail(i, ErrorKind::Malformed);
+                    }
+                    patch.rotate = angle.rem_euclid(360) as u16;
+                    rest
+                }
+                "translation" => {
+                    let (rest, translation) = parse_translation(rest)?;
+                    patch.translation = Some(translation);
+                    rest
+                }
+                "blend" => {
+                    let (rest, blend) = parse_blend(rest)?;
+                    patch.blend = Some(blend);
+                    rest
+                }
+                "alpha" => {
+                    let (rest, alpha) = float(rest)?;
+                    patch.alpha = alpha;
+                    rest
+                }
+                "style" => {
+                    let (after, style) = keyword(rest)?;
+                    match RenderStyle::from_keyword(style) {
+                        Some(style) => patch.style = style,
+                        None => unknown.push((style, rest)),
+                    }
+                    after
+                }
+                _ => {
+                    unknown.push((property, at));
+                    skip_statement(rest, false)?.0
+                }
+            };
+        }
+    }
+}
+
+fn parse_translation(i: Input<'_>) -> ParseResult<'_, Translation<'_>> {
+    if let Ok((rest, named)) = keyword(i) {
+        if named.eq_ignore_ascii_case("Desaturate") {
+            let (rest, amount) = optional_argument(int, rest)?;
+            return Ok((rest, Translation::Desaturate(amount.unwrap_or(0))));
+        }
+        return Ok((rest, Translation::Named(named)));
+    }
+    let mut ranges = Vec::new();
+    let mut i = i;
+    loop {
+        let (rest, range) = match token(i)? {
+            (rest, Some(Token::Str(range))) => (rest, range),
+            _ => return ParseError::fail(i, ErrorKind::Malformed),
+        };
+        // A string may hold several comma-separated ranges as well
+        let mut depth = 0;
+        let ranges_in = range.split(|c| {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+            c == ',' && depth == 0
+        });
+        for range in ranges_in {
+            match TranslationRange::parse(range) {
+                Some(range) => ranges.push(range),
+                None => return ParseError::fail(i, ErrorKind::Malformed),
+            }
+        }
+        i = match optional_symbol(b',', rest) {
+            (rest, true) => rest,
+            (rest, false) => return Ok((rest, Translation::Ranges(ranges))),
+        };
+    }
+}
+
+fn parse_blend(i: Input<'_>) -> ParseResult<'_, Blend<'_>> {
+    let (i, color) = match token(i)? {
+        (rest, Some(Token::Str(color))) => (rest, BlendColor::Named(color)),
+        (_, Some(Token::Int(_))) => {
+            let (rest, r) = int_in(i)?;
+            let (rest, _) = symbol(b',')(rest)?;
+            let (rest, g) = int_in(rest)?;
+            let (rest, _) = symbol(b',')(rest)?;
+            let (rest, b) = int_in(rest)?;
+            (rest, BlendColor::Rgb([r, g, b]))
+        }
+        _ => return ParseError::fail(i, ErrorKind::Malformed),
+    };
+    let (i, alpha) = optional_argument(float, i)?;
+    Ok((i, Blend { color, alpha }))
+}
+
+/// Definition of `TEXTURES` lump, see [`TextureDefinition::from_texture`] for the binary ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureDefinition<'a> {
+    pub kind: TextureKind,
+    pub name: &'a str,
+    pub width: i32,
+    pub height: i32,
+    /// Skipped by ZDoom when the name doesn't replace anything
+    pub optional: bool,
+    pub x_scale: f64,
+    pub y_scale: f64,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub world_panning: bool,
+    pub no_decals: bool,
+    pub null_texture: bool,
+    pub no_trim: bool,
+    pub patches: Vec<TexturePatch<'a>>,
+}
+
+impl<'a> TextureDefinition<'a> {
+    /// Same texture as the binary one, `None` if it refers to patches past `pnames`.
+    pub fn from_texture(texture: &Texture<'a>, pnames: &[&'a str]) -> Option<Self> {
+        let patches = texture
+            .patch_descriptors
+            .iter()
+            .map(|descriptor| {
+                let name = pnames.get(descriptor.id as u16 as usize)?;
+                Some(TexturePatch::new(
+                    name,
+                    descriptor.x_offset.into(),
+                    descriptor.y_offset.into(),
+                ))
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            kind: TextureKind::WallTexture,
+            name: texture.name,
+            width: texture.width.into(),
+            height: texture.height.into(),
+            optional: false,
+            x_scale: texture.x_scale(),
+            y_scale: texture.y_scale(),
+            x_offset: 0,
+            y_offset: 0,
+            world_panning: texture.world_panning(),
+            no_decals: false,
+            null_texture: false,
+            no_trim: false,
+            patches,
+        })
+    }
+
+    /// Parses definition following the keyword of its kind.
+    fn parse(i: Input<'a>, kind: TextureKind, unknown: &mut Unknown<'a>) -> ParseResult<'a, Self> {
+        let (i, optional) = match keyword(i) {
+            Ok((rest, word)) if word.eq_ignore_ascii_case("Optional") => (rest, true),
+            _ => (i, false),
+        };
+        let (i, name) = name(i)?;
+        let (i, _) = symbol(b',')(i)?;
+        let (i, width) = int_in(i)?;
+        let (i, _) = symbol(b',')(i)?;
+        let (i, height) = int_in(i)?;
+        let mut definition = Self {
+            kind,
+            name,
+            width,
+            height,
+            optional,
+            x_scale: 1.0,
+            y_scale: 1.0,
+            x_offset: 0,
+            y_offset: 0,
+            world_panning: false,
+            no_decals: false,
+            null_texture: false,
+            no_trim: false,
+            patches: Vec::new(),
+        };
+        let (mut i, block) = optional_symbol(b'{', i);
+        if !block {
+            return Ok((i, definition));
+        }
+        loop {
+            if let (rest, true) = optional_symbol(b'}', i) {
+                return Ok((rest, definition));
+            }
+            let (at, _) = skip(i)?;
+            let (rest, property) = keyword(at)?;
+            i = match property.to_ascii_lowercase().as_str() {
+                "xscale" => {
+                    let (rest, scale) = float(rest)?;
+                    definition.x_scale = scale;
+                    rest
+                }
+                "yscale" => {
+                    let (rest, scale) = float(rest)?;
+                    definition.y_scale = scale;
+                    rest
+                }
+                "offset" => {
+                    let (rest, x) = int_in(rest)?;
+                    let (rest, _) = symbol(b',')(rest)?;
+                    let (rest, y) = int_in(rest)?;
+                    definition.x_offset = x;
+                    definition.y_offset = y;
+                    rest
+                }
+                "worldpanning" => {
+                    definition.world_panning = true;
+                    rest
+                }
+                "nodecals" => {
+                    definition.no_decals = true;
+                    rest
+                }
+                "nulltexture" => {
+                    definition.null_texture = true;
+                    rest
+                }
+                "notrim" => {
+                    definition.no_trim = true;
+                    rest
+                }
+                "patch" | "graphic" | "sprite" => {
+                    let (rest, patch) = TexturePatch::parse(rest, unknown)?;
+                    definition.patches.push(patch);
+                    rest
+                }
+                _ => {
+                    unknown.push((property, at));
+                    skip_statement(rest, false)?.0
+                }
+            };
+        }
+    }
+}
+
+/// Keyword the parser doesn't support, skipped along with its arguments and block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Skipped<'a> {
+    pub keyword: &'a str,
+    /// Offset of the keyword in the lump
+    pub offset: usize,
+}
+
+/// Definitions of `TEXTURES` lump.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureDefinitions<'a> {
+    pub definitions: Vec<TextureDefinition<'a>>,
+    /// Unsupported properties, definitions and directives like `#include`, left to warn about
+    pub skipped: Vec<Skipped<'a>>,
+}
+
+impl<'a> TextureDefinitions<'a> {
+    /// Parses `TEXTURES` lump, only broken syntax of the known keywords makes it fail.
+    pub fn parse(lump: &'a [u8]) -> OnlyResult<Self> {
+        let mut unknown = Vec::new();
+        let definitions = run(
+            |mut i| {
+                let mut definitions = Vec::new();
+                loop {
+                    let (at, _) = skip(i)?;
+                    if at.is_empty() {
+                        return Ok((at, definitions));
+                    }
+                    let (rest, keyword) = keyword(at)?;
+                    i = match TextureKind::from_keyword(keyword) {
+                        Some(kind) => {
+                            let (rest, definition) =
+                                TextureDefinition::parse(rest, kind, &mut unknown)?;
+                            definitions.push(definition);
+                            rest
+                        }
+                        None => {
+                            unknown.push((keyword, at));
+                            skip_statement(rest, true)?.0
+                        }
+                    };
+                }
+            },
+            lump,
+        )?;
+        let skipped = unknown
+            .into_iter()
+            .map(|(keyword, at)| Skipped {
+                keyword,
+                offset: lump.len() - at.len(),
+            })
+            .collect();
+        Ok(Self {
+            definitions,
+            skipped,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BlendColor, RenderStyle, Skipped, TextureDefinitions, TextureKind, Token, Translation,
+    };
+
+    #[test]
+    fn parse_definitions() {
+        let lump = br#"
+            // Comment
+            WallTexture "BIGDOOR", 128, 96
+            {
+                XScale 2.0
+                Offset 4, -2
+                WorldPanning
+                Patch "DOOR1", 0, 0
+                Patch DOOR2, 64, -8 { FlipX Rotate -90 Translation "0:15=16:31", "32:32=[255,0,0]:[0,0,0]" }
+                Graphic "M_SKULL1", 8, 8
+                {
+                    Blend 255, 128, 0, 0.5
+                    Alpha 0.25 Style Translucent
+                }
+            }
+            /* Block comment */
+            Sprite Optional "TROOA1", 41, 57 { Patch "TROOA1", 0, 0 { Translation Desaturate, 31 Blend "Red" } }
+        "#;
+
+        let tokens = super::tokenize(b"Patch \"A\", -8, 1.5 { }").expect("Error tokenizing");
+        assert_eq!(
+            tokens,
+            [
+                Token::Word("Patch"),
+                Token::Str("A"),
+                Token::Symbol(b','),
+                Token::Int(-8),
+                Token::Symbol(b','),
+                Token::Float(1.5),
+                Token::Symbol(b'{'),
+                Token::Symbol(b'}'),
+            ]
+        );
+
+        let parsed = TextureDefinitions::parse(lump).expect("Error parsing TEXTURES");
+        assert!(parsed.skipped.is_empty());
+        let definitions = &parsed.definitions;
+        assert_eq!(definitions.len(), 2);
+        let door = &definitions[0];
+        assert_eq!(
+            (door.kind, door.name),
+            (TextureKind::WallTexture, "BIGDOOR")
+        );
+        assert_eq!((door.width, door.height, door.x_scale), (128, 96, 2.0));
+        assert_eq!((door.x_offset, door.y_offset), (4, -2));
+        assert!(door.world_panning && !door.optional);
+        assert_eq!(door.patches.len(), 3);
+        let flipped = &door.patches[1];
+        assert_eq!(
+            (flipped.name, flipped.x_offset, flipped.y_offset),
+            ("DOOR2", 64, -8)
+        );
+        assert!(flipped.flip_x && !flipped.flip_y);
+        assert_eq!(flipped.rotate, 270);
+        let map = flipped
+            .translation
+            .as_ref()
+            .and_then(Translation::index_map)
+            .expect("No index translation");
+        assert_eq!((map[0], map[15], map[32], map[100]), (16, 31, 32, 100));
+        let skull = &door.patches[2];
+        let blend = skull.blend.expect("No blend");
+        assert_eq!(blend.color, BlendColor::Rgb([255, 128, 0]));
+        assert_eq!(blend.alpha, Some(0.5));
+        assert_eq!((skull.alpha, skull.style), (0.25, RenderStyle::Translucent));
+
+        let sprite = &definitions[1];
+        assert_eq!((sprite.kind, sprite.optional), (TextureKind::Sprite, true));
+        let patch = &sprite.patches[0];
+        assert_eq!(patch.translation, Some(Translation::Desaturate(31)));
+        assert_eq!(
+            patch.blend.map(|blend| blend.color),
+            Some(BlendColor::Named("Red"))
+        );
+
+        let error = TextureDefinitions::parse(b"Texture \"X\", 8, 8 { Patch \"P\", 0 0 }")
+            .expect_err("Missing comma parsed");
+        assert_eq!(error.kind(), Some(crate::error::ErrorKind::Malformed));
+    }
+
+    #[test]
+    fn skip_unknown_keywords() {
+        let tokens = super::tokenize(b"1_PATCH 1STPATCH 12 -NOFLAT-").expect("Error tokenizing");
+        assert_eq!(
+            tokens,
+            [
+                Token::Word("1_PATCH"),
+                Token::Word("1STPATCH"),
+                Token::Int(12),
+                Token::Word("-NOFLAT-"),
+            ]
+        );
+
+        let lump = br#"#include "textures.txt"
+            Define DEFINED 64 64// Comment
+            Texture "X", 8, 8
+            {
+                Patch 1STPATCH, 0, 0 { Colormap "BLUES" Style Shaded SomeNewFlag FlipY }
+                Patch 1_PATCH, 2, 2
+                Bogus 1, 2, "three" { Nested { } }
+                NoMipmap NoDecals
+            }
+            Flat "Y", 64, 64 { Patch "Y", 0, 0 }"#;
+        let parsed = TextureDefinitions::parse(lump).expect("Error parsing TEXTURES");
+        let names: Vec<_> = parsed.definitions.iter().map(|def| def.name).collect();
+        assert_eq!(names, ["X", "Y"]);
+        let texture = &parsed.definitions[0];
+        assert!(texture.no_decals);
+        let patches: Vec<_> = texture.patches.iter().map(|patch| patch.name).collect();
+        assert_eq!(patches, ["1STPATCH", "1_PATCH"]);
+        assert!(texture.patches[0].flip_y);
+        assert_eq!(texture.patches[0].style, RenderStyle::Copy);
+
+        let keywords: Vec<_> = parsed.skipped.iter().map(|s| s.keyword).collect();
+        assert_eq!(
+            keywords,
+            [
+                "#include",
+                "Define",
+                "Colormap",
+                "Shaded",
+                "SomeNewFlag",
+                "Bogus",
+                "NoMipmap"
+            ]
+        );
+        assert_eq!(
+            parsed.skipped[0],
+            Skipped {
+                keyword: "#include",
+                offset: 0
+            }
+        );
+        let offset = parsed.skipped[5].offset;
+        assert_eq!(&lump[offset..offset + 5], b"Bogus");
+
+        let error = TextureDefinitions::parse(b"Texture \"X\", 8, 8 { Bogus { }")
+            .expect_err("Unclosed block parsed");
+        assert_eq!(error.kind(), Some(crate::error::ErrorKind::Truncated));
+    }
+}
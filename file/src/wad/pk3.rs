@@ -0,0 +1,175 @@
+use std::{
+    convert::TryFrom,
+    fs,
+    io::{self, Read, Seek},
+    path::Path,
+};
+
+use crate::{
+    error::Result,
+    wad::{container::Container, directory::Tree, parser::file::Lump},
+};
+use zip::ZipArchive;
+
+/// Most memory reserved up front for a file, the size in its header may be forged.
+const MAX_RESERVED: usize = 1 << 24;
+/// Largest file read, far more than any resource needs.
+const MAX_FILE_SIZE: u64 = 1 << 30;
+
+/// ZIP-based resource container (`.pk3`), which keeps resources in directories
+/// instead of marker namespaces. Lumps are laid out the same way as in a
+/// [`Directory`](crate::wad::directory::Directory).
+/// 7z-based `.pk7` isn't supported.
+pub struct Pk3 {
+    tree: Tree,
+}
+
+impl Pk3 {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(fs::File::open(path)?)
+    }
+
+    /// Reads and unpacks every resource at once. Files larger than their headers tell
+    /// (or than a sane maximum) are refused, as crafted archives may inflate endlessly.
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self> {
+        let mut zip = ZipArchive::new(reader).map_err(io::Error::from)?;
+        let mut files = Vec::new();
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i).map_err(io::Error::from)?;
+            if file.is_dir() || !Tree::is_needed(file.name()) {
+                continue;
+            }
+            let size = file.size();
+            if size > MAX_FILE_SIZE {
+                return Err(invalid_size(file.name()).into());
+            }
+            let reserved = usize::try_from(size).unwrap_or(usize::MAX);
+            let mut data = Vec::with_capacity(reserved.min(MAX_RESERVED));
+            (&mut file).take(size + 1).read_to_end(&mut data)?;
+            if data.len() as u64 > size {
+                return Err(invalid_size(file.name()).into());
+            }
+            files.push((file.name().to_owned(), data));
+        }
+        Ok(Self {
+            tree: Tree::build(files)?,
+        })
+    }
+
+    /// Path of the file inside container the lump comes from, `None` for generated markers.
+    pub fn path(&self, i: usize) -> Option<&str> {
+        self.tree.path(i)
+    }
+
+    pub fn index_of<S: AsRef<str>>(&self, s: S) -> Option<usize> {
+        self.tree.index_of(s.as_ref())
+    }
+}
+
+fn invalid_size(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("File {} inflates past its size or the limit", name),
+    )
+}
+
+impl Container for Pk3 {
+    fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn get_by_index(&self, i: usize) -> Option<Lump<'_>> {
+        self.tree.get_by_index(i)
+    }
+
+    fn get_by_name(&self, name: &str) -> Option<Lump<'_>> {
+        self.tree
+            .index_of(name)
+            .and_then(|i| self.tree.get_by_index(i))
+    }
+
+    fn get_all_by_name(&self, name: &str) -> Vec<Lump<'_>> {
+        self.tree.get_all_by_name(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::Pk3;
+    use crate::wad::{
+        container::Container,
+        namespace::Namespace,
+        parser::file::{ArchiveBuilder, Type},
+    };
+    use zip::{write::FileOptions, ZipWriter};
+
+    #[test]
+    fn map_directories_to_namespaces() {
+        let map = ArchiveBuilder::new(Type::PWAD)
+            .marker("TESTMAP")
+            .lump("TEXTMAP", b"namespace = \"zdoom\";".to_vec())
+            .marker("ENDMAP")
+            .to_bytes()
+            .expect("Error writing wad");
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, data) in [
+            ("sprites/monsters/TROOA1.lmp", &[1; 16][..]),
+            ("sprites/VILE^1.lmp", &[2; 16][..]),
+            ("Flats/nukage1.lmp", &[3; 4096][..]),
+            ("maps/MAP01.wad", &map[..]),
+            ("PLAYPAL.lmp", &[4; 768][..]),
+            ("acs/script.o", &[5; 4][..]),
+        ] {
+            zip.start_file(path, FileOptions::default())
+                .expect("Error writing zip");
+            zip.write_all(data).expect("Error writing zip");
+        }
+        zip.add_directory("textures", FileOptions::default())
+            .expect("Error writing zip");
+        let zip = zip.finish().expect("Error writing zip").into_inner();
+
+        let pk3 = Pk3::from_reader(Cursor::new(zip)).expect("Error reading pk3");
+        assert_eq!(
+            pk3.iter().map(|lump| lump.name).collect::<Vec<_>>(),
+            [
+                "PLAYPAL", "S_START", "VILE\\1", "TROOA1", "S_END", "F_START", "NUKAGE1", "F_END",
+                "MAP01", "TEXTMAP", "ENDMAP"
+            ]
+        );
+        assert_eq!(pk3.path(3), Some("sprites/monsters/TROOA1.lmp"));
+        assert_eq!(pk3.path(1), None);
+
+        let flats = pk3.namespace(Namespace::Flats);
+        assert_eq!(
+            flats.get_by_name("NUKAGE1").map(|lump| lump.data.len()),
+            Some(4096)
+        );
+        assert_eq!(pk3.namespace(Namespace::Sprites).len(), 2);
+        assert_eq!(
+            pk3.get_by_name("TEXTMAP").map(|lump| lump.data),
+            Some(&b"namespace = \"zdoom\";"[..])
+        );
+        assert!(pk3.get_by_name("SCRIPT").is_none());
+    }
+
+    #[test]
+    fn refuse_inflating_past_size() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("PLAYPAL.lmp", FileOptions::default())
+            .expect("Error writing zip");
+        zip.write_all(&[4; 4096]).expect("Error writing zip");
+        let mut zip = zip.finish().expect("Error writing zip").into_inner();
+        assert!(Pk3::from_reader(Cursor::new(&zip)).is_ok());
+
+        // Central directory tells the file is 16 bytes long
+        let central = zip
+            .windows(4)
+            .position(|magic| magic == b"PK\x01\x02")
+            .expect("No central directory");
+        zip[central + 24..central + 28].copy_from_slice(&16u32.to_le_bytes());
+        assert!(Pk3::from_reader(Cursor::new(&zip)).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use crate::wad::{container::Container, namespace::Namespace, parser::file::Lump};
+
+/// Lump resolved from a [`ResourceSet`] along with the index of file it came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resource<'a> {
+    pub lump: Lump<'a>,
+    pub file: usize,
+}
+
+struct ResourceFile<'a> {
+    name: String,
+    container: Box<dyn Container + 'a>,
+    /// Last index of every lump outside of namespaces
+    global: HashMap<String, usize>,
+    /// Indices of namespace resources in directory order
+    namespaces: HashMap<Namespace, Vec<usize>>,
+}
+
+impl<'a> ResourceFile<'a> {
+    fn new(name: String, container: Box<dyn Container + 'a>) -> Self {
+        let lumps: Vec<_> = (0..container.len())
+            .filter_map(|i| container.get_by_index(i))
+            .collect();
+        let mut global = HashMap::new();
+        let mut namespaces: HashMap<_, Vec<_>> = HashMap::new();
+        Namespace::classify(lumps.iter().copied())
+            .into_iter()
+            .zip(&lumps)
+            .enumerate()
+            .for_each(|(i, (namespace, lump))| match namespace {
+                None => {
+                    global.insert(lump.name.to_owned(), i);
+                }
+                Some(namespace)
+                    if !namespace.is_start(lump.name)
+                        && !namespace.is_end(lump.name)
+                        && namespace.accepts(lump) =>
+                {
+                    namespaces.entry(namespace).or_default().push(i);
+                }
+                Some(_) => {}
+            });
+        Self {
+            name,
+            container,
+            global,
+            namespaces,
+        }
+    }
+}
+
+/// Stack of an IWAD and PWADs loaded on top of it, each kept intact.
+/// Later files override earlier ones like `-file` does, but unlike
+/// [`merge`](crate::wad::utils::merge) markers, namespaces and levels stay in place.
+#[derive(Default)]
+pub struct ResourceSet<'a> {
+    files: Vec<ResourceFile<'a>>,
+}
+
+impl<'a> ResourceSet<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts the file on top of the stack, so IWAD goes first and PWADs follow in load order.
+    /// Returns index of the file used in [`Resource::file`].
+    pub fn add<S, C>(&mut self, name: S, container: C) -> usize
+    where
+        S: Into<String>,
+        C: Container + 'a,
+    {
+        self.files
+            .push(ResourceFile::new(name.into(), Box::new(container)));
+        self.files.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn file_name(&self, file: usize) -> Option<&str> {
+        self.files.get(file).map(|file| file.name.as_str())
+    }
+
+    pub fn file(&self, file: usize) -> Option<&(dyn Container + 'a)> {
+        self.files.get(file).map(|file| file.container.as_ref())
+    }
+
+    /// Finds lump outside of namespaces, e.g. `PLAYPAL` or a level marker.
+    pub fn get_by_name<S: AsRef<str>>(&self, s: S) -> Option<Resource<'_>> {
+        let name = s.as_ref();
+        self.files
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(file, resource_file)| {
+                let &index = resource_file.global.get(name)?;
+                let lump = resource_file.container.get_by_index(index)?;
+                Some(Resource { lump, file })
+            })
+    }
+
+    /// Finds resource of the namespace, lumps of later files replacing earlier ones.
+    pub fn get_in_namespace<S: AsRef<str>>(
+        &self,
+        namespace: Namespace,
+        s: S,
+    ) -> Option<Resource<'_>> {
+        let name = s.as_ref();
+        self.namespace(namespace)
+            .into_iter()
+            .rev()
+            .find(|resource| resource.lump.name == name)
+    }
+
+    /// Namespace coalesced from every file in load order as Boom does,
+    /// so duplicates are kept and the last one of them is the effective.
+    pub fn namespace(&self, namespace: Namespace) -> Vec<Resource<'_>> {
+        self.files
+            .iter()
+            .enumerate()
+            .flat_map(|(file, resource_file)| {
+                resource_file
+                    .namespaces
+                    .get(&namespace)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(move |&index| resource_file.container.get_by_index(index))
+                    .map(move |lump| Resource { lump, file })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResourceSet;
+    use crate::wad::{
+        namespace::Namespace,
+        parser::file::{Archive, ArchiveBuilder, Type},
+    };
+
+    #[test]
+    fn pwad_overrides_iwad() {
+        let iwad = ArchiveBuilder::new(Type::IWAD)
+            .lump("PLAYPAL", b"IWAD PLAYPAL".to_vec())
+            .lump("STARTAN3", b"GLOBAL".to_vec())
+            .marker("F_START")
+            .lump("NUKAGE1", b"IWAD NUKAGE1".to_vec())
+            .lump("NUKAGE2", b"IWAD NUKAGE2".to_vec())
+            .marker("F_END")
+            .marker("S_START")
+            .lump("TROOA1", b"IWAD TROOA1".to_vec())
+            .marker("S_END")
+            .to_bytes()
+            .expect("Error writing wad");
+        let pwad = ArchiveBuilder::new(Type::PWAD)
+            .marker("FF_START")
+            .lump("NUKAGE2", b"PWAD NUKAGE2".to_vec())
+            .lump("STARTAN3", b"PWAD FLAT".to_vec())
+            .marker("FF_END")
+            .lump("PLAYPAL", b"PWAD PLAYPAL".to_vec())
+            .to_bytes()
+            .expect("Error writing wad");
+
+        let mut resources = ResourceSet::new();
+        resources.add(
+            "DOOM.WAD",
+            Archive::parse(&iwad).expect("Wad file parser error"),
+        );
+        let pwad_id = resources.add(
+            "MOD.WAD",
+            Archive::parse(&pwad).expect("Wad file parser error"),
+        );
+
+        let playpal = resources.get_by_name("PLAYPAL").expect("PLAYPAL not found");
+        assert_eq!(playpal.lump.data, b"PWAD PLAYPAL");
+        assert_eq!(resources.file_name(playpal.file), Some("MOD.WAD"));
+
+        let startan = resources
+            .get_by_name("STARTAN3")
+            .expect("STARTAN3 not found");
+        assert_eq!(startan.lump.data, b"GLOBAL");
+        assert!(resources.get_by_name("NUKAGE1").is_none());
+
+        let flats: Vec<_> = resources
+            .namespace(Namespace::Flats)
+            .iter()
+            .map(|flat| (flat.lump.name, flat.file))
+            .collect();
+        assert_eq!(
+            flats,
+            [
+                ("NUKAGE1", 0),
+                ("NUKAGE2", 0),
+                ("NUKAGE2", pwad_id),
+                ("STARTAN3", pwad_id)
+            ]
+        );
+        let nukage = resources
+            .get_in_namespace(Namespace::Flats, "NUKAGE2")
+            .expect("NUKAGE2 not found");
+        assert_eq!(nukage.lump.data, b"PWAD NUKAGE2");
+        assert_eq!(nukage.file, pwad_id);
+
+        let sprite = resources
+            .get_in_namespace(Namespace::Sprites, "TROOA1")
+            .expect("TROOA1 not found");
+        assert_eq!(resources.file_name(sprite.file), Some("DOOM.WAD"));
+    }
+}
@@ -10,6 +10,8 @@ pub fn is_level_name(name: &str) -> bool {
     }
 }
 
+/// Appends lumps of PWADs to the IWAD, so they win the lookup by name.
+/// Namespaces and levels aren't regrouped, see [`ResourceSet`](crate::wad::resource::ResourceSet) for that.
 pub fn merge<'a, I>(iwad: &mut Archive<'a>, pwads: I)
 where
     I: IntoIterator<Item = Archive<'a>>,
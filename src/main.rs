@@ -1,3 +1,20 @@
+use std::{env, process};
+
+use file::wad::{parser::file::Archive, resource::ResourceSet};
+
 fn main() {
-    println!("Hello, world!");
+    // IWAD goes first, PWADs follow in load order; files stay mapped while the game runs
+    let mut resources = ResourceSet::new();
+    for path in env::args().skip(1) {
+        match Archive::open(&path) {
+            Ok(archive) => {
+                resources.add(path, archive);
+            }
+            Err(e) => {
+                eprintln!("Error loading {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    println!("Loaded {} resource files", resources.len());
 }